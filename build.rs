@@ -0,0 +1,29 @@
+// With the `c-header-test` feature, compiles tests/c/membuf_test.c against
+// include/membuf.h into a static library, which the tests of the `ffi`
+// module link and run. Nothing is built otherwise, so ordinary builds need
+// neither a C compiler nor `ar`.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    if env::var("CARGO_FEATURE_C_HEADER_TEST").is_err() { return }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let object = Path::new(&out_dir).join("membuf_test.o");
+    let archive = Path::new(&out_dir).join("libmembuf_test.a");
+    let cc = env::var("CC").unwrap_or("cc".to_string());
+
+    run(Command::new(cc)
+            .args(&["-c", "-fPIC", "-Wall", "-Werror", "-Iinclude", "tests/c/membuf_test.c", "-o"])
+            .arg(&object));
+    run(Command::new("ar").arg("crs").arg(&archive).arg(&object));
+
+    println!("cargo:rustc-link-search=native={}", out_dir);
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    if !status.success() { panic!("{:?} failed with {}", command, status) }
+}
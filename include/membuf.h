@@ -0,0 +1,43 @@
+/*
+ * C interface to membuf byte buffers. See the documentation of the
+ * `membuf::ffi` module for the ownership rules.
+ */
+
+#ifndef MEMBUF_H
+#define MEMBUF_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A buffer of bytes: a pointer, a capacity in bytes and the id of the
+ * backend it was allocated from, which must be left untouched. A failed
+ * allocation is represented by a NULL pointer. */
+typedef struct RawMemBuf {
+    unsigned char *ptr;
+    size_t capacity;
+    size_t backend;
+} RawMemBuf;
+
+/* Allocate a buffer of `capacity` bytes. */
+RawMemBuf membuf_alloc(size_t capacity);
+
+/* Reallocate `buf` to `capacity` bytes in place. Returns 0 on success, -1 if
+ * the allocation fails or -2 if `buf` or its pointer is NULL or its backend
+ * is unknown, leaving `buf` untouched. */
+int membuf_realloc(RawMemBuf *buf, size_t capacity);
+
+/* Free `buf`. Buffers with a NULL pointer or an unknown backend are
+ * ignored. */
+void membuf_free(RawMemBuf buf);
+
+/* Get the capacity of `buf` in bytes, or 0 if `buf` is NULL. */
+size_t membuf_capacity(const RawMemBuf *buf);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
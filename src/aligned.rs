@@ -0,0 +1,309 @@
+use std::ops::{Deref, DerefMut};
+use std::{mem, ptr, slice};
+
+use {registry, UniqueBuf};
+use backend::{BackendHandle, DEFAULT_BACKEND};
+
+// The alignment of an `AlignedByteBuf`, enough for any primitive type and for
+// the archives of zero-copy deserialization libraries such as rkyv.
+const ALIGN: usize = 16;
+
+// The alignment and padding Apache Arrow requires of its buffers.
+const ARROW_ALIGN: usize = 64;
+
+/// A length-tracked byte buffer whose storage is aligned to 16 bytes, and
+/// whose capacity is a multiple of 16 bytes.
+///
+/// Heap buffers of u8 are only guaranteed to be byte aligned, so they cannot
+/// back archives which are read in place as larger types. An
+/// `AlignedByteBuf` over-allocates and starts at the first aligned byte.
+///
+/// Buffers from `allocate_arrow` are instead aligned and padded to 64 bytes,
+/// as Apache Arrow requires.
+///
+/// ```
+/// # use membuf::AlignedByteBuf;
+///
+/// let buffer = AlignedByteBuf::from(&[1u8, 2, 3][..]);
+/// assert_eq!(buffer.as_ptr() as usize % 16, 0);
+/// assert_eq!(buffer.capacity(), 16);
+/// assert_eq!(&*buffer, &[1, 2, 3]);
+/// ```
+#[derive(Debug)]
+pub struct AlignedByteBuf {
+    buffer: UniqueBuf<u8>,
+    ptr: *mut u8,
+    align: usize,
+    cap: usize,
+    len: usize
+}
+
+unsafe impl Send for AlignedByteBuf {}
+unsafe impl Sync for AlignedByteBuf {}
+
+impl AlignedByteBuf {
+    /// Create a new, empty buffer which does not allocate.
+    pub fn new() -> AlignedByteBuf {
+        AlignedByteBuf::allocate(0)
+    }
+
+    /// Create a new buffer with space for at least `cap` bytes, rounded up to
+    /// a multiple of 16.
+    pub fn allocate(cap: usize) -> AlignedByteBuf {
+        AlignedByteBuf::allocate_aligned(cap, ALIGN, UniqueBuf::allocate)
+    }
+
+    // Allocate space for `cap` bytes rounded up to a multiple of `align`,
+    // taking the underlying, byte aligned, memory from `allocate`.
+    fn allocate_aligned<F>(cap: usize, align: usize, allocate: F) -> AlignedByteBuf
+    where F: FnOnce(usize) -> UniqueBuf<u8> {
+        let cap = cap.checked_add(align - 1).expect("Capacity overflow.") & !(align - 1);
+        if cap == 0 {
+            // Like empty slices, empty buffers point at a dangling but
+            // aligned address.
+            return AlignedByteBuf {
+                buffer: UniqueBuf::new(),
+                ptr: align as *mut u8,
+                align: align,
+                cap: 0,
+                len: 0
+            }
+        }
+
+        // The allocation is only byte aligned, so leave room to skip ahead to
+        // the first aligned byte.
+        let padded = cap.checked_add(align - 1).expect("Capacity overflow.");
+        let buffer = allocate(padded);
+        let offset = (align - *buffer as usize % align) % align;
+        let ptr = unsafe { buffer.offset(offset as isize) };
+
+        AlignedByteBuf { buffer: buffer, ptr: ptr, align: align, cap: cap, len: 0 }
+    }
+
+    /// Create a new buffer in the layout of an Apache Arrow buffer: aligned
+    /// to 64 bytes, with space for at least `cap` bytes rounded up to a
+    /// multiple of 64.
+    ///
+    /// The whole capacity is zeroed, so the buffer can be handed to Arrow
+    /// with its padded length, without exposing uninitialized padding.
+    ///
+    /// ```
+    /// # use membuf::AlignedByteBuf;
+    ///
+    /// let mut column = AlignedByteBuf::allocate_arrow(100);
+    /// assert_eq!(column.as_ptr() as usize % 64, 0);
+    /// assert_eq!(column.capacity(), 128);
+    ///
+    /// unsafe { column.set_len(10); }
+    /// assert_eq!(column.padded_len(), 64);
+    /// ```
+    pub fn allocate_arrow(cap: usize) -> AlignedByteBuf {
+        let mut buffer = AlignedByteBuf::allocate_aligned(cap, ARROW_ALIGN, UniqueBuf::allocate);
+        buffer.fill_capacity(0);
+        buffer
+    }
+
+    // Set every byte of the capacity to `byte`.
+    fn fill_capacity(&mut self, byte: u8) {
+        unsafe {
+            ptr::write_bytes(self.ptr, byte, self.cap);
+            let offset = self.offset();
+            registry::mark_initialized(*self.buffer, offset, offset + self.cap);
+        }
+    }
+
+    /// Get the alignment of the buffer, in bytes.
+    pub fn alignment(&self) -> usize {
+        self.align
+    }
+
+    /// Get the length rounded up to the alignment, which never exceeds the
+    /// capacity.
+    pub fn padded_len(&self) -> usize {
+        (self.len + self.align - 1) & !(self.align - 1)
+    }
+
+    // The offset of the first aligned byte into the allocation.
+    fn offset(&self) -> usize {
+        self.ptr as usize - *self.buffer as usize
+    }
+
+    /// Get the number of initialized bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the number of bytes the buffer has space for.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Set the number of initialized bytes.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` bytes must be initialized.
+    pub unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.capacity());
+        self.len = len;
+    }
+
+    /// Get a pointer to the first, aligned, byte.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    /// Get a mutable pointer to the first, aligned, byte.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr
+    }
+}
+
+/// A buffer of Ts aligned to more than `align_of::<T>()`.
+///
+/// Heap buffers are only ever aligned for their element type, so
+/// `MemBufBuilder` builds these when asked for a larger alignment. Like
+/// `AlignedByteBuf`, they over-allocate and start at the first aligned byte.
+/// The destructor frees the memory without dropping any elements.
+///
+/// ```
+/// # use membuf::AlignedBuf;
+///
+/// let buffer: AlignedBuf<u32> = AlignedBuf::allocate(10, 256);
+/// assert_eq!(*buffer as usize % 256, 0);
+/// assert_eq!(buffer.capacity(), 10);
+/// ```
+#[derive(Debug)]
+pub struct AlignedBuf<T> {
+    bytes: AlignedByteBuf,
+    ptr: *mut T,
+    cap: usize
+}
+
+impl<T> AlignedBuf<T> {
+    /// Create a new buffer with space for cap Ts, aligned to `align` bytes.
+    ///
+    /// Panics if `align` is not a power of two or is less than the alignment
+    /// of T.
+    pub fn allocate(cap: usize, align: usize) -> AlignedBuf<T> {
+        AlignedBuf::allocate_in(cap, align, DEFAULT_BACKEND)
+    }
+
+    /// Create a new buffer with space for cap Ts, aligned to `align` bytes
+    /// and allocated from `backend`.
+    pub fn allocate_in(cap: usize, align: usize, backend: BackendHandle) -> AlignedBuf<T> {
+        AlignedBuf::allocate_with(cap, align, |size| UniqueBuf::allocate_in(size, backend))
+    }
+
+    /// Create a new buffer with space for cap Ts, aligned to `align` bytes
+    /// and allocated from `backend`, whose memory is counted towards `tag`.
+    pub fn allocate_tagged_in(cap: usize, align: usize, backend: BackendHandle,
+                              tag: &'static str) -> AlignedBuf<T> {
+        AlignedBuf::allocate_with(cap, align, |size| {
+            UniqueBuf::allocate_tagged_in(size, backend, tag)
+        })
+    }
+
+    fn allocate_with<F>(cap: usize, align: usize, allocate: F) -> AlignedBuf<T>
+    where F: FnOnce(usize) -> UniqueBuf<u8> {
+        if !align.is_power_of_two() || align < mem::align_of::<T>() {
+            panic!("Alignment {} is not a power of two of at least {}", align, mem::align_of::<T>())
+        }
+
+        let size = cap.checked_mul(mem::size_of::<T>()).expect("Capacity overflow.");
+        let bytes = AlignedByteBuf::allocate_aligned(size, align, allocate);
+        AlignedBuf { ptr: bytes.ptr as *mut T, bytes: bytes, cap: cap }
+    }
+
+    /// Get the number of Ts the buffer has space for.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Get the alignment of the buffer, in bytes.
+    pub fn alignment(&self) -> usize {
+        self.bytes.align
+    }
+
+    /// Set every byte of the buffer to `byte`.
+    pub fn fill_bytes(&mut self, byte: u8) {
+        self.bytes.fill_capacity(byte)
+    }
+}
+
+impl<T> Deref for AlignedBuf<T> {
+    type Target = *mut T;
+
+    fn deref(&self) -> &*mut T {
+        &self.ptr
+    }
+}
+
+impl<'a> From<&'a [u8]> for AlignedByteBuf {
+    fn from(bytes: &'a [u8]) -> AlignedByteBuf {
+        let mut buffer = AlignedByteBuf::allocate(bytes.len());
+
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.as_mut_ptr(), bytes.len());
+            let offset = buffer.offset();
+            registry::mark_initialized(*buffer.buffer, offset, offset + bytes.len());
+            buffer.set_len(bytes.len());
+        }
+        buffer
+    }
+}
+
+impl Deref for AlignedByteBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedByteBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AlignedBuf, AlignedByteBuf};
+
+    #[test]
+    fn test_alignment_and_rounding() {
+        for len in 0..40 {
+            let bytes: Vec<u8> = (0..len).collect();
+            let buffer = AlignedByteBuf::from(&bytes[..]);
+
+            assert_eq!(buffer.as_ptr() as usize % 16, 0);
+            assert_eq!(buffer.capacity(), (len as usize + 15) / 16 * 16);
+            assert_eq!(&*buffer, &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn test_arrow_padding() {
+        let mut buffer = AlignedByteBuf::allocate_arrow(65);
+        assert_eq!((buffer.alignment(), buffer.capacity()), (64, 128));
+        assert_eq!(buffer.padded_len(), 0);
+
+        unsafe {
+            buffer.set_len(65);
+            assert_eq!(buffer.padded_len(), 128);
+            assert!(buffer.iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn test_over_aligned_elements() {
+        let mut buffer: AlignedBuf<u16> = AlignedBuf::allocate(3, 128);
+        buffer.fill_bytes(1);
+
+        assert_eq!(*buffer as usize % 128, 0);
+        assert_eq!(buffer.alignment(), 128);
+        assert_eq!(unsafe { *buffer.offset(2) }, 0x0101);
+    }
+}
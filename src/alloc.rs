@@ -7,19 +7,132 @@ extern crate alloc;
 
 use core::nonzero::NonZero;
 use std::rt::heap;
-use std::mem;
+use std::error::Error;
+use std::{cmp, fmt, isize, mem, ptr, usize};
+
+use backend::{self, BackendHandle, DEFAULT_BACKEND};
+use shadow::Shadow;
+use {asan, budget, canary, oom, poison, profile, registry, reserve, stats, valgrind};
+
+#[cfg(feature = "quarantine")]
+use quarantine;
+#[cfg(all(feature = "recycler", not(feature = "quarantine")))]
+use recycler;
+
+/// The reason an allocation could not be made.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum AllocError {
+    /// The requested capacity in bytes is larger than `isize::MAX`.
+    CapacityOverflow,
+    /// The backend could not provide the memory.
+    OutOfMemory
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for AllocError {
+    fn description(&self) -> &str {
+        match *self {
+            AllocError::CapacityOverflow => "Capacity overflow",
+            AllocError::OutOfMemory => "Out of memory"
+        }
+    }
+}
+
+impl From<CapacityOverflow> for AllocError {
+    fn from(_: CapacityOverflow) -> AllocError {
+        AllocError::CapacityOverflow
+    }
+}
+
+/// The requested capacity of a buffer in bytes is larger than `isize::MAX`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct CapacityOverflow;
+
+impl fmt::Display for CapacityOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for CapacityOverflow {
+    fn description(&self) -> &str { "Capacity overflow" }
+}
+
+/// The size and alignment of a request to the backend.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Layout {
+    /// The size of the request in bytes, including any padding added by the
+    /// debugging features.
+    pub size: usize,
+    /// The alignment of the request.
+    pub align: usize
+}
 
 /// Allocate a new pointer to the heap with space for `cap` `T`s.
 pub unsafe fn allocate<T>(cap: NonZero<usize>) -> NonZero<*mut T> {
-    if mem::size_of::<T>() == 0 { return empty() }
+    expect_capacity(checked_allocate(cap))
+}
+
+/// Allocate a new pointer to the heap with space for `cap` `T`s, returning
+/// an error instead of panicking on capacity overflow.
+///
+/// Running out of memory still aborts.
+pub unsafe fn checked_allocate<T>(cap: NonZero<usize>) -> Result<NonZero<*mut T>, CapacityOverflow> {
+    abort_on_oom(try_allocate(cap))
+}
+
+/// Allocate a new pointer to the heap with space for `cap` `T`s, returning
+/// an error instead of panicking or aborting if the allocation fails.
+pub unsafe fn try_allocate<T>(cap: NonZero<usize>) -> Result<NonZero<*mut T>, AllocError> {
+    try_allocate_in(DEFAULT_BACKEND, cap)
+}
+
+/// Allocate a new pointer with space for `cap` `T`s from `backend`.
+///
+/// The allocation must only be reallocated and deallocated with the `_in`
+/// functions, passing the same backend.
+pub unsafe fn allocate_in<T>(backend: BackendHandle, cap: NonZero<usize>) -> NonZero<*mut T> {
+    expect_capacity(abort_on_oom(try_allocate_in(backend, cap)))
+}
+
+/// Allocate a new pointer with space for `cap` `T`s from `backend`,
+/// returning an error instead of panicking or aborting if the allocation
+/// fails.
+pub unsafe fn try_allocate_in<T>(backend: BackendHandle,
+                                 cap: NonZero<usize>) -> Result<NonZero<*mut T>, AllocError> {
+    if mem::size_of::<T>() == 0 { return Ok(empty()) }
+
+    let size = try!(allocation_size::<T>(cap));
+    try!(budget::admit(size));
+    let ptr = try!(raw_typed_allocate::<T>(backend, size));
+
+    registry::insert(*ptr as *mut u8, size, Shadow::new(size));
+    budget::charge(size);
+    stats::allocated(*ptr as *mut u8, size);
+    profile::allocated(*ptr as *mut u8, size);
+
+    Ok(ptr)
+}
+
+unsafe fn raw_typed_allocate<T>(backend: BackendHandle,
+                                size: usize) -> Result<NonZero<*mut T>, AllocError> {
+    let align = mem::align_of::<T>();
 
     // Allocate
-    let ptr = heap::allocate(allocation_size::<T>(cap), mem::align_of::<T>());
+    let base = raw_allocate(backend, canary::padded_size(size, align), align);
 
     // Check for allocation failure
-    if ptr.is_null() { alloc::oom() }
+    if base.is_null() { return Err(AllocError::OutOfMemory) }
 
-    NonZero::new(ptr as *mut T)
+    let ptr = canary::install(base, size, align);
+    valgrind::malloclike(ptr, size);
+
+    Ok(NonZero::new(ptr as *mut T))
 }
 
 /// Reallocate an allocation allocated with `allocate` or a previous call to
@@ -27,20 +140,123 @@ pub unsafe fn allocate<T>(cap: NonZero<usize>) -> NonZero<*mut T> {
 pub unsafe fn reallocate<T>(ptr: NonZero<*mut T>,
                             old_cap: NonZero<usize>,
                             new_cap: NonZero<usize>) -> NonZero<*mut T> {
-    if mem::size_of::<T>() == 0 { return empty() }
+    expect_capacity(checked_reallocate(ptr, old_cap, new_cap))
+}
+
+/// Reallocate an allocation allocated with `allocate` or a previous call to
+/// `reallocate` to be a larger or smaller size, returning an error instead
+/// of panicking on capacity overflow.
+///
+/// Running out of memory still aborts. On capacity overflow the original
+/// allocation is left untouched.
+pub unsafe fn checked_reallocate<T>(ptr: NonZero<*mut T>,
+                                    old_cap: NonZero<usize>,
+                                    new_cap: NonZero<usize>) -> Result<NonZero<*mut T>, CapacityOverflow> {
+    abort_on_oom(try_reallocate(ptr, old_cap, new_cap))
+}
+
+/// Reallocate an allocation allocated with `allocate` or a previous call to
+/// `reallocate` to be a larger or smaller size, returning an error instead
+/// of panicking or aborting if the allocation fails.
+///
+/// On failure the original allocation is left untouched.
+pub unsafe fn try_reallocate<T>(ptr: NonZero<*mut T>,
+                                old_cap: NonZero<usize>,
+                                new_cap: NonZero<usize>) -> Result<NonZero<*mut T>, AllocError> {
+    try_reallocate_in(DEFAULT_BACKEND, ptr, old_cap, new_cap)
+}
+
+/// Reallocate an allocation allocated from `backend` with `allocate_in` or
+/// a previous call to `reallocate_in` to be a larger or smaller size.
+pub unsafe fn reallocate_in<T>(backend: BackendHandle,
+                               ptr: NonZero<*mut T>,
+                               old_cap: NonZero<usize>,
+                               new_cap: NonZero<usize>) -> NonZero<*mut T> {
+    expect_capacity(abort_on_oom(try_reallocate_in(backend, ptr, old_cap, new_cap)))
+}
+
+/// Reallocate an allocation allocated from `backend` with `allocate_in` or
+/// a previous call to `reallocate_in` to be a larger or smaller size,
+/// returning an error instead of panicking or aborting if the allocation
+/// fails.
+///
+/// On failure the original allocation is left untouched.
+pub unsafe fn try_reallocate_in<T>(backend: BackendHandle,
+                                   ptr: NonZero<*mut T>,
+                                   old_cap: NonZero<usize>,
+                                   new_cap: NonZero<usize>) -> Result<NonZero<*mut T>, AllocError> {
+    if mem::size_of::<T>() == 0 { return Ok(empty()) }
 
     let old_size = unchecked_allocation_size::<T>(old_cap);
-    let new_size = allocation_size::<T>(new_cap);
+    let new_size = try!(allocation_size::<T>(new_cap));
+    try!(budget::admit(new_size.saturating_sub(old_size)));
+
+    let shadow = registry::remove(*ptr as *mut u8, "reallocate");
+
+    // Valgrind needs to see the contents being copied between blocks, to
+    // carry over which bytes are defined.
+    let result = if valgrind::enabled() {
+        move_reallocate(backend, ptr, old_cap, new_cap)
+    } else {
+        raw_typed_reallocate(backend, ptr, old_cap, new_size)
+    };
+
+    match result {
+        Ok(new) => {
+            registry::insert(*new as *mut u8, new_size, shadow.resize(new_size));
+            budget::charge(new_size.saturating_sub(old_size));
+            budget::refund(old_size.saturating_sub(new_size));
+            stats::reallocated(*ptr as *mut u8, *new as *mut u8, old_size, new_size);
+            profile::reallocated(*ptr as *mut u8, *new as *mut u8, old_size, new_size);
+            Ok(new)
+        },
+        Err(err) => {
+            registry::insert(*ptr as *mut u8, old_size, shadow);
+            Err(err)
+        }
+    }
+}
+
+unsafe fn raw_typed_reallocate<T>(backend: BackendHandle,
+                                  ptr: NonZero<*mut T>,
+                                  old_cap: NonZero<usize>,
+                                  new_size: usize) -> Result<NonZero<*mut T>, AllocError> {
+    let old_size = unchecked_allocation_size::<T>(old_cap);
+    let align = mem::align_of::<T>();
+
+    canary::verify(*ptr as *mut u8, old_size);
+    asan::unpoison(*ptr as *mut u8, old_size);
 
     // Reallocate
-    let new = heap::reallocate(*ptr as *mut u8, old_size, new_size, mem::align_of::<T>());
+    let new = raw_reallocate(backend,
+                             canary::base(*ptr as *mut u8, align),
+                             canary::padded_size(old_size, align),
+                             canary::padded_size(new_size, align),
+                             align);
 
     // Check for allocation failure
     if new.is_null() {
-        alloc::oom()
+        return Err(AllocError::OutOfMemory)
     }
 
-    NonZero::new(new as *mut T)
+    Ok(NonZero::new(canary::install(new, new_size, align) as *mut T))
+}
+
+/// Abort on running out of memory, as the checked functions do.
+fn abort_on_oom<T>(result: Result<T, AllocError>) -> Result<T, CapacityOverflow> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(AllocError::CapacityOverflow) => Err(CapacityOverflow),
+        Err(AllocError::OutOfMemory) => oom()
+    }
+}
+
+/// Panic on capacity overflow, as the infallible functions do.
+fn expect_capacity<T>(result: Result<T, CapacityOverflow>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(CapacityOverflow) => panic!("Capacity overflow.")
+    }
 }
 
 /// A zero-sized allocation, appropriate for use with zero sized types.
@@ -48,17 +264,218 @@ pub fn empty<T>() -> NonZero<*mut T> {
     unsafe { NonZero::new(heap::EMPTY as *mut T) }
 }
 
+/// Abort the process after failing to allocate memory.
+pub fn oom() -> ! {
+    alloc::oom()
+}
+
 /// Deallocate an allocation allocated with `allocate` or `reallocate`.
 pub unsafe fn deallocate<T>(ptr: NonZero<*mut T>, cap: NonZero<usize>) {
+    deallocate_in(DEFAULT_BACKEND, ptr, cap)
+}
+
+/// Deallocate an allocation allocated from `backend` with `allocate_in` or
+/// `reallocate_in`.
+pub unsafe fn deallocate_in<T>(backend: BackendHandle, ptr: NonZero<*mut T>, cap: NonZero<usize>) {
     if mem::size_of::<T>() == 0 { return }
 
+    registry::remove(*ptr as *mut u8, "deallocate");
+    let size = unchecked_allocation_size::<T>(cap);
+    budget::refund(size);
+    stats::deallocated(*ptr as *mut u8, size);
+    profile::deallocated(*ptr as *mut u8, size);
+    release(backend, ptr, cap)
+}
+
+/// Do allocations made through this module come straight from the standard
+/// heap, so that they can be exchanged with `Vec` and `Box`?
+///
+/// This is not the case if the backend has been replaced with `set_backend`,
+/// or with the `canaries` feature, which pads every allocation.
+pub fn is_std_heap() -> bool {
+    !canary::enabled() && backend::is_default()
+}
+
+/// Take over an allocation of `cap` `T`s made directly with the allocator
+/// of the current backend, such as the buffer of a `Vec` when the backend is
+/// the standard heap, so it can later be reallocated or deallocated through
+/// this module.
+///
+/// The memory is counted as allocated, but is never refused by a budget.
+/// Must not be called with the `canaries` feature.
+pub unsafe fn adopt<T>(ptr: NonZero<*mut T>, cap: NonZero<usize>) {
+    if mem::size_of::<T>() == 0 { return }
+    debug_assert!(!canary::enabled());
+
+    let size = unchecked_allocation_size::<T>(cap);
+    valgrind::malloclike(*ptr as *mut u8, size);
+    registry::insert(*ptr as *mut u8, size, Shadow::new(size));
+    budget::charge(size);
+    stats::allocated(*ptr as *mut u8, size);
+    profile::allocated(*ptr as *mut u8, size);
+}
+
+/// Give up an allocation made with `allocate`, `reallocate` or `adopt`
+/// without releasing it, so that it can be owned by a `Vec` or `Box`, or by
+/// foreign code sharing the backend.
+///
+/// Must not be called with the `canaries` feature.
+pub unsafe fn disown<T>(ptr: NonZero<*mut T>, cap: NonZero<usize>) {
+    if mem::size_of::<T>() == 0 { return }
+    debug_assert!(!canary::enabled());
+
+    registry::remove(*ptr as *mut u8, "disown");
+    let size = unchecked_allocation_size::<T>(cap);
+    budget::refund(size);
+    stats::deallocated(*ptr as *mut u8, size);
+    profile::deallocated(*ptr as *mut u8, size);
+    asan::unpoison(*ptr as *mut u8, size);
+    valgrind::freelike(*ptr as *mut u8);
+}
+
+/// Release an allocation which has already been removed from the registry.
+unsafe fn release<T>(backend: BackendHandle, ptr: NonZero<*mut T>, cap: NonZero<usize>) {
     let old_size = unchecked_allocation_size::<T>(cap);
+    let align = mem::align_of::<T>();
+
+    let base = canary::base(*ptr as *mut u8, align);
+    let padded_size = canary::padded_size(old_size, align);
+
+    canary::verify(*ptr as *mut u8, old_size);
+    asan::unpoison(*ptr as *mut u8, old_size);
+
+    // Poison while Valgrind still considers the block allocated, or every
+    // byte written would be reported as an invalid write.
+    poison::fill(base, padded_size);
+    valgrind::freelike(*ptr as *mut u8);
+
+    free(backend, base, padded_size, align)
+}
+
+/// Mark the elements of an allocation from `len` onwards as inaccessible to
+/// AddressSanitizer and undefined to Valgrind, and the elements before `len`
+/// as accessible.
+///
+/// This is a no-op unless the `asan` or `valgrind` feature is enabled.
+pub unsafe fn set_unpoisoned_len<T>(ptr: NonZero<*mut T>, cap: NonZero<usize>, len: usize) {
+    if mem::size_of::<T>() == 0 { return }
+
+    let size = unchecked_allocation_size::<T>(cap);
+    let unpoisoned = mem::size_of::<T>() * cmp::min(len, *cap);
+
+    asan::unpoison(*ptr as *mut u8, unpoisoned);
+    asan::poison((*ptr as *mut u8).offset(unpoisoned as isize), size - unpoisoned);
+    valgrind::make_undefined((*ptr as *mut u8).offset(unpoisoned as isize), size - unpoisoned);
+}
+
+/// Check whether the canaries around an allocation allocated with `allocate`
+/// or `reallocate` are intact.
+#[cfg(feature = "canaries")]
+pub unsafe fn canaries_intact<T>(ptr: NonZero<*mut T>, cap: NonZero<usize>) -> bool {
+    if mem::size_of::<T>() == 0 { return true }
+
+    canary::intact(*ptr as *mut u8, unchecked_allocation_size::<T>(cap))
+}
+
+/// Reallocate by allocating a new buffer, copying the contents over and
+/// releasing the old buffer, which has already been removed from the registry.
+unsafe fn move_reallocate<T>(backend: BackendHandle,
+                             ptr: NonZero<*mut T>,
+                             old_cap: NonZero<usize>,
+                             new_cap: NonZero<usize>) -> Result<NonZero<*mut T>, AllocError> {
+    let size = unchecked_allocation_size::<T>(new_cap);
+    let new = try!(raw_typed_allocate::<T>(backend, size));
+    ptr::copy_nonoverlapping(*ptr, *new, cmp::min(*old_cap, *new_cap));
+    release(backend, ptr, old_cap);
+    Ok(new)
+}
 
-    heap::deallocate(*ptr as *mut u8, old_size, mem::align_of::<T>())
+unsafe fn raw_allocate(backend: BackendHandle, size: usize, align: usize) -> *mut u8 {
+    if let Some(ptr) = recycled(backend, size, align) { return ptr }
+    retry(Layout { size: size, align: align }, || backend.get().allocate(size, align))
+}
+
+unsafe fn raw_reallocate(backend: BackendHandle, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+    if !poison::enabled() {
+        let layout = Layout { size: size, align: align };
+        return retry(layout, || backend.get().reallocate(ptr, old_size, size, align))
+    }
+
+    // Always move to a fresh allocation, so stale handles to the old one
+    // see poisoned memory.
+    let new = raw_allocate(backend, size, align);
+
+    if !new.is_null() {
+        ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+        raw_deallocate(backend, ptr, old_size, align);
+    }
+
+    new
+}
+
+/// Make an allocation of `layout`, retrying as long as the OOM hook asks
+/// to if it fails, and then once more after releasing the emergency reserve.
+fn retry<F: FnMut() -> *mut u8>(layout: Layout, mut allocate: F) -> *mut u8 {
+    let mut ptr = allocate();
+    let mut attempts = 1;
+
+    while ptr.is_null() && oom::should_retry(layout, attempts) {
+        ptr = allocate();
+        attempts += 1;
+    }
+
+    if ptr.is_null() && reserve::release() {
+        ptr = allocate();
+    }
+
+    ptr
+}
+
+unsafe fn raw_deallocate(backend: BackendHandle, ptr: *mut u8, old_size: usize, align: usize) {
+    poison::fill(ptr, old_size);
+    free(backend, ptr, old_size, align)
+}
+
+#[cfg(not(any(feature = "quarantine", feature = "recycler")))]
+unsafe fn free(backend: BackendHandle, ptr: *mut u8, old_size: usize, align: usize) {
+    backend.get().deallocate(ptr, old_size, align)
+}
+
+#[cfg(all(feature = "recycler", not(feature = "quarantine")))]
+unsafe fn free(backend: BackendHandle, ptr: *mut u8, old_size: usize, align: usize) {
+    recycler::keep(backend, ptr, old_size, align)
+}
+
+#[cfg(feature = "quarantine")]
+unsafe fn free(backend: BackendHandle, ptr: *mut u8, old_size: usize, align: usize) {
+    quarantine::quarantine_heap(backend, ptr, old_size, align)
+}
+
+#[cfg(all(feature = "recycler", not(feature = "quarantine")))]
+fn recycled(backend: BackendHandle, size: usize, align: usize) -> Option<*mut u8> {
+    recycler::take(backend, size, align)
+}
+
+#[cfg(not(all(feature = "recycler", not(feature = "quarantine"))))]
+fn recycled(_: BackendHandle, _: usize, _: usize) -> Option<*mut u8> {
+    None
+}
+
+/// The largest number of `T`s which fit in a single allocation.
+///
+/// No object may be larger than `isize::MAX` bytes, since pointer offsets
+/// within it must fit in an `isize`.
+pub fn max_capacity<T>() -> usize {
+    match mem::size_of::<T>() {
+        0 => usize::MAX,
+        size => isize::MAX as usize / size
+    }
 }
 
-fn allocation_size<T>(cap: NonZero<usize>) -> usize {
-    mem::size_of::<T>().checked_mul(*cap).expect("Capacity overflow")
+fn allocation_size<T>(cap: NonZero<usize>) -> Result<usize, AllocError> {
+    if *cap > max_capacity::<T>() { return Err(AllocError::CapacityOverflow) }
+    Ok(mem::size_of::<T>() * *cap)
 }
 
 fn unchecked_allocation_size<T>(cap: NonZero<usize>) -> usize {
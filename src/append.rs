@@ -0,0 +1,176 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ptr;
+
+use {SliceBuf, UniqueBuf};
+
+/// A fixed-capacity buffer which many threads append to at once.
+///
+/// Each thread reserves a range of elements with `reserve`, which is a
+/// single atomic update of a cursor, and then fills its range without any
+/// further coordination. Once every reservation is filled and dropped,
+/// `finish` turns the buffer into a `SliceBuf` of the appended elements, in
+/// the order the ranges were reserved.
+///
+/// ```
+/// # use membuf::ConcurrentAppendBuf;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let log = Arc::new(ConcurrentAppendBuf::with_capacity(64));
+///
+/// let threads: Vec<_> = (0..4).map(|id| {
+///     let log = log.clone();
+///     thread::spawn(move || {
+///         let mut batch = log.reserve(16).unwrap();
+///         for i in 0..16 { batch.push(id * 100 + i); }
+///     })
+/// }).collect();
+/// for thread in threads { thread.join().unwrap(); }
+///
+/// let entries = Arc::try_unwrap(log).unwrap().finish();
+/// assert_eq!(entries.len(), 64);
+/// assert!(entries.iter().all(|&entry| entry % 100 < 16));
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentAppendBuf<T> {
+    buffer: UniqueBuf<T>,
+    reserved: AtomicUsize,
+    written: AtomicUsize
+}
+
+unsafe impl<T: Send> Send for ConcurrentAppendBuf<T> {}
+unsafe impl<T: Send> Sync for ConcurrentAppendBuf<T> {}
+
+/// A range of a `ConcurrentAppendBuf` reserved by one thread, which it
+/// fills in order with `push`.
+pub struct Reservation<'a, T: 'a> {
+    ptr: *mut T,
+    len: usize,
+    filled: usize,
+    written: &'a AtomicUsize,
+    _marker: PhantomData<&'a mut [T]>
+}
+
+unsafe impl<'a, T: Send> Send for Reservation<'a, T> {}
+
+impl<T> ConcurrentAppendBuf<T> {
+    /// Create a new buffer with space for cap Ts.
+    pub fn with_capacity(cap: usize) -> ConcurrentAppendBuf<T> {
+        ConcurrentAppendBuf {
+            buffer: UniqueBuf::allocate(cap),
+            reserved: AtomicUsize::new(0),
+            written: AtomicUsize::new(0)
+        }
+    }
+
+    /// Get the capacity, which never changes.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Get the number of elements reserved so far.
+    pub fn reserved(&self) -> usize {
+        self.reserved.load(Ordering::SeqCst)
+    }
+
+    /// Reserve the next `n` elements for the calling thread to fill.
+    ///
+    /// Returns `None` if fewer than `n` elements are left.
+    pub fn reserve<'a>(&'a self, n: usize) -> Option<Reservation<'a, T>> {
+        let mut start = self.reserved.load(Ordering::Relaxed);
+        loop {
+            if self.capacity() - start < n { return None }
+
+            let current = self.reserved.compare_and_swap(start, start + n, Ordering::Relaxed);
+            if current == start { break }
+            start = current;
+        }
+
+        Some(Reservation {
+            ptr: unsafe { self.buffer.offset(start as isize) },
+            len: n,
+            filled: 0,
+            written: &self.written,
+            _marker: PhantomData
+        })
+    }
+
+    /// Get the appended elements.
+    ///
+    /// Panics if a reservation was dropped before it was filled, leaking the
+    /// elements.
+    pub fn finish(self) -> SliceBuf<T> {
+        // Pairs with the release in `Reservation::drop`, so every element
+        // written by another thread is visible.
+        let (reserved, written) = (self.reserved.load(Ordering::Acquire),
+                                   self.written.load(Ordering::Acquire));
+        if written != reserved {
+            panic!("ConcurrentAppendBuf finished with {} of {} reserved elements unwritten",
+                   reserved - written, reserved)
+        }
+
+        unsafe { SliceBuf::from_raw(self.buffer, reserved) }
+    }
+}
+
+impl<'a, T> Reservation<'a, T> {
+    /// Get the number of elements reserved.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the number of elements left to fill.
+    pub fn remaining(&self) -> usize {
+        self.len - self.filled
+    }
+
+    /// Write `value` into the next unfilled element.
+    ///
+    /// Panics if the reservation is already filled.
+    pub fn push(&mut self, value: T) {
+        assert!(self.filled < self.len, "Push to a filled reservation of {} elements", self.len);
+        unsafe { ptr::write(self.ptr.offset(self.filled as isize), value) }
+        self.filled += 1;
+    }
+}
+
+impl<'a, T> Drop for Reservation<'a, T> {
+    fn drop(&mut self) {
+        self.written.fetch_add(self.filled, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ConcurrentAppendBuf;
+
+    #[test]
+    fn test_reservations_are_disjoint() {
+        let buffer = ConcurrentAppendBuf::with_capacity(5);
+
+        {
+            let mut first = buffer.reserve(2).unwrap();
+            let mut second = buffer.reserve(3).unwrap();
+            assert!(buffer.reserve(1).is_none());
+
+            second.push('c');
+            first.push('a');
+            second.push('d');
+            first.push('b');
+            second.push('e');
+            assert_eq!((first.remaining(), second.remaining()), (0, 0));
+        }
+
+        assert_eq!(&*buffer.finish(), &['a', 'b', 'c', 'd', 'e']);
+    }
+
+    #[test]
+    #[should_panic = "ConcurrentAppendBuf finished with 1 of 3 reserved elements unwritten"]
+    fn test_unfilled_reservation() {
+        let buffer = ConcurrentAppendBuf::with_capacity(4);
+        buffer.reserve(2).unwrap().push(1);
+        buffer.reserve(1).unwrap().push(2);
+        buffer.finish();
+    }
+}
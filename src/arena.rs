@@ -0,0 +1,414 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{cmp, mem, ptr, slice};
+
+use {PinnedBuf, UniqueBuf};
+use backend::{BackendHandle, DEFAULT_BACKEND};
+
+// The size of the first chunk of an `Arena` created with `new`.
+const FIRST_CHUNK_BYTES: usize = 4096;
+
+/// An arena of Ts, which allocates them bump-style out of a chain of
+/// buffers and drops them all at once along with the arena.
+///
+/// Each chunk is twice the capacity of the one before, so the number of
+/// allocations grows only logarithmically with the number of values.
+/// Chunks never move, so the references `alloc` returns live as long as the
+/// arena does.
+///
+/// ```
+/// # use membuf::Arena;
+///
+/// let strings = Arena::new();
+/// let words: Vec<&str> = "the quick brown fox".split(' ')
+///     .map(|word| &**strings.alloc(word.to_uppercase()))
+///     .collect();
+///
+/// assert_eq!(words, ["THE", "QUICK", "BROWN", "FOX"]);
+/// assert_eq!(strings.len(), 4);
+/// ```
+#[derive(Debug)]
+pub struct Arena<T> {
+    chunks: RefCell<Vec<PinnedBuf<T>>>
+}
+
+impl<T> Arena<T> {
+    /// Create a new arena, whose first chunk takes about 4KiB.
+    pub fn new() -> Arena<T> {
+        Arena::with_capacity(FIRST_CHUNK_BYTES / cmp::max(mem::size_of::<T>(), 1))
+    }
+
+    /// Create a new arena whose first chunk has space for `cap` Ts.
+    pub fn with_capacity(cap: usize) -> Arena<T> {
+        Arena { chunks: RefCell::new(vec![PinnedBuf::allocate(cmp::max(cap, 1))]) }
+    }
+
+    /// Move `value` into the arena, returning a reference which lives as
+    /// long as the arena.
+    pub fn alloc(&self, value: T) -> &mut T {
+        let mut chunks = self.chunks.borrow_mut();
+
+        let value = match chunks.last().unwrap().push(value) {
+            Ok(slot) => return unsafe { &mut *(slot as *mut T) },
+            Err(value) => value
+        };
+
+        let cap = chunks.last().unwrap().capacity().saturating_mul(2);
+        let chunk = PinnedBuf::allocate(cap);
+        let slot = match chunk.push(value) {
+            Ok(slot) => slot as *mut T,
+            Err(_) => unreachable!()
+        };
+        chunks.push(chunk);
+
+        unsafe { &mut *slot }
+    }
+
+    /// Get the number of values in the arena.
+    pub fn len(&self) -> usize {
+        self.chunks.borrow().iter().fold(0, |total, chunk| total + chunk.len())
+    }
+
+    /// Get the number of chunks allocated.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Arena<T> { Arena::new() }
+}
+
+/// An arena of bytes, which allocates values of any type bump-style out of
+/// a chain of buffers and frees them all at once along with the arena.
+///
+/// This is the single-threaded counterpart of `ConcurrentArena`, without
+/// the cost of atomic operations. As there, values are never dropped, only
+/// their memory is freed.
+///
+/// ```
+/// # use membuf::ByteArena;
+///
+/// let arena = ByteArena::new(4096);
+///
+/// let name = arena.alloc_slice_copy(b"membuf");
+/// let version = arena.alloc((0u32, 1u32));
+/// name[0] = b'M';
+///
+/// assert_eq!(&name[..], b"Membuf");
+/// assert_eq!(*version, (0, 1));
+/// ```
+#[derive(Debug)]
+pub struct ByteArena {
+    chunks: RefCell<Vec<UniqueBuf<u8>>>,
+    // The offset of the first free byte in the last chunk.
+    cursor: Cell<usize>,
+    // The bytes used by every chunk but the last.
+    filled: Cell<usize>,
+    chunk_size: usize,
+    backend: BackendHandle
+}
+
+impl ByteArena {
+    /// Create a new arena which allocates chunks of `chunk_size` bytes.
+    pub fn new(chunk_size: usize) -> ByteArena {
+        ByteArena::with_backend(chunk_size, DEFAULT_BACKEND)
+    }
+
+    /// Create a new arena which allocates chunks of `chunk_size` bytes from
+    /// `backend`.
+    pub fn with_backend(chunk_size: usize, backend: BackendHandle) -> ByteArena {
+        ByteArena {
+            chunks: RefCell::new(vec![UniqueBuf::allocate_in(chunk_size, backend)]),
+            cursor: Cell::new(0),
+            filled: Cell::new(0),
+            chunk_size: chunk_size,
+            backend: backend
+        }
+    }
+
+    /// Allocate `size` bytes aligned to `align`, which must be a power of
+    /// two, returning a pointer which stays valid as long as the arena.
+    ///
+    /// Allocations larger than the chunk size get a chunk of their own.
+    pub fn alloc_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+
+        let mut chunks = self.chunks.borrow_mut();
+        if let Some(ptr) = self.bump(chunks.last().unwrap(), size, align) { return ptr }
+
+        let cap = cmp::max(self.chunk_size, size.saturating_add(align));
+        self.filled.set(self.filled.get() + self.cursor.get());
+        self.cursor.set(0);
+        chunks.push(UniqueBuf::allocate_in(cap, self.backend));
+
+        self.bump(chunks.last().unwrap(), size, align).unwrap()
+    }
+
+    /// Move `value` into the arena, returning a reference which lives as
+    /// long as the arena.
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_bytes(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    /// Copy `slice` into the arena, returning a reference which lives as
+    /// long as the arena.
+    pub fn alloc_slice_copy<T: Copy>(&self, slice: &[T]) -> &mut [T] {
+        let size = mem::size_of::<T>().checked_mul(slice.len()).expect("capacity overflow");
+        let ptr = self.alloc_bytes(size, mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr::copy_nonoverlapping(slice.as_ptr(), ptr, slice.len());
+            slice::from_raw_parts_mut(ptr, slice.len())
+        }
+    }
+
+    /// Get the number of bytes allocated from the arena, including padding
+    /// for alignment.
+    pub fn allocated_bytes(&self) -> usize {
+        self.filled.get() + self.cursor.get()
+    }
+
+    /// Get the number of chunks allocated.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+
+    fn bump(&self, chunk: &UniqueBuf<u8>, size: usize, align: usize) -> Option<*mut u8> {
+        bump_offsets(chunk, self.cursor.get(), size, align).map(|(start, end)| {
+            self.cursor.set(end);
+            unsafe { chunk.offset(start as isize) }
+        })
+    }
+}
+
+// Find where an allocation of `size` bytes aligned to `align` starts and
+// ends in `chunk` when it is filled up to `cursor`, or `None` if it does
+// not fit.
+fn bump_offsets(chunk: &UniqueBuf<u8>, cursor: usize, size: usize, align: usize) -> Option<(usize, usize)> {
+    // Align the address rather than the offset, as the chunk itself may be
+    // less aligned than the value.
+    let base = **chunk as usize;
+    let start = ((base + cursor + align - 1) & !(align - 1)) - base;
+    match start.checked_add(size) {
+        Some(end) if end <= chunk.capacity() => Some((start, end)),
+        _ => None
+    }
+}
+
+/// A bump allocator which many threads allocate from at once.
+///
+/// Allocation is a compare-and-swap on the cursor of the current chunk.
+/// When the chunk is full, a new one is allocated from the arena's backend
+/// under a lock, which is the only time allocating threads wait for each
+/// other. Chunks are only freed when the arena is dropped, so everything
+/// allocated lives as long as the arena does.
+///
+/// Values are never dropped, only their memory is freed along with the
+/// arena.
+///
+/// ```
+/// # use membuf::ConcurrentArena;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let arena = Arc::new(ConcurrentArena::new(4096));
+///
+/// let threads: Vec<_> = (0..4u64).map(|id| {
+///     let arena = arena.clone();
+///     thread::spawn(move || {
+///         let tokens: Vec<&mut u64> = (0..100).map(|i| arena.alloc(id * 1000 + i)).collect();
+///         assert!(tokens.iter().enumerate().all(|(i, token)| **token == id * 1000 + i as u64));
+///     })
+/// }).collect();
+/// for thread in threads { thread.join().unwrap(); }
+///
+/// assert_eq!(arena.allocated_bytes(), 4 * 100 * 8);
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentArena {
+    current: AtomicPtr<Chunk>,
+    // Every chunk, which also serializes growing the arena.
+    chunks: Mutex<Vec<Box<Chunk>>>,
+    chunk_size: usize,
+    backend: BackendHandle
+}
+
+unsafe impl Send for ConcurrentArena {}
+unsafe impl Sync for ConcurrentArena {}
+
+#[derive(Debug)]
+struct Chunk {
+    buffer: UniqueBuf<u8>,
+    cursor: AtomicUsize
+}
+
+impl Chunk {
+    fn new(cap: usize, backend: BackendHandle) -> Box<Chunk> {
+        Box::new(Chunk { buffer: UniqueBuf::allocate_in(cap, backend), cursor: AtomicUsize::new(0) })
+    }
+
+    fn bump(&self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut cursor = self.cursor.load(Ordering::Relaxed);
+
+        loop {
+            let (start, end) = match bump_offsets(&self.buffer, cursor, size, align) {
+                Some(offsets) => offsets,
+                None => return None
+            };
+
+            let current = self.cursor.compare_and_swap(cursor, end, Ordering::Relaxed);
+            if current == cursor { return Some(unsafe { self.buffer.offset(start as isize) }) }
+            cursor = current;
+        }
+    }
+}
+
+impl ConcurrentArena {
+    /// Create a new arena which allocates chunks of `chunk_size` bytes.
+    pub fn new(chunk_size: usize) -> ConcurrentArena {
+        ConcurrentArena::with_backend(chunk_size, DEFAULT_BACKEND)
+    }
+
+    /// Create a new arena which allocates chunks of `chunk_size` bytes from
+    /// `backend`.
+    pub fn with_backend(chunk_size: usize, backend: BackendHandle) -> ConcurrentArena {
+        let mut first = Chunk::new(chunk_size, backend);
+        ConcurrentArena {
+            current: AtomicPtr::new(&mut *first),
+            chunks: Mutex::new(vec![first]),
+            chunk_size: chunk_size,
+            backend: backend
+        }
+    }
+
+    /// Allocate `size` bytes aligned to `align`, which must be a power of
+    /// two, returning a pointer which stays valid as long as the arena.
+    ///
+    /// Allocations larger than the chunk size get a chunk of their own.
+    pub fn alloc_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+
+        loop {
+            let chunk = unsafe { &*self.current.load(Ordering::Acquire) };
+            match chunk.bump(size, align) {
+                Some(ptr) => return ptr,
+                None => self.grow(chunk, size, align)
+            }
+        }
+    }
+
+    /// Move `value` into the arena, returning a reference which lives as
+    /// long as the arena.
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_bytes(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    /// Copy `slice` into the arena, returning a reference which lives as
+    /// long as the arena.
+    pub fn alloc_slice_copy<T: Copy>(&self, slice: &[T]) -> &mut [T] {
+        let size = mem::size_of::<T>().checked_mul(slice.len()).expect("capacity overflow");
+        let ptr = self.alloc_bytes(size, mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr::copy_nonoverlapping(slice.as_ptr(), ptr, slice.len());
+            slice::from_raw_parts_mut(ptr, slice.len())
+        }
+    }
+
+    /// Get the number of bytes allocated from the arena, including padding
+    /// for alignment.
+    pub fn allocated_bytes(&self) -> usize {
+        let chunks = self.chunks.lock().unwrap_or_else(|e| e.into_inner());
+        chunks.iter().fold(0, |total, chunk| total + chunk.cursor.load(Ordering::Relaxed))
+    }
+
+    /// Get the number of chunks allocated.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    // Replace `full`, unless another thread already has.
+    fn grow(&self, full: &Chunk, size: usize, align: usize) {
+        let mut chunks = self.chunks.lock().unwrap_or_else(|e| e.into_inner());
+        if self.current.load(Ordering::Acquire) as *const Chunk != full as *const Chunk {
+            return
+        }
+
+        let cap = cmp::max(self.chunk_size, size.saturating_add(align));
+        let mut chunk = Chunk::new(cap, self.backend);
+        self.current.store(&mut *chunk, Ordering::Release);
+        chunks.push(chunk);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::thread;
+
+    use {Arena, ByteArena, ConcurrentArena};
+
+    #[test]
+    fn test_arena_chains_chunks_and_drops_values() {
+        let counted = Rc::new(());
+        let arena = Arena::with_capacity(2);
+
+        let values: Vec<*const Rc<()>> = (0..7).map(|_| {
+            &*arena.alloc(counted.clone()) as *const Rc<()>
+        }).collect();
+
+        // Chunks of 2, 4 and 8, and nothing moved as the arena grew.
+        assert_eq!(arena.chunk_count(), 3);
+        assert_eq!(arena.len(), 7);
+        assert!(values.iter().all(|&value| unsafe { Rc::ptr_eq(&*value, &counted) }));
+
+        assert_eq!(Rc::strong_count(&counted), 8);
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    fn test_growth_under_contention() {
+        let arena = Arc::new(ConcurrentArena::new(256));
+
+        let threads: Vec<_> = (0..8u8).map(|id| {
+            let arena = arena.clone();
+            thread::spawn(move || {
+                let strings: Vec<&mut [u8]> = (0..200)
+                    .map(|_| arena.alloc_slice_copy(&[id; 7]))
+                    .collect();
+                assert!(strings.iter().all(|string| string.iter().all(|&b| b == id)));
+            })
+        }).collect();
+        for thread in threads { thread.join().unwrap(); }
+
+        assert_eq!(arena.allocated_bytes(), 8 * 200 * 7);
+        assert!(arena.chunk_count() >= 8 * 200 * 7 / 256);
+    }
+
+    #[test]
+    fn test_alignment_and_large_allocations() {
+        let arena = ByteArena::new(64);
+        arena.alloc(1u8);
+        assert_eq!(arena.alloc_bytes(8, 32) as usize % 32, 0);
+        assert_eq!(arena.alloc_slice_copy(&[3u32; 100]).len(), 100);
+        assert_eq!(arena.chunk_count(), 2);
+        assert!(arena.allocated_bytes() >= 1 + 8 + 400);
+
+        let arena = ConcurrentArena::new(64);
+        arena.alloc(1u8);
+        assert_eq!(arena.alloc_bytes(8, 32) as usize % 32, 0);
+        assert_eq!(arena.alloc_slice_copy(&[3u32; 100]).len(), 100);
+        assert_eq!(arena.chunk_count(), 2);
+        assert!(arena.allocated_bytes() >= 1 + 8 + 400);
+    }
+}
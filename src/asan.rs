@@ -0,0 +1,36 @@
+//! AddressSanitizer annotations.
+//!
+//! With the `asan` feature enabled, and the crate built with AddressSanitizer,
+//! collections can tell ASAN which part of a buffer holds live elements with
+//! `MemBuf::set_unpoisoned_len`, and accesses to the rest of its capacity are
+//! then reported as errors.
+//!
+//! Without the feature every function in here is a no-op.
+
+#[cfg(feature = "asan")]
+extern {
+    fn __asan_poison_memory_region(addr: *const u8, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+}
+
+/// Mark `size` bytes at `ptr` as inaccessible.
+#[cfg(feature = "asan")]
+pub unsafe fn poison(ptr: *mut u8, size: usize) {
+    __asan_poison_memory_region(ptr, size)
+}
+
+/// Mark `size` bytes at `ptr` as accessible.
+#[cfg(feature = "asan")]
+pub unsafe fn unpoison(ptr: *mut u8, size: usize) {
+    __asan_unpoison_memory_region(ptr, size)
+}
+
+/// Mark `size` bytes at `ptr` as inaccessible.
+#[cfg(not(feature = "asan"))]
+#[inline(always)]
+pub unsafe fn poison(_: *mut u8, _: usize) {}
+
+/// Mark `size` bytes at `ptr` as accessible.
+#[cfg(not(feature = "asan"))]
+#[inline(always)]
+pub unsafe fn unpoison(_: *mut u8, _: usize) {}
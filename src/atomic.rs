@@ -0,0 +1,261 @@
+use std::ops::Deref;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use UniqueBuf;
+
+/// A function given each buffer displaced from an `AtomicBuf` once no
+/// reader can still be using it.
+pub type ReclaimFn<T> = fn(UniqueBuf<T>);
+
+/// A slot holding a `UniqueBuf`, which can be replaced atomically while
+/// other threads are reading it.
+///
+/// Readers get a guard from `load_acquire`, which keeps the buffer they
+/// loaded alive. Writers `store` or `swap` in a new buffer; a displaced
+/// buffer which might still be in use is retired, and handed to the reclaim
+/// function once every reader which started before it was retired has
+/// dropped its guard. By default it is simply dropped.
+///
+/// Readers are counted per epoch, and the epoch only moves on once the
+/// readers of the one before it have finished, so a steady stream of
+/// overlapping readers delays reclamation by at most two epochs rather than
+/// holding every retired buffer back.
+///
+/// ```
+/// # use membuf::{AtomicBuf, UniqueBuf};
+///
+/// let config = AtomicBuf::new(UniqueBuf::from_slice(b"v1"));
+///
+/// let old = config.load_acquire();
+/// config.store(UniqueBuf::from_slice(b"v2"));
+///
+/// // The old buffer stays valid while its guard is alive.
+/// assert_eq!(unsafe { old.as_slice(2) }, b"v1");
+/// drop(old);
+///
+/// assert_eq!(unsafe { config.load_acquire().as_slice(2) }, b"v2");
+/// ```
+pub struct AtomicBuf<T> {
+    current: AtomicPtr<UniqueBuf<T>>,
+    epoch: AtomicUsize,
+    // The readers which started in even and odd epochs.
+    readers: [AtomicUsize; 2],
+    // Buffers with the epoch they were retired in.
+    retired: Mutex<Vec<(usize, Box<UniqueBuf<T>>)>>,
+    reclaim: ReclaimFn<T>
+}
+
+unsafe impl<T: Send + Sync> Send for AtomicBuf<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicBuf<T> {}
+
+/// A buffer loaded from an `AtomicBuf`, which is kept alive until the guard
+/// is dropped.
+pub struct AtomicBufGuard<'a, T: 'a> {
+    parent: &'a AtomicBuf<T>,
+    buffer: &'a UniqueBuf<T>,
+    slot: usize
+}
+
+impl<T> AtomicBuf<T> {
+    /// Create a slot holding `buffer`, whose displaced buffers are dropped.
+    pub fn new(buffer: UniqueBuf<T>) -> AtomicBuf<T> {
+        AtomicBuf::with_reclaim(buffer, drop)
+    }
+
+    /// Create a slot holding `buffer`, whose displaced buffers are given to
+    /// `reclaim` once they are no longer in use, for example to return them
+    /// to a pool.
+    pub fn with_reclaim(buffer: UniqueBuf<T>, reclaim: ReclaimFn<T>) -> AtomicBuf<T> {
+        AtomicBuf {
+            current: AtomicPtr::new(Box::into_raw(Box::new(buffer))),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            retired: Mutex::new(Vec::new()),
+            reclaim: reclaim
+        }
+    }
+
+    /// Load the current buffer, which stays valid until the guard is dropped
+    /// even if it is replaced.
+    pub fn load_acquire<'a>(&'a self) -> AtomicBufGuard<'a, T> {
+        // Announcing the reader before loading means a writer which retires
+        // the buffer afterwards does so in this epoch or a later one. If the
+        // epoch moved on in between, the count may already have been
+        // checked, so announce again in the new one.
+        let mut slot;
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            slot = epoch % 2;
+            self.readers[slot].fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch { break }
+            self.readers[slot].fetch_sub(1, Ordering::SeqCst);
+        }
+
+        let buffer = unsafe { &*self.current.load(Ordering::SeqCst) };
+        AtomicBufGuard { parent: self, buffer: buffer, slot: slot }
+    }
+
+    /// Replace the current buffer with `buffer`, retiring the old one.
+    pub fn store(&self, buffer: UniqueBuf<T>) {
+        let old = self.replace(buffer);
+        self.retire(old);
+    }
+
+    /// Replace the current buffer with `buffer`, returning the old one if no
+    /// reader can still be using it, or else retiring it.
+    ///
+    /// ```
+    /// # use membuf::{AtomicBuf, UniqueBuf};
+    ///
+    /// let (front, back): (UniqueBuf<u8>, UniqueBuf<u8>) =
+    ///     (UniqueBuf::allocate(8), UniqueBuf::allocate(8));
+    /// let slot = AtomicBuf::new(front);
+    ///
+    /// let front = slot.swap(back).unwrap();
+    /// let guard = slot.load_acquire();
+    /// assert!(slot.swap(front).is_none());
+    /// ```
+    pub fn swap(&self, buffer: UniqueBuf<T>) -> Option<UniqueBuf<T>> {
+        let old = self.replace(buffer);
+
+        if self.readers.iter().all(|readers| readers.load(Ordering::SeqCst) == 0) {
+            Some(*old)
+        } else {
+            self.retire(old);
+            None
+        }
+    }
+
+    /// Hand every retired buffer which no reader can still be using to the
+    /// reclaim function.
+    ///
+    /// This happens automatically when buffers are retired and when the last
+    /// guard of an epoch is dropped.
+    pub fn reclaim(&self) {
+        let ready = {
+            let mut retired = self.retired.lock().unwrap_or_else(|e| e.into_inner());
+            if retired.is_empty() { return }
+
+            // The epoch moves on once no reader of the epoch before the
+            // current one is left, which shares its count with the next.
+            for _ in 0..2 {
+                let epoch = self.epoch.load(Ordering::SeqCst);
+                if self.readers[(epoch + 1) % 2].load(Ordering::SeqCst) != 0 { break }
+                self.epoch.compare_and_swap(epoch, epoch + 1, Ordering::SeqCst);
+            }
+
+            // A buffer was replaced before it was retired, so only readers
+            // of its epoch or earlier ones can hold it, and two epochs later
+            // those have all finished.
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let (ready, waiting): (Vec<_>, Vec<_>) = retired.drain(..)
+                .partition(|&(retired_in, _)| retired_in + 2 <= epoch);
+            *retired = waiting;
+            ready
+        };
+
+        for (_, buffer) in ready { (self.reclaim)(*buffer) }
+    }
+
+    /// Get the number of retired buffers waiting for readers to finish.
+    pub fn retired(&self) -> usize {
+        self.retired.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn replace(&self, buffer: UniqueBuf<T>) -> Box<UniqueBuf<T>> {
+        let new = Box::into_raw(Box::new(buffer));
+        unsafe { Box::from_raw(self.current.swap(new, Ordering::SeqCst)) }
+    }
+
+    fn retire(&self, buffer: Box<UniqueBuf<T>>) {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        self.retired.lock().unwrap_or_else(|e| e.into_inner()).push((epoch, buffer));
+        self.reclaim();
+    }
+}
+
+impl<T> Drop for AtomicBuf<T> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(*self.current.get_mut())); }
+        self.reclaim();
+    }
+}
+
+impl<'a, T> Deref for AtomicBufGuard<'a, T> {
+    type Target = UniqueBuf<T>;
+
+    fn deref(&self) -> &UniqueBuf<T> { self.buffer }
+}
+
+impl<'a, T> Drop for AtomicBufGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.parent.readers[self.slot].fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.parent.reclaim();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use {AtomicBuf, UniqueBuf};
+
+    static RECLAIMED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn count(buffer: UniqueBuf<u64>) {
+        RECLAIMED.fetch_add(buffer.capacity(), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_reclaim_waits_for_readers() {
+        let slot = AtomicBuf::with_reclaim(UniqueBuf::allocate(1), count);
+
+        let guard = slot.load_acquire();
+        slot.store(UniqueBuf::allocate(2));
+        slot.store(UniqueBuf::allocate(4));
+        assert_eq!((slot.retired(), RECLAIMED.load(Ordering::SeqCst)), (2, 0));
+
+        assert_eq!(guard.capacity(), 1);
+        drop(guard);
+        assert_eq!((slot.retired(), RECLAIMED.load(Ordering::SeqCst)), (0, 3));
+    }
+
+    #[test]
+    fn test_overlapping_readers_do_not_block_reclaim() {
+        use std::mem;
+
+        let slot = AtomicBuf::new(UniqueBuf::<u8>::allocate(1));
+        let mut guard = slot.load_acquire();
+
+        for _ in 0..100 {
+            slot.store(UniqueBuf::allocate(1));
+            let next = slot.load_acquire();
+            drop(mem::replace(&mut guard, next));
+            assert!(slot.retired() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_readers_see_whole_buffers() {
+        let slot = Arc::new(AtomicBuf::new(UniqueBuf::from_slice(&[0u64; 16])));
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let slot = slot.clone();
+            thread::spawn(move || for _ in 0..1000 {
+                let buffer = slot.load_acquire();
+                let values = unsafe { buffer.as_slice(16) };
+                assert!(values.iter().all(|&value| value == values[0]));
+            })
+        }).collect();
+
+        for i in 1..1000 { slot.store(UniqueBuf::from_slice(&[i; 16])); }
+        for reader in readers { reader.join().unwrap(); }
+
+        slot.reclaim();
+        assert_eq!(slot.retired(), 0);
+    }
+}
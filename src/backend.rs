@@ -0,0 +1,777 @@
+//! Pluggable allocation backends.
+//!
+//! All memory allocated through `alloc`, and so by `MemBuf` and the buffers
+//! built on it, ultimately comes from a backend. This is the default backend,
+//! which is the standard heap unless replaced with `set_backend`, unless
+//! another backend registered with `register_backend` is chosen for a
+//! buffer.
+
+use std::cell::RefCell;
+use std::{cmp, mem, ptr};
+use std::rt::heap;
+use std::thread::LocalKeyState;
+
+use libc;
+
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+/// A source of raw memory, with the same interface as `std::rt::heap`.
+///
+/// Implementations must be safe to use from multiple threads at once.
+pub trait Backend: Sync {
+    /// Allocate `size` bytes aligned to `align`, returning null on failure.
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8;
+
+    /// Resize an allocation to `size` bytes, returning null on failure, in
+    /// which case the original allocation is left untouched.
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8;
+
+    /// Release an allocation of `old_size` bytes.
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize);
+}
+
+/// The standard heap, the default backend.
+#[derive(Copy, Clone, Debug)]
+pub struct Heap;
+
+impl Backend for Heap {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        heap::allocate(size, align)
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        heap::reallocate(ptr, old_size, size, align)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        heap::deallocate(ptr, old_size, align)
+    }
+}
+
+/// The C heap, through `malloc`, `realloc` and `free`.
+///
+/// Memory from the standard heap must never be released with `free`, nor
+/// memory from `malloc` be released through the standard heap. With this
+/// backend installed, buffers can be handed to C libraries which will `free`
+/// them with `UniqueBuf::into_foreign`, and memory from `malloc` can be
+/// taken over with `UniqueBuf::from_foreign`.
+///
+/// ```
+/// # extern crate libc;
+/// # extern crate membuf;
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, Malloc};
+///
+/// # fn main() {
+/// unsafe { backend::set_backend(&Malloc) }
+/// # if cfg!(feature = "canaries") { return }
+///
+/// let buffer: UniqueBuf<u32> = UniqueBuf::allocate(16);
+/// unsafe { libc::free(buffer.into_foreign() as *mut libc::c_void) }
+///
+/// let ptr = unsafe { libc::malloc(64) as *mut u8 };
+/// let mut buffer = unsafe { UniqueBuf::from_foreign(ptr, 64) };
+/// buffer.reallocate(4096);
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Malloc;
+
+// The alignment `malloc` guarantees for every allocation.
+#[cfg(target_pointer_width = "64")]
+const MALLOC_ALIGN: usize = 16;
+#[cfg(not(target_pointer_width = "64"))]
+const MALLOC_ALIGN: usize = 8;
+
+impl Backend for Malloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        if align <= MALLOC_ALIGN {
+            libc::malloc(size) as *mut u8
+        } else {
+            aligned_malloc(size, align)
+        }
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        if align <= MALLOC_ALIGN {
+            return libc::realloc(ptr as *mut libc::c_void, size) as *mut u8
+        }
+
+        // `realloc` only preserves the alignment of `malloc`.
+        let new = aligned_malloc(size, align);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+            libc::free(ptr as *mut libc::c_void);
+        }
+        new
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, _old_size: usize, _align: usize) {
+        libc::free(ptr as *mut libc::c_void)
+    }
+}
+
+#[cfg(unix)]
+unsafe fn aligned_malloc(size: usize, align: usize) -> *mut u8 {
+    let mut ptr = ptr::null_mut();
+    let align = cmp::max(align, mem::size_of::<usize>());
+
+    if libc::posix_memalign(&mut ptr, align, size) == 0 { ptr as *mut u8 } else { ptr::null_mut() }
+}
+
+// Other platforms have no over-aligned allocation which `free` can release.
+#[cfg(not(unix))]
+unsafe fn aligned_malloc(_size: usize, _align: usize) -> *mut u8 {
+    ptr::null_mut()
+}
+
+/// jemalloc, through its extended interface, which is given the size of
+/// every allocation it releases and tries to grow allocations in place.
+///
+/// Only available with the `jemalloc` feature, which links the system's
+/// jemalloc. Like `Malloc`, memory from this backend must only ever be
+/// released through it.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, Jemalloc};
+///
+/// unsafe { backend::set_backend(&Jemalloc) }
+///
+/// let mut buffer: UniqueBuf<u64> = UniqueBuf::allocate(16);
+/// buffer.reallocate(1024);
+/// assert_eq!(buffer.capacity(), 1024);
+/// ```
+#[cfg(feature = "jemalloc")]
+#[derive(Copy, Clone, Debug)]
+pub struct Jemalloc;
+
+#[cfg(feature = "jemalloc")]
+#[link(name = "jemalloc")]
+extern {
+    fn mallocx(size: libc::size_t, flags: libc::c_int) -> *mut libc::c_void;
+    fn rallocx(ptr: *mut libc::c_void, size: libc::size_t,
+               flags: libc::c_int) -> *mut libc::c_void;
+    fn xallocx(ptr: *mut libc::c_void, size: libc::size_t, extra: libc::size_t,
+               flags: libc::c_int) -> libc::size_t;
+    fn sdallocx(ptr: *mut libc::c_void, size: libc::size_t, flags: libc::c_int);
+}
+
+// The flags requesting `align` from jemalloc, which on its own only aligns
+// to the size class.
+#[cfg(feature = "jemalloc")]
+fn mallocx_flags(align: usize) -> libc::c_int {
+    if align <= MALLOC_ALIGN { 0 } else { align.trailing_zeros() as libc::c_int }
+}
+
+#[cfg(feature = "jemalloc")]
+impl Backend for Jemalloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        mallocx(size, mallocx_flags(align)) as *mut u8
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, _old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        let flags = mallocx_flags(align);
+
+        // Resizing in place avoids both a new allocation and a copy.
+        if xallocx(ptr as *mut libc::c_void, size, 0, flags) >= size {
+            return ptr
+        }
+
+        rallocx(ptr as *mut libc::c_void, size, flags) as *mut u8
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        sdallocx(ptr as *mut libc::c_void, old_size, mallocx_flags(align))
+    }
+}
+
+/// mimalloc, for programs which already link it but do not use it as the
+/// global allocator.
+///
+/// Only available with the `mimalloc` feature, which links the system's
+/// mimalloc. Like `Malloc`, memory from this backend must only ever be
+/// released through it.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, Mimalloc};
+///
+/// unsafe { backend::set_backend(&Mimalloc) }
+///
+/// let mut buffer: UniqueBuf<u64> = UniqueBuf::allocate(16);
+/// buffer.reallocate(1024);
+/// assert_eq!(buffer.capacity(), 1024);
+/// ```
+#[cfg(feature = "mimalloc")]
+#[derive(Copy, Clone, Debug)]
+pub struct Mimalloc;
+
+#[cfg(feature = "mimalloc")]
+#[link(name = "mimalloc")]
+extern {
+    fn mi_malloc_aligned(size: libc::size_t, alignment: libc::size_t) -> *mut libc::c_void;
+    fn mi_realloc_aligned(ptr: *mut libc::c_void, size: libc::size_t,
+                          alignment: libc::size_t) -> *mut libc::c_void;
+    fn mi_free(ptr: *mut libc::c_void);
+}
+
+#[cfg(feature = "mimalloc")]
+impl Backend for Mimalloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        mi_malloc_aligned(size, align) as *mut u8
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, _old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        mi_realloc_aligned(ptr as *mut libc::c_void, size, align) as *mut u8
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, _old_size: usize, _align: usize) {
+        mi_free(ptr as *mut libc::c_void)
+    }
+}
+
+static HEAP: Heap = Heap;
+static mut BACKEND: &'static Backend = &HEAP;
+
+/// Replace the default backend, which all allocations are made with unless
+/// another backend is chosen with a `BackendHandle`.
+///
+/// ## Safety
+///
+/// Memory must always be released through the backend it was allocated
+/// with, so this must be called before any memory is allocated through
+/// `alloc`, and before any other threads are started.
+pub unsafe fn set_backend(backend: &'static Backend) {
+    BACKEND = backend;
+}
+
+/// Get the default backend.
+pub fn backend() -> &'static Backend {
+    unsafe { BACKEND }
+}
+
+/// Is the backend still the default standard heap?
+pub fn is_default() -> bool {
+    backend() as *const Backend as *const u8 == &HEAP as *const Heap as *const u8
+}
+
+/// A backend registered by name with `register_backend`, or the default
+/// backend.
+///
+/// Buffers allocated with a handle, for instance by `MemBuf::allocate_in`,
+/// record it, so they are always reallocated and released through the
+/// backend they were allocated with. This allows different subsystems to
+/// allocate from different backends side by side.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, Malloc, DEFAULT_BACKEND};
+///
+/// static MALLOC: Malloc = Malloc;
+///
+/// let malloc = unsafe { backend::register_backend("c-heap", &MALLOC) };
+/// assert_eq!(backend::find_backend("c-heap"), Some(malloc));
+/// assert_eq!(backend::find_backend("default"), Some(DEFAULT_BACKEND));
+///
+/// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate_in(64, malloc);
+/// buffer.reallocate(4096);
+/// assert_eq!(buffer.backend().name(), "c-heap");
+/// ```
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BackendHandle(usize);
+
+/// The handle of the default backend, which is whichever backend was
+/// installed with `set_backend`, and the standard heap otherwise.
+pub const DEFAULT_BACKEND: BackendHandle = BackendHandle(0);
+
+/// The handle of the standard heap, registered as `heap`, regardless of the
+/// default backend.
+pub const HEAP_BACKEND: BackendHandle = BackendHandle(1);
+
+/// The most backends which can be registered, including the default and the
+/// standard heap.
+pub const MAX_BACKENDS: usize = 16;
+
+// Backends by handle. The first slot, for the default backend, is never
+// read, since the default can be replaced at any time by `set_backend`.
+static mut BACKENDS: [Option<(&'static str, &'static Backend)>; MAX_BACKENDS] = [
+    None, Some(("heap", &HEAP as &'static Backend)), None, None,
+    None, None, None, None, None, None, None, None, None, None, None, None
+];
+
+impl BackendHandle {
+    /// Get the backend this handle refers to.
+    pub fn get(&self) -> &'static Backend {
+        match self.0 {
+            0 => backend(),
+            n => unsafe { BACKENDS[n].unwrap().1 }
+        }
+    }
+
+    /// Get the name the backend was registered with, `default` for the
+    /// default backend.
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            0 => "default",
+            n => unsafe { BACKENDS[n].unwrap().0 }
+        }
+    }
+
+    /// Get the number identifying this handle, for passing it through code
+    /// which cannot hold a `BackendHandle`, such as C.
+    pub fn id(&self) -> usize {
+        self.0
+    }
+
+    /// Get the handle identified by `id`, as returned by `id`.
+    ///
+    /// Panics if no backend is registered under `id`.
+    pub fn from_id(id: usize) -> BackendHandle {
+        match BackendHandle::try_from_id(id) {
+            Some(handle) => handle,
+            None => panic!("No backend is registered with id {}", id)
+        }
+    }
+
+    /// Get the handle identified by `id`, as returned by `id`, or `None` if
+    /// no backend is registered under `id`.
+    pub fn try_from_id(id: usize) -> Option<BackendHandle> {
+        if id >= MAX_BACKENDS || (id != 0 && unsafe { BACKENDS[id].is_none() }) {
+            None
+        } else {
+            Some(BackendHandle(id))
+        }
+    }
+}
+
+/// Register a backend under `name`, returning the handle buffers can be
+/// allocated with.
+///
+/// Panics if the name is already taken, or if `MAX_BACKENDS` are already
+/// registered.
+///
+/// ## Safety
+///
+/// As with `set_backend`, this must be called before any other threads are
+/// started.
+pub unsafe fn register_backend(name: &'static str, backend: &'static Backend) -> BackendHandle {
+    if find_backend(name).is_some() {
+        panic!("A backend named {} is already registered", name)
+    }
+
+    match BACKENDS.iter().skip(1).position(|slot| slot.is_none()) {
+        Some(index) => {
+            BACKENDS[index + 1] = Some((name, backend));
+            BackendHandle(index + 1)
+        },
+        None => panic!("Cannot register more than {} backends", MAX_BACKENDS)
+    }
+}
+
+/// Find the backend registered under `name`.
+pub fn find_backend(name: &str) -> Option<BackendHandle> {
+    if name == "default" { return Some(DEFAULT_BACKEND) }
+
+    unsafe {
+        BACKENDS.iter().position(|slot| match *slot {
+            Some((registered, _)) => registered == name,
+            None => false
+        }).map(BackendHandle)
+    }
+}
+
+/// A backend which allocates from the heap, while keeping count of live
+/// allocations and bytes.
+///
+/// Install it at the start of a test suite to check that code built on
+/// membuf does not leak. Note that the counts cover every thread, so tests
+/// which run concurrently can see each other's allocations.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, TrackingAlloc, TRACKING_ALLOC_INIT};
+///
+/// static TRACKER: TrackingAlloc = TRACKING_ALLOC_INIT;
+///
+/// unsafe { backend::set_backend(&TRACKER) }
+///
+/// let buffer: UniqueBuf<u64> = UniqueBuf::allocate(16);
+/// assert_eq!(TRACKER.live_allocations(), 1);
+/// assert!(TRACKER.live_bytes() >= 128);
+///
+/// drop(buffer);
+/// TRACKER.assert_no_leaks();
+/// ```
+pub struct TrackingAlloc {
+    allocations: AtomicUsize,
+    bytes: AtomicUsize
+}
+
+/// A `TrackingAlloc` with no live allocations, for initializing statics.
+pub const TRACKING_ALLOC_INIT: TrackingAlloc = TrackingAlloc {
+    allocations: ATOMIC_USIZE_INIT,
+    bytes: ATOMIC_USIZE_INIT
+};
+
+impl TrackingAlloc {
+    /// The number of allocations which have not been deallocated.
+    pub fn live_allocations(&self) -> usize {
+        self.allocations.load(Ordering::SeqCst)
+    }
+
+    /// The number of bytes allocated which have not been deallocated.
+    pub fn live_bytes(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    /// Panic if any allocation has not been deallocated.
+    ///
+    /// With the `quarantine` feature, the quarantine is flushed first, and
+    /// with the `recycler` feature, the recycler is trimmed.
+    pub fn assert_no_leaks(&self) {
+        flush_quarantine();
+        trim_recycler();
+
+        let (allocations, bytes) = (self.live_allocations(), self.live_bytes());
+
+        if allocations != 0 {
+            panic!("Leaked {} allocations totalling {} bytes", allocations, bytes)
+        }
+    }
+}
+
+impl Backend for TrackingAlloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        let ptr = HEAP.allocate(size, align);
+
+        if !ptr.is_null() {
+            self.allocations.fetch_add(1, Ordering::SeqCst);
+            self.bytes.fetch_add(size, Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        let new = HEAP.reallocate(ptr, old_size, size, align);
+
+        if !new.is_null() {
+            self.bytes.fetch_add(size, Ordering::SeqCst);
+            self.bytes.fetch_sub(old_size, Ordering::SeqCst);
+        }
+
+        new
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        HEAP.deallocate(ptr, old_size, align);
+
+        self.allocations.fetch_sub(1, Ordering::SeqCst);
+        self.bytes.fetch_sub(old_size, Ordering::SeqCst);
+    }
+}
+
+/// A backend which allocates from the heap, but can be told to fail, for
+/// testing how code copes with running out of memory.
+///
+/// Failures can be injected on the Nth allocation, or on any allocation that
+/// would take the live bytes over a budget. Every call to `allocate` or
+/// `reallocate` counts as an allocation, so running code once and reading
+/// `allocations` tells a test how many failure points there are to try.
+///
+/// ```
+/// use membuf::{AllocError, UniqueBuf};
+/// use membuf::backend::{self, FailingAlloc, FAILING_ALLOC_INIT};
+///
+/// static FAILING: FailingAlloc = FAILING_ALLOC_INIT;
+///
+/// unsafe { backend::set_backend(&FAILING) }
+///
+/// FAILING.fail_nth_allocation(2);
+/// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(16);
+/// assert_eq!(buffer.try_reallocate(32), Err(AllocError::OutOfMemory));
+/// assert_eq!(buffer.capacity(), 16);
+/// buffer.reallocate(32);
+///
+/// FAILING.fail_after_bytes(1024);
+/// assert!(UniqueBuf::<u8>::try_allocate(4096).is_err());
+///
+/// FAILING.reset();
+/// assert!(UniqueBuf::<u8>::try_allocate(4096).is_ok());
+/// ```
+pub struct FailingAlloc {
+    allocations: AtomicUsize,
+    fail_at: AtomicUsize,
+    bytes: AtomicUsize,
+    budget: AtomicUsize,
+    budgeted: AtomicBool
+}
+
+/// A `FailingAlloc` which does not fail, for initializing statics.
+pub const FAILING_ALLOC_INIT: FailingAlloc = FailingAlloc {
+    allocations: ATOMIC_USIZE_INIT,
+    fail_at: ATOMIC_USIZE_INIT,
+    bytes: ATOMIC_USIZE_INIT,
+    budget: ATOMIC_USIZE_INIT,
+    budgeted: ATOMIC_BOOL_INIT
+};
+
+impl FailingAlloc {
+    /// Fail the `n`th allocation from now, counting from one. Later
+    /// allocations succeed again.
+    ///
+    /// This resets the count returned by `allocations`.
+    pub fn fail_nth_allocation(&self, n: usize) {
+        self.allocations.store(0, Ordering::SeqCst);
+        self.fail_at.store(n, Ordering::SeqCst);
+    }
+
+    /// Fail every allocation which would take the number of live bytes over
+    /// `budget`.
+    pub fn fail_after_bytes(&self, budget: usize) {
+        self.budget.store(budget, Ordering::SeqCst);
+        self.budgeted.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop injecting failures, and reset the count returned by
+    /// `allocations`.
+    pub fn reset(&self) {
+        self.fail_at.store(0, Ordering::SeqCst);
+        self.budgeted.store(false, Ordering::SeqCst);
+        self.allocations.store(0, Ordering::SeqCst);
+    }
+
+    /// The number of allocations attempted since the last call to `reset`
+    /// or `fail_nth_allocation`, including failed ones.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::SeqCst)
+    }
+
+    /// The number of bytes allocated which have not been deallocated.
+    pub fn live_bytes(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    /// Should an allocation growing the live bytes from `old_size` to `size`
+    /// succeed?
+    fn admit(&self, old_size: usize, size: usize) -> bool {
+        let n = self.allocations.fetch_add(1, Ordering::SeqCst) + 1;
+        if n == self.fail_at.load(Ordering::SeqCst) { return false }
+
+        // `old_size` may be larger than the live bytes, if it was not counted
+        // by this backend, so saturate instead of underflowing.
+        !self.budgeted.load(Ordering::SeqCst) ||
+            self.live_bytes().saturating_add(size).saturating_sub(old_size) <=
+                self.budget.load(Ordering::SeqCst)
+    }
+}
+
+impl Backend for FailingAlloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        if !self.admit(0, size) { return ptr::null_mut() }
+
+        let ptr = HEAP.allocate(size, align);
+        if !ptr.is_null() { self.bytes.fetch_add(size, Ordering::SeqCst); }
+        ptr
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        if !self.admit(old_size, size) { return ptr::null_mut() }
+
+        let new = HEAP.reallocate(ptr, old_size, size, align);
+
+        if !new.is_null() {
+            self.bytes.fetch_add(size, Ordering::SeqCst);
+            self.bytes.fetch_sub(old_size, Ordering::SeqCst);
+        }
+
+        new
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        HEAP.deallocate(ptr, old_size, align);
+        self.bytes.fetch_sub(old_size, Ordering::SeqCst);
+    }
+}
+
+/// A backend which keeps a cache of recently freed blocks on each thread,
+/// in front of the standard heap.
+///
+/// Small allocations are rounded up to a power of two, from 16 bytes up to
+/// `THREAD_CACHE_MAX_SIZE`, and when freed are kept in the freeing thread's
+/// cache for that size class, up to `THREAD_CACHE_BLOCKS` blocks per class.
+/// The next allocation of that class on the thread reuses one without
+/// touching the heap, which removes the cost of short-lived scratch
+/// buffers. Larger or more aligned allocations go straight to the heap.
+///
+/// A thread's cache is released to the heap when the thread exits, or with
+/// `flush`.
+///
+/// The cache is opt-in: it only serves buffers allocated with its handle, or
+/// every buffer once installed with `set_backend`, and the default
+/// allocation path never goes through it. The `recycler` feature instead
+/// reuses freed blocks for every buffer, shared between threads.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, ThreadCache};
+///
+/// static CACHE: ThreadCache = ThreadCache;
+///
+/// # fn main() {
+/// let cached = unsafe { backend::register_backend("thread-cache", &CACHE) };
+/// # if cfg!(any(feature = "canaries", feature = "quarantine", feature = "recycler")) { return }
+///
+/// let scratch: UniqueBuf<u8> = UniqueBuf::allocate_in(100, cached);
+/// let address = *scratch;
+/// drop(scratch);
+///
+/// // The same size class reuses the cached block.
+/// let scratch: UniqueBuf<u8> = UniqueBuf::allocate_in(120, cached);
+/// assert_eq!(*scratch, address);
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct ThreadCache;
+
+/// The largest allocation, in bytes, which `ThreadCache` caches.
+pub const THREAD_CACHE_MAX_SIZE: usize = 32 * 1024;
+
+/// The most blocks `ThreadCache` keeps per size class on each thread.
+pub const THREAD_CACHE_BLOCKS: usize = 32;
+
+// The smallest size class, which is also the alignment of every cached
+// block.
+const THREAD_CACHE_MIN_SIZE: usize = 16;
+
+// Free blocks by size class, released to the heap when the thread exits.
+struct BlockCache {
+    classes: Vec<Vec<*mut u8>>
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        for (class, blocks) in self.classes.iter_mut().enumerate() {
+            for block in blocks.drain(..) {
+                unsafe { HEAP.deallocate(block, class_size(class), THREAD_CACHE_MIN_SIZE) }
+            }
+        }
+    }
+}
+
+thread_local!(static BLOCK_CACHE: RefCell<BlockCache> = RefCell::new(BlockCache {
+    classes: (0..size_class(THREAD_CACHE_MAX_SIZE, 1).unwrap() + 1).map(|_| Vec::new()).collect()
+}));
+
+// The size class of an allocation, if it is cached.
+fn size_class(size: usize, align: usize) -> Option<usize> {
+    if size > THREAD_CACHE_MAX_SIZE || align > THREAD_CACHE_MIN_SIZE { return None }
+
+    let size = cmp::max(size, THREAD_CACHE_MIN_SIZE).next_power_of_two();
+    Some((size.trailing_zeros() - THREAD_CACHE_MIN_SIZE.trailing_zeros()) as usize)
+}
+
+fn class_size(class: usize) -> usize {
+    THREAD_CACHE_MIN_SIZE << class
+}
+
+// Run `f` on the calling thread's cache, unless the thread is exiting and
+// the cache is gone.
+fn with_block_cache<F, R>(f: F) -> Option<R> where F: FnOnce(&mut BlockCache) -> R {
+    if BLOCK_CACHE.state() == LocalKeyState::Destroyed { return None }
+    Some(BLOCK_CACHE.with(|cache| f(&mut *cache.borrow_mut())))
+}
+
+impl ThreadCache {
+    /// Release every block cached by the calling thread to the heap.
+    pub fn flush(&self) {
+        with_block_cache(|cache| {
+            let classes = cache.classes.len();
+            drop(mem::replace(cache, BlockCache {
+                classes: (0..classes).map(|_| Vec::new()).collect()
+            }));
+        });
+    }
+
+    /// The number of bytes cached by the calling thread.
+    pub fn cached_bytes(&self) -> usize {
+        with_block_cache(|cache| {
+            cache.classes.iter().enumerate()
+                .fold(0, |total, (class, blocks)| total + blocks.len() * class_size(class))
+        }).unwrap_or(0)
+    }
+}
+
+impl Backend for ThreadCache {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        let class = match size_class(size, align) {
+            Some(class) => class,
+            None => return HEAP.allocate(size, align)
+        };
+
+        // The cache is gone while the thread is exiting.
+        match with_block_cache(|cache| cache.classes[class].pop()) {
+            Some(Some(block)) => block,
+            _ => HEAP.allocate(class_size(class), THREAD_CACHE_MIN_SIZE)
+        }
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        match (size_class(old_size, align), size_class(size, align)) {
+            (Some(old), Some(new)) if old == new => ptr,
+            (None, None) => HEAP.reallocate(ptr, old_size, size, align),
+            _ => {
+                let new = self.allocate(size, align);
+                if !new.is_null() {
+                    ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+                    self.deallocate(ptr, old_size, align);
+                }
+                new
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        let class = match size_class(old_size, align) {
+            Some(class) => class,
+            None => return HEAP.deallocate(ptr, old_size, align)
+        };
+
+        let cached = with_block_cache(|cache| {
+            let blocks = &mut cache.classes[class];
+            if blocks.len() < THREAD_CACHE_BLOCKS { blocks.push(ptr); true } else { false }
+        });
+
+        if cached != Some(true) {
+            HEAP.deallocate(ptr, class_size(class), THREAD_CACHE_MIN_SIZE)
+        }
+    }
+}
+
+#[cfg(all(feature = "quarantine", not(test)))]
+fn flush_quarantine() { ::quarantine::flush_quarantine() }
+
+#[cfg(all(feature = "quarantine", test))]
+fn flush_quarantine() {
+    let _lock = ::quarantine::TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    ::quarantine::flush_quarantine()
+}
+
+#[cfg(not(feature = "quarantine"))]
+fn flush_quarantine() {}
+
+#[cfg(feature = "recycler")]
+fn trim_recycler() { ::recycler::recycler().trim() }
+
+#[cfg(not(feature = "recycler"))]
+fn trim_recycler() {}
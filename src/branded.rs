@@ -0,0 +1,176 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::ops::{Index, IndexMut, Range};
+
+use SliceBuf;
+
+// A unique, invariant lifetime tying indices to the buffer they were checked
+// against.
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+/// The initialized elements of a `SliceBuf`, branded with a lifetime which
+/// is unique to one call of `SliceBuf::branded`.
+///
+/// Indices are checked against the buffer once, when a `BrandedIndex` is
+/// made, and can then be used any number of times without bounds checks.
+/// An index carries the brand of its buffer, so it cannot be used with any
+/// other buffer, and the length of a branded buffer never changes.
+///
+/// ```
+/// # use membuf::SliceBuf;
+///
+/// let mut buffer = SliceBuf::from_slice(&[3, 1, 2]);
+///
+/// let sum = buffer.branded(|mut buffer| {
+///     let first = buffer.index(0).unwrap();
+///     buffer[first] *= 10;
+///
+///     buffer.indices().fold(0, |sum, index| sum + buffer[index])
+/// });
+/// assert_eq!(sum, 33);
+/// ```
+///
+/// Indices from another buffer are rejected at compile time:
+///
+/// ```compile_fail
+/// # use membuf::SliceBuf;
+///
+/// let mut a = SliceBuf::from_slice(&[1, 2]);
+/// let mut b = SliceBuf::from_slice(&[3]);
+///
+/// a.branded(|a| b.branded(|b| {
+///     let index = a.index(1).unwrap();
+///     b[index]
+/// }));
+/// ```
+pub struct BrandedBuf<'id, 'a, T: 'a> {
+    slice: &'a mut [T],
+    _brand: Brand<'id>
+}
+
+/// An index which is known to be in bounds for the `BrandedBuf` with the
+/// same brand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BrandedIndex<'id> {
+    index: usize,
+    _brand: Brand<'id>
+}
+
+impl<T> SliceBuf<T> {
+    /// Call `f` with the initialized elements as a `BrandedBuf`, whose brand
+    /// is unique to this call.
+    pub fn branded<'a, F, R>(&'a mut self, f: F) -> R
+    where F: for<'id> FnOnce(BrandedBuf<'id, 'a, T>) -> R {
+        f(BrandedBuf { slice: self.as_mut_slice(), _brand: PhantomData })
+    }
+}
+
+impl<'id, 'a, T> BrandedBuf<'id, 'a, T> {
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.slice.len()
+    }
+
+    /// Check `index` against the length, returning a branded index if it is
+    /// in bounds.
+    pub fn index(&self, index: usize) -> Option<BrandedIndex<'id>> {
+        if index < self.len() { Some(BrandedIndex::new(index)) } else { None }
+    }
+
+    /// Iterate over every index of the buffer, in order.
+    pub fn indices(&self) -> BrandedIndices<'id> {
+        BrandedIndices { range: 0..self.len(), _brand: PhantomData }
+    }
+
+    /// Get a reference to the element at `index`, without a bounds check.
+    pub fn get(&self, index: BrandedIndex<'id>) -> &T {
+        unsafe { self.slice.get_unchecked(index.index) }
+    }
+
+    /// Get a mutable reference to the element at `index`, without a bounds
+    /// check.
+    pub fn get_mut(&mut self, index: BrandedIndex<'id>) -> &mut T {
+        unsafe { self.slice.get_unchecked_mut(index.index) }
+    }
+
+    /// Swap the elements at two indices, without bounds checks.
+    pub fn swap(&mut self, a: BrandedIndex<'id>, b: BrandedIndex<'id>) {
+        unsafe {
+            let base = self.slice.as_mut_ptr();
+            ptr::swap(base.offset(a.index as isize), base.offset(b.index as isize))
+        }
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        self.slice
+    }
+}
+
+impl<'id, 'a, T> Index<BrandedIndex<'id>> for BrandedBuf<'id, 'a, T> {
+    type Output = T;
+
+    fn index(&self, index: BrandedIndex<'id>) -> &T { self.get(index) }
+}
+
+impl<'id, 'a, T> IndexMut<BrandedIndex<'id>> for BrandedBuf<'id, 'a, T> {
+    fn index_mut(&mut self, index: BrandedIndex<'id>) -> &mut T { self.get_mut(index) }
+}
+
+impl<'id> BrandedIndex<'id> {
+    fn new(index: usize) -> BrandedIndex<'id> {
+        BrandedIndex { index: index, _brand: PhantomData }
+    }
+
+    /// Get the index as a plain number.
+    pub fn get(&self) -> usize {
+        self.index
+    }
+}
+
+/// An iterator over the indices of a `BrandedBuf`, from
+/// `BrandedBuf::indices`.
+pub struct BrandedIndices<'id> {
+    range: Range<usize>,
+    _brand: Brand<'id>
+}
+
+impl<'id> Iterator for BrandedIndices<'id> {
+    type Item = BrandedIndex<'id>;
+
+    fn next(&mut self) -> Option<BrandedIndex<'id>> {
+        self.range.next().map(BrandedIndex::new)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'id> DoubleEndedIterator for BrandedIndices<'id> {
+    fn next_back(&mut self) -> Option<BrandedIndex<'id>> {
+        self.range.next_back().map(BrandedIndex::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use SliceBuf;
+
+    #[test]
+    fn test_branded_reverse() {
+        let mut buffer = SliceBuf::from_slice(&[1, 2, 3, 4, 5]);
+
+        buffer.branded(|mut buffer| {
+            let mut indices = buffer.indices();
+            while let (Some(front), Some(back)) = (indices.next(), indices.next_back()) {
+                buffer.swap(front, back);
+            }
+
+            assert!(buffer.index(5).is_none());
+            assert_eq!(buffer.index(4).map(|index| index.get()), Some(4));
+        });
+
+        assert_eq!(&*buffer, &[5, 4, 3, 2, 1]);
+    }
+}
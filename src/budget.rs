@@ -0,0 +1,370 @@
+//! Memory budget enforcement.
+//!
+//! With the `budget` feature enabled, `set_budget` caps the number of live
+//! bytes allocated through `alloc`. An allocation which would take the live
+//! bytes over the budget is refused: the fallible functions such as
+//! `MemBuf::try_allocate` return `AllocError::OutOfMemory`, and the others
+//! abort as if the backend had run out of memory. A policy callback set with
+//! `set_budget_policy` is invoked first, and may panic instead, for instance
+//! to unwind out of untrusted code.
+//!
+//! The budget is checked against the `stats` counters before allocating, so
+//! threads allocating concurrently may overshoot it slightly.
+//!
+//! A `QuotaGuard` additionally limits the bytes the current thread may
+//! allocate while it is alive, so one worker cannot starve the rest.
+//!
+//! A soft limit set with `set_soft_limit` never refuses allocations, but
+//! fires a callback when the live bytes cross it, so the application can
+//! shed caches before reaching the budget.
+//!
+//! Without the feature every function in here is a no-op.
+
+#[cfg(feature = "budget")]
+use std::cell::Cell;
+#[cfg(feature = "budget")]
+use std::marker::PhantomData;
+#[cfg(feature = "budget")]
+use std::mem;
+#[cfg(feature = "budget")]
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
+#[cfg(feature = "budget")]
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use alloc::AllocError;
+#[cfg(feature = "budget")]
+use stats;
+
+#[cfg(feature = "budget")]
+static BUDGET: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "budget")]
+static BUDGETED: AtomicBool = ATOMIC_BOOL_INIT;
+#[cfg(feature = "budget")]
+static POLICY: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "budget")]
+static SOFT_LIMIT: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "budget")]
+static SOFT_LIMITED: AtomicBool = ATOMIC_BOOL_INIT;
+#[cfg(feature = "budget")]
+static SOFT_CALLBACK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Refuse allocations which would take the number of live bytes allocated
+/// through `alloc` over `bytes`.
+///
+/// Only available with the `budget` feature. Allocations which are already
+/// live are unaffected, even if they exceed the new budget.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+///
+/// membuf::set_budget(membuf::stats().live_bytes + 1024);
+/// assert!(UniqueBuf::<u8>::try_allocate(512).is_ok());
+/// assert!(UniqueBuf::<u8>::try_allocate(4096).is_err());
+///
+/// membuf::clear_budget();
+/// assert!(UniqueBuf::<u8>::try_allocate(4096).is_ok());
+/// ```
+#[cfg(feature = "budget")]
+pub fn set_budget(bytes: usize) {
+    BUDGET.store(bytes, Ordering::SeqCst);
+    BUDGETED.store(true, Ordering::SeqCst);
+}
+
+/// Remove the budget set with `set_budget`.
+///
+/// Only available with the `budget` feature.
+#[cfg(feature = "budget")]
+pub fn clear_budget() {
+    BUDGETED.store(false, Ordering::SeqCst);
+}
+
+/// Get the budget set with `set_budget`, if any.
+///
+/// Only available with the `budget` feature.
+#[cfg(feature = "budget")]
+pub fn budget() -> Option<usize> {
+    if BUDGETED.load(Ordering::SeqCst) {
+        Some(BUDGET.load(Ordering::SeqCst))
+    } else {
+        None
+    }
+}
+
+/// Set a callback invoked with the size of the request and the number of
+/// live bytes whenever an allocation is refused for exceeding the budget.
+///
+/// Only available with the `budget` feature. When the allocation exceeds a
+/// `QuotaGuard` rather than the global budget, the callback receives the
+/// bytes used against the quota instead of the live bytes. The allocation
+/// still fails if the callback returns.
+///
+/// ```should_panic
+/// # use membuf::UniqueBuf;
+///
+/// fn policy(requested: usize, live: usize) {
+///     panic!("Refused to allocate {} bytes with {} bytes live", requested, live)
+/// }
+///
+/// membuf::set_budget_policy(policy);
+/// membuf::set_budget(0);
+/// let _: UniqueBuf<u8> = UniqueBuf::allocate(16);
+/// ```
+#[cfg(feature = "budget")]
+pub fn set_budget_policy(policy: fn(usize, usize)) {
+    POLICY.store(policy as usize, Ordering::SeqCst);
+}
+
+/// Invoke `callback` whenever an allocation takes the number of live bytes
+/// allocated through `alloc` from below `bytes` to `bytes` or more.
+///
+/// Only available with the `budget` feature. The callback receives the live
+/// bytes and the size of the allocation which crossed the limit, and runs
+/// before that allocation is made, so memory it frees is available to it.
+/// Allocations made by the callback itself can fire it again.
+///
+/// ```
+/// use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+/// # use membuf::UniqueBuf;
+///
+/// static FIRED: AtomicBool = ATOMIC_BOOL_INIT;
+///
+/// fn shed_caches(_live: usize, _requested: usize) {
+///     FIRED.store(true, Ordering::SeqCst);
+/// }
+///
+/// membuf::set_soft_limit(membuf::stats().live_bytes + 1024, shed_caches);
+/// let _small: UniqueBuf<u8> = UniqueBuf::allocate(512);
+/// assert!(!FIRED.load(Ordering::SeqCst));
+///
+/// let _large: UniqueBuf<u8> = UniqueBuf::allocate(1024);
+/// assert!(FIRED.load(Ordering::SeqCst));
+/// # membuf::clear_soft_limit();
+/// ```
+#[cfg(feature = "budget")]
+pub fn set_soft_limit(bytes: usize, callback: fn(usize, usize)) {
+    SOFT_CALLBACK.store(callback as usize, Ordering::SeqCst);
+    SOFT_LIMIT.store(bytes, Ordering::SeqCst);
+    SOFT_LIMITED.store(true, Ordering::SeqCst);
+}
+
+/// Remove the soft limit set with `set_soft_limit`.
+///
+/// Only available with the `budget` feature.
+#[cfg(feature = "budget")]
+pub fn clear_soft_limit() {
+    SOFT_LIMITED.store(false, Ordering::SeqCst);
+}
+
+/// A limit on the bytes the current thread may allocate through `alloc`,
+/// which is lifted when the guard is dropped.
+///
+/// Only available with the `budget` feature. The quota counts the bytes
+/// allocated by the thread while the guard is alive, minus those it frees
+/// again, and allocations beyond it are refused just like allocations
+/// beyond the global budget. Guards nest: the bytes used within an inner
+/// guard also count towards the outer one once it is dropped.
+///
+/// Freed bytes are returned to the quota of the thread which frees them, not
+/// the one which allocated them, as no record is kept of which thread made
+/// an allocation. A buffer sent to another thread and freed there stays
+/// counted against the quota it was allocated under, and instead lowers the
+/// bytes used against the freeing thread's quota, which never goes below
+/// zero.
+///
+/// ```
+/// use membuf::{QuotaGuard, UniqueBuf};
+///
+/// let quota = QuotaGuard::new(1024);
+///
+/// let first = UniqueBuf::<u8>::try_allocate(768).unwrap();
+/// assert_eq!(quota.used(), 768);
+/// assert!(UniqueBuf::<u8>::try_allocate(512).is_err());
+///
+/// drop(first);
+/// assert!(UniqueBuf::<u8>::try_allocate(512).is_ok());
+/// ```
+#[cfg(feature = "budget")]
+pub struct QuotaGuard {
+    previous: Option<Quota>,
+    // Quotas are per-thread, so the guard must be dropped on its own thread.
+    _marker: PhantomData<*mut ()>
+}
+
+#[cfg(feature = "budget")]
+#[derive(Copy, Clone)]
+struct Quota {
+    limit: usize,
+    used: usize
+}
+
+#[cfg(feature = "budget")]
+thread_local!(static QUOTA: Cell<Option<Quota>> = Cell::new(None));
+
+#[cfg(feature = "budget")]
+impl QuotaGuard {
+    /// Limit the current thread to allocating `limit` bytes until the guard
+    /// is dropped.
+    pub fn new(limit: usize) -> QuotaGuard {
+        let previous = QUOTA.with(|cell| {
+            let previous = cell.get();
+            cell.set(Some(Quota { limit: limit, used: 0 }));
+            previous
+        });
+
+        QuotaGuard { previous: previous, _marker: PhantomData }
+    }
+
+    /// The number of bytes the current thread has allocated against this
+    /// quota.
+    pub fn used(&self) -> usize {
+        QUOTA.with(|cell| cell.get().map(|quota| quota.used).unwrap_or(0))
+    }
+}
+
+#[cfg(feature = "budget")]
+impl Drop for QuotaGuard {
+    fn drop(&mut self) {
+        // Bytes used within this guard count towards the enclosing one.
+        let used = self.used();
+        let previous = self.previous.map(|quota| Quota { used: quota.used + used, ..quota });
+        QUOTA.with(|cell| cell.set(previous));
+    }
+}
+
+/// Check that `size` more bytes can be allocated without exceeding the
+/// budget or the current thread's quota, firing the soft limit callback if
+/// they cross the soft limit.
+#[cfg(feature = "budget")]
+pub fn admit(size: usize) -> Result<(), AllocError> {
+    if size == 0 { return Ok(()) }
+
+    if let Some(quota) = QUOTA.with(|cell| cell.get()) {
+        if quota.used.saturating_add(size) > quota.limit {
+            return refuse(size, quota.used)
+        }
+    }
+
+    if SOFT_LIMITED.load(Ordering::SeqCst) {
+        let live = stats::stats().live_bytes;
+        let limit = SOFT_LIMIT.load(Ordering::SeqCst);
+
+        if live < limit && live.saturating_add(size) >= limit {
+            let callback: fn(usize, usize) =
+                unsafe { mem::transmute(SOFT_CALLBACK.load(Ordering::SeqCst)) };
+            callback(live, size);
+        }
+    }
+
+    if !BUDGETED.load(Ordering::SeqCst) { return Ok(()) }
+
+    let live = stats::stats().live_bytes;
+    match live.checked_add(size) {
+        Some(total) if total <= BUDGET.load(Ordering::SeqCst) => Ok(()),
+        _ => refuse(size, live)
+    }
+}
+
+/// Count `size` newly allocated bytes against the current thread's quota.
+#[cfg(feature = "budget")]
+pub fn charge(size: usize) {
+    QUOTA.with(|cell| {
+        if let Some(quota) = cell.get() {
+            cell.set(Some(Quota { used: quota.used + size, ..quota }))
+        }
+    })
+}
+
+/// Return `size` freed bytes to the current thread's quota.
+#[cfg(feature = "budget")]
+pub fn refund(size: usize) {
+    QUOTA.with(|cell| {
+        if let Some(quota) = cell.get() {
+            cell.set(Some(Quota { used: quota.used.saturating_sub(size), ..quota }))
+        }
+    })
+}
+
+#[cfg(feature = "budget")]
+fn refuse(size: usize, live: usize) -> Result<(), AllocError> {
+    match POLICY.load(Ordering::SeqCst) {
+        0 => (),
+        policy => {
+            let policy: fn(usize, usize) = unsafe { mem::transmute(policy) };
+            policy(size, live)
+        }
+    }
+
+    Err(AllocError::OutOfMemory)
+}
+
+/// Check that `size` more bytes can be allocated without exceeding the
+/// budget or the current thread's quota.
+#[cfg(not(feature = "budget"))]
+#[inline(always)]
+pub fn admit(_: usize) -> Result<(), AllocError> { Ok(()) }
+
+/// Count `size` newly allocated bytes against the current thread's quota.
+#[cfg(not(feature = "budget"))]
+#[inline(always)]
+pub fn charge(_: usize) {}
+
+/// Return `size` freed bytes to the current thread's quota.
+#[cfg(not(feature = "budget"))]
+#[inline(always)]
+pub fn refund(_: usize) {}
+
+#[cfg(all(test, feature = "budget"))]
+mod test {
+    use super::QuotaGuard;
+    use UniqueBuf;
+
+    #[test]
+    fn test_nested_quotas() {
+        let outer = QuotaGuard::new(4096);
+        let first: UniqueBuf<u8> = UniqueBuf::allocate(1024);
+
+        let second = {
+            let inner = QuotaGuard::new(2048);
+            let second: UniqueBuf<u8> = UniqueBuf::allocate(2048);
+            assert_eq!(inner.used(), 2048);
+            assert!(UniqueBuf::<u8>::try_allocate(1).is_err());
+            second
+        };
+
+        assert_eq!(outer.used(), 3072);
+        assert!(UniqueBuf::<u8>::try_allocate(2048).is_err());
+
+        drop(first);
+        drop(second);
+        assert_eq!(outer.used(), 0);
+    }
+
+    #[test]
+    fn test_cross_thread_free_refunds_freeing_thread() {
+        let quota = QuotaGuard::new(4096);
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(1024);
+
+        let used = ::std::thread::spawn(move || {
+            let quota = QuotaGuard::new(4096);
+            let own: UniqueBuf<u8> = UniqueBuf::allocate(2048);
+            drop(buffer);
+            let used = quota.used();
+            drop(own);
+            used
+        }).join().unwrap();
+
+        assert_eq!(used, 1024);
+        assert_eq!(quota.used(), 1024);
+    }
+
+    #[test]
+    fn test_quota_is_per_thread() {
+        let _quota = QuotaGuard::new(0);
+
+        ::std::thread::spawn(|| {
+            let _: UniqueBuf<u8> = UniqueBuf::allocate(1024);
+        }).join().unwrap();
+
+        assert!(UniqueBuf::<u8>::try_allocate(1).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+
+use {AlignedBuf, UniqueBuf};
+use backend::{BackendHandle, DEFAULT_BACKEND};
+#[cfg(unix)]
+use {os, PageBuf};
+
+/// Where a `MemBufBuilder` takes its memory from.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Placement {
+    /// The heap, through `alloc`, producing a `UniqueBuf`.
+    Heap,
+    /// Pages mapped directly from the operating system, producing a `PageBuf`.
+    #[cfg(unix)]
+    Mmap
+}
+
+/// A buffer built by `MemBufBuilder`, whose type depends on its placement.
+#[derive(Debug)]
+pub enum AnyBuf<T> {
+    /// A buffer allocated on the heap.
+    Heap(UniqueBuf<T>),
+    /// A buffer allocated on the heap with more than the natural alignment
+    /// of T.
+    Aligned(AlignedBuf<T>),
+    /// A buffer mapped from the operating system.
+    #[cfg(unix)]
+    Mmap(PageBuf<T>)
+}
+
+impl<T> AnyBuf<T> {
+    /// Get the capacity of the buffer.
+    pub fn capacity(&self) -> usize {
+        match *self {
+            AnyBuf::Heap(ref buffer) => buffer.capacity(),
+            AnyBuf::Aligned(ref buffer) => buffer.capacity(),
+            #[cfg(unix)]
+            AnyBuf::Mmap(ref buffer) => buffer.capacity()
+        }
+    }
+
+    /// Unwrap a heap buffer of the natural alignment of T.
+    ///
+    /// Panics if the buffer was built with more than the natural alignment of
+    /// T, and so is an `AnyBuf::Aligned`, or was mapped from the operating
+    /// system.
+    pub fn into_unique(self) -> UniqueBuf<T> {
+        match self {
+            AnyBuf::Heap(buffer) => buffer,
+            AnyBuf::Aligned(_) => panic!("AnyBuf::into_unique called on an over-aligned buffer"),
+            #[cfg(unix)]
+            AnyBuf::Mmap(_) => panic!("AnyBuf::into_unique called on a mapped buffer")
+        }
+    }
+}
+
+impl<T> Deref for AnyBuf<T> {
+    type Target = *mut T;
+
+    fn deref(&self) -> &*mut T {
+        match *self {
+            AnyBuf::Heap(ref buffer) => &**buffer,
+            AnyBuf::Aligned(ref buffer) => &**buffer,
+            #[cfg(unix)]
+            AnyBuf::Mmap(ref buffer) => &**buffer
+        }
+    }
+}
+
+/// A builder combining the allocation options of the various buffers.
+///
+/// Every option is independent, so new ones do not multiply the number of
+/// constructors:
+///
+/// ```
+/// use membuf::{MemBufBuilder, Placement};
+///
+/// let buffer = MemBufBuilder::<u64>::new()
+///     .capacity(512)
+///     .zeroed(true)
+///     .tag("parser")
+///     .build()
+///     .into_unique();
+///
+/// assert_eq!(buffer.capacity(), 512);
+/// assert_eq!(unsafe { buffer.as_slice(512) }, &[0; 512][..]);
+///
+/// # #[cfg(unix)] {
+/// let pages = MemBufBuilder::<u8>::new()
+///     .capacity(100)
+///     .align(4096)
+///     .placement(Placement::Mmap)
+///     .build();
+/// assert_eq!(*pages as usize % 4096, 0);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MemBufBuilder<T> {
+    cap: usize,
+    align: usize,
+    zeroed: bool,
+    placement: Placement,
+    backend: BackendHandle,
+    tag: Option<&'static str>,
+    _marker: PhantomData<T>
+}
+
+impl<T> MemBufBuilder<T> {
+    /// Create a builder for an empty heap buffer with the natural alignment
+    /// of T.
+    pub fn new() -> MemBufBuilder<T> {
+        MemBufBuilder {
+            cap: 0,
+            align: mem::align_of::<T>(),
+            zeroed: false,
+            placement: Placement::Heap,
+            backend: DEFAULT_BACKEND,
+            tag: None,
+            _marker: PhantomData
+        }
+    }
+
+    /// Set the number of Ts the buffer should have space for.
+    pub fn capacity(mut self, cap: usize) -> MemBufBuilder<T> {
+        self.cap = cap;
+        self
+    }
+
+    /// Require the buffer to be aligned to `align` bytes, which must be a
+    /// power of two.
+    ///
+    /// Heap buffers aligned beyond the alignment of T are built as an
+    /// `AlignedBuf`. Mapped buffers are aligned to a page, so `build` panics
+    /// if they are asked for more.
+    pub fn align(mut self, align: usize) -> MemBufBuilder<T> {
+        assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+        self.align = align;
+        self
+    }
+
+    /// Set whether the memory of the buffer should be zeroed.
+    ///
+    /// Mapped buffers are always zeroed.
+    pub fn zeroed(mut self, zeroed: bool) -> MemBufBuilder<T> {
+        self.zeroed = zeroed;
+        self
+    }
+
+    /// Set where the memory of the buffer comes from.
+    pub fn placement(mut self, placement: Placement) -> MemBufBuilder<T> {
+        self.placement = placement;
+        self
+    }
+
+    /// Set the backend heap buffers are allocated from.
+    ///
+    /// Mapped buffers always come directly from the operating system.
+    pub fn backend(mut self, backend: BackendHandle) -> MemBufBuilder<T> {
+        self.backend = backend;
+        self
+    }
+
+    /// Count the memory of the buffer towards `tag` in `stats_by_tag`.
+    ///
+    /// Only heap buffers are counted, so `build` panics if a mapped buffer
+    /// is tagged.
+    pub fn tag(mut self, tag: &'static str) -> MemBufBuilder<T> {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Allocate the buffer.
+    pub fn build(self) -> AnyBuf<T> {
+        match self.placement {
+            Placement::Heap => {
+                if self.align > mem::align_of::<T>() {
+                    let mut buffer: AlignedBuf<T> = match self.tag {
+                        Some(tag) => AlignedBuf::allocate_tagged_in(self.cap, self.align,
+                                                                    self.backend, tag),
+                        None => AlignedBuf::allocate_in(self.cap, self.align, self.backend)
+                    };
+                    if self.zeroed { buffer.fill_bytes(0) }
+                    return AnyBuf::Aligned(buffer)
+                }
+
+                let mut buffer: UniqueBuf<T> = match self.tag {
+                    Some(tag) => UniqueBuf::allocate_tagged_in(self.cap, self.backend, tag),
+                    None => UniqueBuf::allocate_in(self.cap, self.backend)
+                };
+                if self.zeroed { buffer.fill_bytes(0) }
+                AnyBuf::Heap(buffer)
+            },
+            // Fresh mappings are always zeroed.
+            #[cfg(unix)]
+            Placement::Mmap => {
+                self.check_align(os::page_size(), "mmap");
+                if self.tag.is_some() { panic!("Tags are not supported by the mmap placement") }
+                AnyBuf::Mmap(PageBuf::allocate(self.cap))
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn check_align(&self, supported: usize, placement: &str) {
+        if self.align > supported {
+            panic!("Alignment {} is not supported by the {} placement", self.align, placement)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use MemBufBuilder;
+    #[cfg(unix)]
+    use Placement;
+
+    #[test]
+    fn test_over_aligned_heap_buffer() {
+        let buffer = MemBufBuilder::<u8>::new().capacity(8).align(64).zeroed(true).build();
+
+        assert_eq!(*buffer as usize % 64, 0);
+        assert_eq!(buffer.capacity(), 8);
+        assert_eq!(unsafe { *buffer.offset(7) }, 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[should_panic = "Tags are not supported by the mmap placement"]
+    fn test_tagged_mapping() {
+        MemBufBuilder::<u8>::new().capacity(8).tag("test").placement(Placement::Mmap).build();
+    }
+}
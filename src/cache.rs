@@ -0,0 +1,147 @@
+//! Cache control: streaming stores which bypass the cache, and prefetches.
+//!
+//! Filling a large buffer which will not be read again soon through the
+//! cache evicts everything else from it. On x86 the copy is done with
+//! non-temporal stores instead, followed by a store fence so that the data is
+//! visible to other threads once the copy returns. Elsewhere this falls back
+//! to an ordinary `memcpy`.
+//!
+//! Prefetches are only hints, and compile to nothing on targets without them.
+
+use std::{cmp, mem, ptr};
+
+use {registry, UniqueBuf};
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+extern {
+    #[link_name = "llvm.x86.sse2.movnt.i"]
+    fn movnt_i32(ptr: *mut i32, value: i32);
+    #[link_name = "llvm.x86.sse.sfence"]
+    fn sfence();
+}
+
+extern {
+    #[link_name = "llvm.prefetch"]
+    fn prefetch(address: *const i8, rw: i32, locality: i32, cache_type: i32);
+}
+
+// Arguments to `llvm.prefetch`.
+const READ: i32 = 0;
+const WRITE: i32 = 1;
+const HIGH_LOCALITY: i32 = 3;
+const DATA_CACHE: i32 = 1;
+
+/// Copy `size` bytes from `src` to `dst` without polluting the cache.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn copy_nontemporal(src: *const u8, dst: *mut u8, size: usize) {
+    // Streaming stores must be aligned, so copy up to the first word boundary
+    // of the destination normally.
+    let head = cmp::min((4 - dst as usize % 4) % 4, size);
+    ptr::copy_nonoverlapping(src, dst, head);
+
+    let words = (size - head) / 4;
+    for i in 0..words {
+        let offset = (head + i * 4) as isize;
+        let mut word: i32 = 0;
+        ptr::copy_nonoverlapping(src.offset(offset), &mut word as *mut i32 as *mut u8, 4);
+        movnt_i32(dst.offset(offset) as *mut i32, word);
+    }
+
+    let tail = head + words * 4;
+    ptr::copy_nonoverlapping(src.offset(tail as isize), dst.offset(tail as isize), size - tail);
+    sfence();
+}
+
+/// Copy `size` bytes from `src` to `dst` without polluting the cache.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+unsafe fn copy_nontemporal(src: *const u8, dst: *mut u8, size: usize) {
+    ptr::copy_nonoverlapping(src, dst, size)
+}
+
+impl<T> UniqueBuf<T> {
+    /// Copy `count` elements from `src` to the start of this buffer, with
+    /// streaming stores which bypass the cache where supported.
+    ///
+    /// This is useful for filling large buffers which will not be read again
+    /// soon. Like `copy_from`, this is a bitwise `memcpy`.
+    ///
+    /// ## Safety
+    ///
+    /// `count` must be at most the capacity, which is checked in debug
+    /// builds, `src` must be valid for reads of `count` elements and must not
+    /// overlap this buffer. Any previous contents of this buffer are
+    /// overwritten without being dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let source: Vec<u16> = (0..1000).collect();
+    /// let mut buffer: UniqueBuf<u16> = UniqueBuf::allocate(1000);
+    ///
+    /// unsafe {
+    ///     buffer.copy_from_nontemporal(source.as_ptr(), 1000);
+    ///     assert_eq!(buffer.as_slice(1000), &source[..]);
+    /// }
+    /// ```
+    pub unsafe fn copy_from_nontemporal(&mut self, src: *const T, count: usize) {
+        debug_assert!(count <= self.capacity());
+
+        let size = count * mem::size_of::<T>();
+        copy_nontemporal(src as *const u8, **self as *mut u8, size);
+
+        if registry::is_initialized(src as *mut u8, 0, size) {
+            registry::mark_initialized(**self as *mut u8, 0, size);
+        }
+    }
+
+    /// Hint that the element at `index` will soon be read, so the cache line
+    /// containing it should be fetched.
+    ///
+    /// Panics if `index` is not less than the capacity of the buffer.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u64> = UniqueBuf::allocate(1024);
+    /// buffer.prefetch_read(512);
+    /// ```
+    pub fn prefetch_read(&self, index: usize) {
+        let ptr = self.ptr_at(index);
+        unsafe { prefetch(ptr as *const i8, READ, HIGH_LOCALITY, DATA_CACHE) }
+    }
+
+    /// Hint that the element at `index` will soon be written, so the cache
+    /// line containing it should be fetched.
+    ///
+    /// Panics if `index` is not less than the capacity of the buffer.
+    pub fn prefetch_write(&self, index: usize) {
+        let ptr = self.ptr_at(index);
+        unsafe { prefetch(ptr as *const i8, WRITE, HIGH_LOCALITY, DATA_CACHE) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use UniqueBuf;
+
+    #[test]
+    fn test_copy_nontemporal_unaligned() {
+        let source: Vec<u8> = (0..255).collect();
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(255);
+
+        for start in 0..8 {
+            for len in 0..20 {
+                buffer.fill_bytes(0);
+
+                unsafe {
+                    let dst = buffer.offset(start as isize);
+                    super::copy_nontemporal(source[start..].as_ptr(), dst, len);
+
+                    let copied = &buffer.as_slice(255)[start..start + len];
+                    assert_eq!(copied, &source[start..start + len]);
+                    assert!(buffer.as_slice(255)[start + len..].iter().all(|&b| b == 0));
+                }
+            }
+        }
+    }
+}
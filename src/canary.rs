@@ -0,0 +1,76 @@
+//! Canary words surrounding heap buffers.
+//!
+//! With the `canaries` feature enabled, every heap allocation is padded with
+//! a canary word directly before and directly after the usable region. The
+//! canaries are checked whenever the buffer is reallocated or deallocated,
+//! and can be checked at any time with `MemBuf::verify_canaries`, so writes
+//! which stray just outside of a buffer are caught close to their source.
+//!
+//! Without the feature every function in here is a no-op.
+
+use std::{mem, ptr};
+
+/// The value written to both canary words.
+pub const CANARY: u64 = 0x5ca1_ab1e_feed_f00d;
+
+const CANARY_SIZE: usize = 8;
+
+/// Is the `canaries` feature enabled?
+pub fn enabled() -> bool { cfg!(feature = "canaries") }
+
+/// The number of bytes before the usable region, including the canary.
+fn padding(align: usize) -> usize {
+    if !enabled() { return 0 }
+    (CANARY_SIZE + align - 1) & !(align - 1)
+}
+
+/// The size of the underlying allocation for a usable region of `size` bytes.
+pub fn padded_size(size: usize, align: usize) -> usize {
+    if !enabled() { return size }
+
+    size.checked_add(padding(align) + CANARY_SIZE).expect("Capacity overflow")
+}
+
+/// Write the canaries into an allocation of `padded_size(size, align)` bytes
+/// and return a pointer to its usable region.
+pub unsafe fn install(base: *mut u8, size: usize, align: usize) -> *mut u8 {
+    if !enabled() { return base }
+
+    let ptr = base.offset(padding(align) as isize);
+    write_canary(ptr.offset(-(CANARY_SIZE as isize)));
+    write_canary(ptr.offset(size as isize));
+    ptr
+}
+
+/// Get the start of the allocation containing the usable region at `ptr`.
+pub unsafe fn base(ptr: *mut u8, align: usize) -> *mut u8 {
+    ptr.offset(-(padding(align) as isize))
+}
+
+/// Check whether the canaries around the usable region at `ptr` are intact.
+pub unsafe fn intact(ptr: *mut u8, size: usize) -> bool {
+    if !enabled() { return true }
+
+    read_canary(ptr.offset(-(CANARY_SIZE as isize))) == CANARY
+        && read_canary(ptr.offset(size as isize)) == CANARY
+}
+
+/// Panic if the canaries around the usable region at `ptr` are not intact.
+pub unsafe fn verify(ptr: *mut u8, size: usize) {
+    if !intact(ptr, size) {
+        panic!("Canary overwritten, buffer at {:p} of {} bytes was overrun", ptr, size)
+    }
+}
+
+// The trailing canary is not necessarily aligned, so canaries are always
+// accessed bytewise.
+unsafe fn write_canary(dst: *mut u8) {
+    ptr::copy_nonoverlapping(&CANARY as *const u64 as *const u8, dst, CANARY_SIZE);
+}
+
+unsafe fn read_canary(src: *mut u8) -> u64 {
+    let mut canary: u64 = 0;
+    ptr::copy_nonoverlapping(src, &mut canary as *mut u64 as *mut u8,
+                             mem::size_of::<u64>());
+    canary
+}
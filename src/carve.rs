@@ -0,0 +1,114 @@
+use core::nonzero::NonZero;
+
+use {MemBuf, UniqueBuf};
+
+/// A single allocation carved up into many fixed-size regions.
+///
+/// Allocating thousands of small buffers individually, for instance the nodes
+/// of a tree, spends most of its time in the allocator. A `CarvedBuf` makes
+/// one allocation big enough for all of them, and hands out each region as a
+/// `MemBuf<T>` with `carve`. The whole allocation is freed at once when the
+/// `CarvedBuf` is dropped.
+///
+/// The regions do not own their memory: they must not be reallocated or
+/// deallocated, and must not be used after the `CarvedBuf` is dropped.
+///
+/// ```
+/// # use membuf::CarvedBuf;
+///
+/// let mut nodes: CarvedBuf<u64> = CarvedBuf::allocate(16, 3);
+///
+/// let first = nodes.carve().unwrap();
+/// let second = nodes.carve().unwrap();
+/// assert_eq!(first.capacity(), 16);
+/// assert_eq!(unsafe { first.offset(16) }, *second);
+///
+/// nodes.carve().unwrap();
+/// assert!(nodes.carve().is_none());
+/// ```
+#[derive(Debug)]
+pub struct CarvedBuf<T> {
+    buffer: UniqueBuf<T>,
+    region_cap: usize,
+    regions: usize,
+    carved: usize
+}
+
+impl<T> CarvedBuf<T> {
+    /// Make a single allocation with room for `regions` regions of
+    /// `region_cap` Ts each.
+    pub fn allocate(region_cap: usize, regions: usize) -> CarvedBuf<T> {
+        let cap = region_cap.checked_mul(regions).expect("Capacity overflow.");
+
+        CarvedBuf {
+            buffer: UniqueBuf::allocate(cap),
+            region_cap: region_cap,
+            regions: regions,
+            carved: 0
+        }
+    }
+
+    /// Carve the next region out of the allocation, or return None if every
+    /// region has already been handed out.
+    pub fn carve(&mut self) -> Option<MemBuf<T>> {
+        if self.carved == self.regions { return None }
+
+        let offset = self.carved * self.region_cap;
+        self.carved += 1;
+
+        unsafe {
+            let ptr = self.buffer.offset(offset as isize);
+            Some(MemBuf::from_raw(NonZero::new(ptr), self.region_cap))
+        }
+    }
+
+    /// The capacity of every region.
+    pub fn region_capacity(&self) -> usize {
+        self.region_cap
+    }
+
+    /// The number of regions which have not been carved yet.
+    ///
+    /// ```
+    /// # use membuf::CarvedBuf;
+    ///
+    /// let mut buffer: CarvedBuf<u8> = CarvedBuf::allocate(4, 2);
+    /// assert_eq!(buffer.remaining(), 2);
+    ///
+    /// buffer.carve();
+    /// assert_eq!(buffer.remaining(), 1);
+    /// ```
+    pub fn remaining(&self) -> usize {
+        self.regions - self.carved
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::usize;
+    use CarvedBuf;
+
+    #[test]
+    fn test_regions_are_disjoint() {
+        let mut buffer: CarvedBuf<u32> = CarvedBuf::allocate(4, 8);
+        let regions: Vec<_> = (0..8).map(|_| buffer.carve().unwrap()).collect();
+
+        unsafe {
+            for (i, region) in regions.iter().enumerate() {
+                for j in 0..4 { region.write_at(j, (i * 4 + j) as u32); }
+            }
+
+            for (i, region) in regions.iter().enumerate() {
+                for j in 0..4 { assert_eq!(region.read_at(j), (i * 4 + j) as u32); }
+            }
+        }
+
+        assert!(buffer.carve().is_none());
+    }
+
+    #[test]
+    #[should_panic = "Capacity overflow."]
+    fn test_capacity_overflow() {
+        let _: CarvedBuf<u8> = CarvedBuf::allocate(usize::MAX, 2);
+    }
+}
@@ -0,0 +1,128 @@
+use core::nonzero::NonZero;
+use std::error::Error;
+use std::{fmt, mem};
+
+use alloc::empty;
+use MemBuf;
+
+/// The reason a buffer could not be cast to another element type.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum CastError {
+    /// The buffer is not sufficiently aligned for the new element type.
+    Misaligned,
+    /// The size of the buffer in bytes is not a multiple of the size of the
+    /// new element type.
+    SizeMismatch
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for CastError {
+    fn description(&self) -> &str {
+        match *self {
+            CastError::Misaligned => "Buffer is misaligned for the new element type",
+            CastError::SizeMismatch => "Buffer size is not a multiple of the new element size"
+        }
+    }
+}
+
+impl<T> MemBuf<T> {
+    /// View the memory of this buffer as a buffer of Us, recomputing the
+    /// capacity from the size of the buffer in bytes.
+    ///
+    /// Fails if the buffer is not aligned for `U`, or if its size is not a
+    /// multiple of the size of `U`. Empty buffers can always be cast.
+    ///
+    /// The allocator must be given the alignment the memory was allocated
+    /// with, so if `T` and `U` have different alignments the returned buffer
+    /// must be cast back to `T` before it is reallocated or deallocated.
+    ///
+    /// ```
+    /// # use membuf::{MemBuf, CastError};
+    ///
+    /// let words: MemBuf<u32> = MemBuf::allocate(6);
+    ///
+    /// let halves: MemBuf<u16> = words.cast().unwrap();
+    /// assert_eq!(halves.capacity(), 12);
+    /// assert_eq!(words.cast::<[u8; 5]>(), Err(CastError::SizeMismatch));
+    ///
+    /// unsafe { halves.cast::<u32>().unwrap().deallocate() }
+    /// ```
+    pub fn cast<U>(self) -> Result<MemBuf<U>, CastError> {
+        let (old_size, new_size) = (mem::size_of::<T>(), mem::size_of::<U>());
+
+        if old_size == 0 && new_size == 0 {
+            return Ok(MemBuf {
+                buffer: empty(),
+                cap: self.cap,
+                generation: self.generation,
+                backend: self.backend,
+                tag: self.tag
+            })
+        }
+
+        let bytes = self.cap * old_size;
+        if bytes == 0 {
+            let mut empty = MemBuf::allocate_in(0, self.backend);
+            empty.tag = self.tag;
+            return Ok(empty)
+        }
+
+        if new_size == 0 || bytes % new_size != 0 {
+            return Err(CastError::SizeMismatch)
+        }
+
+        if *self.buffer as usize % mem::align_of::<U>() != 0 {
+            return Err(CastError::Misaligned)
+        }
+
+        Ok(MemBuf {
+            buffer: unsafe { NonZero::new(*self.buffer as *mut U) },
+            cap: bytes / new_size,
+            generation: self.generation,
+            backend: self.backend,
+            tag: self.tag
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::nonzero::NonZero;
+    use std::usize;
+    use {CastError, MemBuf};
+
+    #[test]
+    fn test_cast_bytes_to_words() {
+        let bytes: MemBuf<u8> = MemBuf::allocate(17);
+        assert_eq!(bytes.cast::<u64>(), Err(CastError::SizeMismatch));
+
+        unsafe { bytes.deallocate(); }
+
+        let bytes: MemBuf<u64> = MemBuf::allocate(2);
+        let bytes: MemBuf<u8> = bytes.cast().unwrap();
+        assert_eq!(bytes.capacity(), 16);
+
+        let misaligned: MemBuf<u8> = unsafe {
+            MemBuf::from_raw(NonZero::new(bytes.offset(1)), 8)
+        };
+        assert_eq!(misaligned.cast::<u64>(), Err(CastError::Misaligned));
+
+        unsafe { bytes.cast::<u64>().unwrap().deallocate(); }
+    }
+
+    #[test]
+    fn test_cast_empty() {
+        let empty: MemBuf<u8> = MemBuf::new();
+        assert_eq!(empty.cast::<u64>().unwrap().capacity(), 0);
+        assert_eq!(empty.cast::<()>().unwrap().capacity(), usize::MAX);
+
+        let bytes: MemBuf<u8> = MemBuf::allocate(4);
+        assert_eq!(bytes.cast::<()>(), Err(CastError::SizeMismatch));
+        unsafe { bytes.deallocate(); }
+    }
+}
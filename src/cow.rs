@@ -0,0 +1,110 @@
+use std::ops::Deref;
+
+use {SharedBuf, SliceBuf};
+
+/// A copy-on-write buffer of Ts, built on `SharedBuf`.
+///
+/// Clones share one allocation until one of them is modified with
+/// `make_mut`, which first gives it a copy of its own unless it is already
+/// the only reference. This is the storage behind immutable strings and
+/// persistent collections.
+///
+/// ```
+/// # use membuf::CowBuf;
+///
+/// let mut original = CowBuf::from_slice(&[1, 2, 3]);
+/// let snapshot = original.clone();
+///
+/// original.make_mut()[0] = 10;
+///
+/// assert_eq!(&*original, &[10, 2, 3]);
+/// assert_eq!(&*snapshot, &[1, 2, 3]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CowBuf<T> {
+    inner: SharedBuf<T>
+}
+
+impl<T: Clone> CowBuf<T> {
+    /// Create a new buffer holding clones of the elements of `slice`.
+    pub fn from_slice(slice: &[T]) -> CowBuf<T> {
+        CowBuf { inner: SharedBuf::from_slice(slice) }
+    }
+
+    /// View the elements as a mutable slice, first cloning them into a new
+    /// allocation if the buffer is shared.
+    pub fn make_mut(&mut self) -> &mut [T] {
+        if !self.inner.is_unique() {
+            self.inner = SharedBuf::from_slice(&self.inner);
+        }
+
+        self.inner.get_mut().unwrap()
+    }
+}
+
+impl<T> CowBuf<T> {
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        self.inner.as_slice()
+    }
+
+    /// Is this the only reference to the allocation, so that `make_mut`
+    /// will not copy?
+    pub fn is_unique(&self) -> bool {
+        self.inner.is_unique()
+    }
+
+    /// Get the `SharedBuf` holding the elements.
+    pub fn into_shared(self) -> SharedBuf<T> {
+        self.inner
+    }
+}
+
+impl<T> Deref for CowBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T> From<SharedBuf<T>> for CowBuf<T> {
+    fn from(inner: SharedBuf<T>) -> CowBuf<T> {
+        CowBuf { inner: inner }
+    }
+}
+
+impl<T> From<SliceBuf<T>> for CowBuf<T> {
+    fn from(buffer: SliceBuf<T>) -> CowBuf<T> {
+        CowBuf { inner: SharedBuf::from(buffer) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use CowBuf;
+
+    #[test]
+    fn test_make_mut_copies_only_when_shared() {
+        let mut buffer = CowBuf::from_slice(&["a".to_string()]);
+        let address = buffer.as_ptr();
+
+        buffer.make_mut()[0].push('b');
+        assert_eq!(buffer.as_ptr(), address);
+
+        let snapshot = buffer.clone();
+        assert!(!buffer.is_unique());
+        buffer.make_mut()[0].push('c');
+        assert!(buffer.as_ptr() != address);
+        assert_eq!((&*buffer[0], &*snapshot[0]), ("abc", "ab"));
+
+        // The snapshot is now the only reference to the original.
+        let mut snapshot = snapshot;
+        assert!(snapshot.is_unique());
+        snapshot.make_mut()[0].clear();
+        assert_eq!(snapshot.as_ptr(), address);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Read, Write};
+use std::{cmp, ptr};
+
+use SliceBuf;
+
+/// An `io::Read` and `io::Write` adapter over a `SliceBuf<u8>`.
+///
+/// Writes append to the initialized bytes of the buffer, growing it when it
+/// runs out of spare capacity. Reads start from the beginning of the
+/// initialized bytes and advance a read position, independently of writes.
+///
+/// ```
+/// # use membuf::{BufCursor, SliceBuf};
+/// use std::io::{Read, Write};
+///
+/// let mut cursor = BufCursor::new(SliceBuf::new());
+/// write!(cursor, "{} + {}", 1, 2).unwrap();
+///
+/// let mut text = String::new();
+/// cursor.read_to_string(&mut text).unwrap();
+/// assert_eq!(text, "1 + 2");
+///
+/// assert_eq!(&*cursor.into_inner(), b"1 + 2");
+/// ```
+#[derive(Debug)]
+pub struct BufCursor {
+    buffer: SliceBuf<u8>,
+    pos: usize
+}
+
+impl BufCursor {
+    /// Create a cursor over `buffer`, reading from its start.
+    pub fn new(buffer: SliceBuf<u8>) -> BufCursor {
+        BufCursor { buffer: buffer, pos: 0 }
+    }
+
+    /// Get the underlying buffer.
+    pub fn into_inner(self) -> SliceBuf<u8> {
+        self.buffer
+    }
+
+    /// Get a reference to the underlying buffer.
+    pub fn get_ref(&self) -> &SliceBuf<u8> {
+        &self.buffer
+    }
+
+    /// Get a mutable reference to the underlying buffer.
+    pub fn get_mut(&mut self) -> &mut SliceBuf<u8> {
+        &mut self.buffer
+    }
+
+    /// The read position, as an offset into the initialized bytes.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Set the read position.
+    pub fn set_position(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+}
+
+impl Write for BufCursor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.buffer.len();
+
+        if buf.len() > self.buffer.capacity() - len {
+            let cap = cmp::max(self.buffer.capacity() * 2, len + buf.len());
+            self.buffer.reallocate(cap);
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(),
+                                     self.buffer.spare_capacity_mut().as_mut_ptr(),
+                                     buf.len());
+            self.buffer.set_len(len + buf.len());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Read for BufCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = cmp::min(self.pos, self.buffer.len());
+        let n = cmp::min(buf.len(), self.buffer.len() - start);
+
+        unsafe {
+            ptr::copy_nonoverlapping(self.buffer.as_ptr().offset(start as isize),
+                                     buf.as_mut_ptr(), n);
+        }
+        self.pos = start + n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use {BufCursor, SliceBuf};
+
+    #[test]
+    fn test_interleaved_reads_and_writes() {
+        let mut cursor = BufCursor::new(SliceBuf::allocate(4));
+        let mut out = [0; 3];
+
+        cursor.write_all(b"abcdef").unwrap();
+        assert_eq!(cursor.read(&mut out).unwrap(), 3);
+        assert_eq!(&out, b"abc");
+
+        cursor.write_all(b"gh").unwrap();
+        assert_eq!(cursor.read(&mut out).unwrap(), 3);
+        assert_eq!(&out, b"def");
+        assert_eq!(cursor.read(&mut out).unwrap(), 2);
+        assert_eq!(cursor.read(&mut out).unwrap(), 0);
+
+        cursor.set_position(100);
+        assert_eq!(cursor.read(&mut out).unwrap(), 0);
+        assert_eq!(cursor.get_ref().len(), 8);
+    }
+}
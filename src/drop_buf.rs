@@ -0,0 +1,185 @@
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+use {SliceBuf, SpareCapacity};
+
+/// A buffer of Ts which tracks how many of its elements are initialized, and
+/// drops them when it is dropped.
+///
+/// `DropBuf` is the drop-aware sibling of `SliceBuf`: its destructor drops
+/// the first `len()` elements before freeing the memory, and `truncate` and
+/// shrinking `reallocate` drop the elements they remove.
+///
+/// ```
+/// # use membuf::DropBuf;
+/// use std::rc::Rc;
+///
+/// let shared = Rc::new(());
+///
+/// let mut buffer: DropBuf<Rc<()>> = DropBuf::allocate(2);
+/// buffer.write(0, shared.clone());
+/// buffer.write(1, shared.clone());
+/// unsafe { buffer.set_len(2); }
+/// assert_eq!(Rc::strong_count(&shared), 3);
+///
+/// drop(buffer);
+/// assert_eq!(Rc::strong_count(&shared), 1);
+/// ```
+#[derive(Debug)]
+pub struct DropBuf<T> {
+    inner: SliceBuf<T>
+}
+
+impl<T> DropBuf<T> {
+    /// Create a new, empty DropBuf.
+    ///
+    /// ```
+    /// # use membuf::DropBuf;
+    ///
+    /// let buffer: DropBuf<String> = DropBuf::new();
+    /// assert_eq!(buffer.len(), 0);
+    /// ```
+    pub fn new() -> DropBuf<T> {
+        DropBuf { inner: SliceBuf::new() }
+    }
+
+    /// Create a new buffer with space for cap Ts, none of which are
+    /// initialized.
+    ///
+    /// ```
+    /// # use membuf::DropBuf;
+    ///
+    /// let buffer: DropBuf<String> = DropBuf::allocate(8);
+    /// assert_eq!(buffer.capacity(), 8);
+    /// ```
+    pub fn allocate(cap: usize) -> DropBuf<T> {
+        DropBuf { inner: SliceBuf::allocate(cap) }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts, dropping any
+    /// elements which no longer fit.
+    ///
+    /// ```
+    /// # use membuf::DropBuf;
+    ///
+    /// let mut buffer: DropBuf<String> = DropBuf::allocate(2);
+    /// buffer.write(0, "a".to_string());
+    /// buffer.write(1, "b".to_string());
+    /// unsafe { buffer.set_len(2); }
+    ///
+    /// buffer.reallocate(1);
+    /// assert_eq!(buffer.as_slice(), &["a".to_string()]);
+    /// ```
+    pub fn reallocate(&mut self, cap: usize) {
+        self.truncate(cap);
+        self.inner.reallocate(cap);
+    }
+
+    /// Get the number of initialized elements.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Get the current capacity of the DropBuf.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Set the number of initialized elements, without dropping any.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized. Elements beyond a
+    /// reduced length are forgotten rather than dropped.
+    pub unsafe fn set_len(&mut self, len: usize) {
+        self.inner.set_len(len)
+    }
+
+    /// Drop the elements from `len` onwards, if there are any.
+    ///
+    /// ```
+    /// # use membuf::DropBuf;
+    ///
+    /// let mut buffer: DropBuf<String> = DropBuf::allocate(2);
+    /// buffer.write(0, "a".to_string());
+    /// buffer.write(1, "b".to_string());
+    /// unsafe { buffer.set_len(2); }
+    ///
+    /// buffer.truncate(1);
+    /// assert_eq!(buffer.len(), 1);
+    /// ```
+    pub fn truncate(&mut self, len: usize) {
+        // Shorten the buffer first, so a panicking destructor leaks the rest
+        // of the tail instead of dropping it twice.
+        while self.len() > len {
+            unsafe {
+                let last = self.len() - 1;
+                self.inner.set_len(last);
+                ptr::read(self.inner.as_mut_slice().as_mut_ptr().offset(last as isize));
+            }
+        }
+    }
+
+    /// View the initialized elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        self.inner.as_slice()
+    }
+
+    /// View the initialized elements as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.inner.as_mut_slice()
+    }
+
+    /// Write `value` into the element at `index`, without reading or dropping
+    /// the old value.
+    ///
+    /// Panics if `index` is not less than the capacity. This does not change
+    /// the length, so `set_len` must be used to include new elements.
+    pub fn write(&mut self, index: usize, value: T) {
+        self.inner.write(index, value)
+    }
+
+    /// Get the uninitialized capacity after the initialized elements.
+    pub fn spare_capacity_mut<'a>(&'a mut self) -> SpareCapacity<'a, T> {
+        self.inner.spare_capacity_mut()
+    }
+}
+
+impl<T> Drop for DropBuf<T> {
+    fn drop(&mut self) {
+        self.truncate(0)
+    }
+}
+
+impl<T> Deref for DropBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T> DerefMut for DropBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] { self.as_mut_slice() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+    use DropBuf;
+
+    #[test]
+    fn test_truncate_drops_tail() {
+        let shared = Rc::new(());
+        let mut buffer: DropBuf<Rc<()>> = DropBuf::allocate(4);
+
+        for i in 0..4 { buffer.write(i, shared.clone()); }
+        unsafe { buffer.set_len(4); }
+        assert_eq!(Rc::strong_count(&shared), 5);
+
+        buffer.truncate(1);
+        assert_eq!(Rc::strong_count(&shared), 2);
+
+        buffer.reallocate(0);
+        assert_eq!(Rc::strong_count(&shared), 1);
+    }
+}
@@ -0,0 +1,232 @@
+//! A C interface for allocating byte buffers, declared in `include/membuf.h`.
+//!
+//! C and C++ code can allocate, grow and free buffers through the same
+//! allocator, registry and debugging features as Rust code, and hand them to
+//! Rust as a `UniqueBuf<u8>`.
+//!
+//! ## Ownership
+//!
+//! A `RawMemBuf` owns its allocation, exactly like the `UniqueBuf<u8>` it
+//! stands for. It must be released exactly once, either by passing it to
+//! `membuf_free` or by adopting it with `UniqueBuf::from_raw_handle`, after
+//! which the handle must no longer be used. Memory from `malloc` or any other
+//! allocator must never be passed in, nor may a handle's memory be passed to
+//! `free`.
+//!
+//! ## Errors
+//!
+//! Null handles and handles naming an unknown backend are reported with an
+//! error code where the function has one. Any other failure, such as a
+//! double free caught by the `detect-double-free` feature, aborts the
+//! process, since a panic must not unwind into C.
+//!
+//! ```
+//! use membuf::UniqueBuf;
+//! use membuf::ffi::{membuf_alloc, membuf_capacity};
+//!
+//! // Allocated on the C side...
+//! let handle = membuf_alloc(64);
+//! assert_eq!(unsafe { membuf_capacity(&handle) }, 64);
+//!
+//! // ...and adopted by Rust, which frees it when dropped.
+//! let buffer = unsafe { UniqueBuf::from_raw_handle(handle) };
+//! assert_eq!(buffer.capacity(), 64);
+//! ```
+
+use core::nonzero::NonZero;
+use std::{mem, ptr, thread};
+use std::intrinsics;
+
+use libc::c_int;
+
+use {MemBuf, UniqueBuf};
+use backend::{BackendHandle, DEFAULT_BACKEND};
+
+/// A buffer of bytes owned by C code: a pointer, a capacity in bytes and the
+/// backend the buffer was allocated from.
+///
+/// A failed allocation is represented by a null pointer.
+#[repr(C)]
+#[derive(Debug)]
+pub struct RawMemBuf {
+    /// The first byte of the buffer, or null.
+    pub ptr: *mut u8,
+    /// The number of bytes the buffer has space for.
+    pub capacity: usize,
+    /// The `id` of the buffer's backend, which C code must leave untouched.
+    pub backend: usize
+}
+
+impl UniqueBuf<u8> {
+    /// Give up ownership of this buffer to C code, as a `RawMemBuf`.
+    pub fn into_raw_handle(self) -> RawMemBuf {
+        let handle = RawMemBuf {
+            ptr: *self,
+            capacity: self.capacity(),
+            backend: self.backend().id()
+        };
+        mem::forget(self);
+        handle
+    }
+
+    /// Take ownership of a buffer allocated by `membuf_alloc`.
+    ///
+    /// The buffer is reallocated and freed through the backend recorded in
+    /// the handle.
+    ///
+    /// ## Safety
+    ///
+    /// `handle` must have come from `membuf_alloc`, `membuf_realloc` or
+    /// `into_raw_handle`, and must not be used again.
+    ///
+    /// Panics if the handle is null.
+    pub unsafe fn from_raw_handle(handle: RawMemBuf) -> UniqueBuf<u8> {
+        if handle.ptr.is_null() { panic!("UniqueBuf::from_raw_handle called with a null handle") }
+        let backend = BackendHandle::from_id(handle.backend);
+        UniqueBuf::from_raw(MemBuf::from_raw_in(NonZero::new(handle.ptr), handle.capacity, backend))
+    }
+}
+
+/// Allocate a buffer of `capacity` bytes.
+///
+/// Returns a handle with a null pointer if the allocation fails.
+#[no_mangle]
+pub extern "C" fn membuf_alloc(capacity: usize) -> RawMemBuf {
+    let _guard = AbortOnUnwind;
+
+    match UniqueBuf::<u8>::try_allocate(capacity) {
+        Ok(buffer) => buffer.into_raw_handle(),
+        Err(_) => RawMemBuf { ptr: ptr::null_mut(), capacity: 0, backend: DEFAULT_BACKEND.id() }
+    }
+}
+
+/// Reallocate the buffer behind `handle` to `capacity` bytes, updating the
+/// handle in place.
+///
+/// Returns 0 on success. If the allocation fails returns -1, and if `handle`
+/// or its pointer is null, or its backend is unknown, returns -2, leaving
+/// the buffer untouched.
+#[no_mangle]
+pub unsafe extern "C" fn membuf_realloc(handle: *mut RawMemBuf, capacity: usize) -> c_int {
+    let _guard = AbortOnUnwind;
+    if !is_valid(handle) { return -2 }
+
+    let mut buffer = UniqueBuf::from_raw_handle(ptr::read(handle));
+    let result = buffer.try_reallocate(capacity);
+
+    ptr::write(handle, buffer.into_raw_handle());
+    if result.is_ok() { 0 } else { -1 }
+}
+
+/// Free the buffer behind `handle`. Handles with a null pointer or an
+/// unknown backend are ignored.
+#[no_mangle]
+pub unsafe extern "C" fn membuf_free(handle: RawMemBuf) {
+    let _guard = AbortOnUnwind;
+
+    if is_valid(&handle) {
+        drop(UniqueBuf::from_raw_handle(handle))
+    }
+}
+
+/// Get the capacity in bytes of the buffer behind `handle`, or 0 if `handle`
+/// is null.
+#[no_mangle]
+pub unsafe extern "C" fn membuf_capacity(handle: *const RawMemBuf) -> usize {
+    if handle.is_null() { 0 } else { (*handle).capacity }
+}
+
+// Can the buffer behind `handle` be adopted by `from_raw_handle`?
+unsafe fn is_valid(handle: *const RawMemBuf) -> bool {
+    !handle.is_null() && !(*handle).ptr.is_null() &&
+        BackendHandle::try_from_id((*handle).backend).is_some()
+}
+
+// Aborts the process if dropped while unwinding, so a panic in the Rust
+// half of a C function never unwinds into its C caller.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        if thread::panicking() { unsafe { intrinsics::abort() } }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::usize;
+
+    use std::ptr;
+
+    use super::{membuf_alloc, membuf_capacity, membuf_free, membuf_realloc};
+
+    #[cfg(feature = "c-header-test")]
+    #[link(name = "membuf_test", kind = "static")]
+    extern {
+        fn membuf_c_test() -> i32;
+    }
+
+    #[test]
+    fn test_c_lifecycle() {
+        unsafe {
+            let mut handle = membuf_alloc(16);
+            *handle.ptr.offset(15) = 0xaa;
+
+            assert_eq!(membuf_realloc(&mut handle, 4096), 0);
+            assert_eq!(membuf_capacity(&handle), 4096);
+            assert_eq!(*handle.ptr.offset(15), 0xaa);
+
+            let before = handle.ptr;
+            assert_eq!(membuf_realloc(&mut handle, usize::MAX), -1);
+            assert_eq!((handle.ptr, handle.capacity), (before, 4096));
+
+            membuf_free(handle);
+        }
+
+        let mut failed = membuf_alloc(usize::MAX);
+        assert!(failed.ptr.is_null());
+        unsafe {
+            assert_eq!(membuf_realloc(&mut failed, 16), -2);
+            assert_eq!(membuf_realloc(ptr::null_mut(), 16), -2);
+            assert_eq!(membuf_capacity(ptr::null()), 0);
+            membuf_free(failed)
+        }
+    }
+
+    #[test]
+    fn test_unknown_backend_is_rejected() {
+        let mut handle = membuf_alloc(16);
+        let backend = handle.backend;
+        handle.backend = usize::MAX;
+
+        unsafe {
+            assert_eq!(membuf_realloc(&mut handle, 64), -2);
+            assert_eq!(membuf_capacity(&handle), 16);
+
+            handle.backend = backend;
+            membuf_free(handle);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "c-header-test")]
+    fn test_c_header() {
+        assert_eq!(unsafe { membuf_c_test() }, 0);
+    }
+
+    #[test]
+    fn test_handle_keeps_backend() {
+        use backend::{self, TrackingAlloc, TRACKING_ALLOC_INIT};
+        use UniqueBuf;
+
+        static TRACKER: TrackingAlloc = TRACKING_ALLOC_INIT;
+        let tracked = unsafe { backend::register_backend("test::ffi", &TRACKER) };
+
+        let handle = UniqueBuf::<u8>::allocate_in(64, tracked).into_raw_handle();
+        let buffer = unsafe { UniqueBuf::from_raw_handle(handle) };
+        assert_eq!(buffer.backend(), tracked);
+
+        drop(buffer);
+        TRACKER.assert_no_leaks();
+    }
+}
@@ -0,0 +1,157 @@
+use std::{mem, ptr, usize};
+
+use UniqueBuf;
+
+// The index stored in the last free slot.
+const NIL: usize = usize::MAX;
+
+/// An intrusive list of free slots in a buffer, for slabs and slot maps.
+///
+/// The list itself is only the index of the first free slot. The index of
+/// the next free slot is stored in the memory of each free slot, so keeping
+/// track of any number of free slots takes no memory beyond the buffer. As
+/// the links are indices rather than pointers, the list survives the buffer
+/// being reallocated.
+///
+/// Elements must be at least as large as a `usize`, but need not be aligned
+/// like one.
+///
+/// ```
+/// # use membuf::{FreeList, UniqueBuf};
+///
+/// let mut slots: UniqueBuf<[u8; 16]> = UniqueBuf::allocate(4);
+/// let mut free = FreeList::new();
+///
+/// unsafe {
+///     for index in (0..4).rev() { free.push_free(&mut slots, index); }
+///
+///     assert_eq!(free.pop_free(&slots), Some(0));
+///     assert_eq!(free.pop_free(&slots), Some(1));
+///
+///     free.push_free(&mut slots, 0);
+///     assert_eq!(free.pop_free(&slots), Some(0));
+///     assert_eq!(free.pop_free(&slots), Some(2));
+/// }
+/// assert_eq!(free.len(), 1);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FreeList {
+    head: usize,
+    len: usize
+}
+
+impl FreeList {
+    /// Create a new, empty list.
+    pub fn new() -> FreeList {
+        FreeList { head: NIL, len: 0 }
+    }
+
+    /// Get the number of free slots in the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the list has no free slots.
+    pub fn is_empty(&self) -> bool {
+        self.head == NIL
+    }
+
+    /// Get the index of the slot `pop_free` will return next, if any.
+    pub fn peek(&self) -> Option<usize> {
+        if self.is_empty() { None } else { Some(self.head) }
+    }
+
+    /// Add the slot at `index` of `buffer` to the front of the list,
+    /// overwriting its start with the index of the next free slot.
+    ///
+    /// Panics if `index` is out of bounds, or if `T` is smaller than a
+    /// `usize`.
+    ///
+    /// ## Safety
+    ///
+    /// The slot must not hold a value which is used again, since it is not
+    /// dropped, nor already be in the list. Until it is popped, the slot
+    /// must not be written to, and the list must only be used with `buffer`.
+    pub unsafe fn push_free<T>(&mut self, buffer: &mut UniqueBuf<T>, index: usize) {
+        check_slot_size::<T>();
+        let slot = buffer.ptr_at(index) as *mut u8;
+
+        ptr::copy_nonoverlapping(&self.head as *const usize as *const u8, slot,
+                                 mem::size_of::<usize>());
+        self.head = index;
+        self.len += 1;
+    }
+
+    /// Remove the slot at the front of the list from `buffer`, returning its
+    /// index, or `None` if the list is empty.
+    ///
+    /// The contents of the slot are unspecified.
+    ///
+    /// ## Safety
+    ///
+    /// `buffer` must be the buffer the slots were pushed to, or a
+    /// reallocation of it, and no free slot may have been written to since.
+    pub unsafe fn pop_free<T>(&mut self, buffer: &UniqueBuf<T>) -> Option<usize> {
+        if self.is_empty() { return None }
+
+        let index = self.head;
+        let slot = buffer.ptr_at(index) as *const u8;
+
+        ptr::copy_nonoverlapping(slot, &mut self.head as *mut usize as *mut u8,
+                                 mem::size_of::<usize>());
+        self.len -= 1;
+        Some(index)
+    }
+
+    /// Forget every free slot, for instance when the buffer is cleared.
+    pub fn clear(&mut self) {
+        *self = FreeList::new();
+    }
+}
+
+impl Default for FreeList {
+    fn default() -> FreeList { FreeList::new() }
+}
+
+fn check_slot_size<T>() {
+    if mem::size_of::<T>() < mem::size_of::<usize>() {
+        panic!("FreeList needs slots of at least {} bytes, but they have {}",
+               mem::size_of::<usize>(), mem::size_of::<T>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {FreeList, UniqueBuf};
+
+    #[test]
+    fn test_links_survive_reallocation() {
+        let mut slots: UniqueBuf<(u64, u64)> = UniqueBuf::allocate(2);
+        let mut free = FreeList::new();
+
+        unsafe {
+            free.push_free(&mut slots, 1);
+            free.push_free(&mut slots, 0);
+
+            slots.reallocate(64);
+            free.push_free(&mut slots, 63);
+
+            assert_eq!(free.len(), 3);
+            assert_eq!(free.peek(), Some(63));
+            assert_eq!(free.pop_free(&slots), Some(63));
+            assert_eq!(free.pop_free(&slots), Some(0));
+            assert_eq!(free.pop_free(&slots), Some(1));
+            assert_eq!(free.pop_free(&slots), None);
+        }
+
+        assert!(free.is_empty());
+    }
+
+    #[test]
+    #[should_panic = "FreeList needs slots of at least 8 bytes, but they have 4"]
+    #[cfg(target_pointer_width = "64")]
+    fn test_small_slots() {
+        let mut slots: UniqueBuf<u32> = UniqueBuf::allocate(4);
+        unsafe { FreeList::new().push_free(&mut slots, 0) }
+    }
+}
@@ -0,0 +1,128 @@
+use std::ops::Deref;
+use std::ptr;
+
+use {shared, SharedBuf, SliceBuf, UniqueBuf};
+
+/// An immutable buffer of Ts which can be cloned cheaply and shared between
+/// threads, made by freezing a `UniqueBuf` or `SliceBuf` once it is built.
+///
+/// Freezing does not copy the elements: the buffer keeps its allocation,
+/// and a separate reference count is allocated next to it. The elements are
+/// dropped along with the last clone.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+/// use std::thread;
+///
+/// let mut builder: UniqueBuf<u32> = UniqueBuf::allocate(4);
+/// for i in 0..4 { unsafe { *builder.offset(i) = i as u32 * i as u32; } }
+///
+/// let frozen = unsafe { builder.freeze(4) };
+/// let shared = frozen.clone();
+/// thread::spawn(move || assert_eq!(shared[3], 9)).join().unwrap();
+///
+/// assert_eq!(&*frozen, &[0, 1, 4, 9]);
+/// ```
+#[derive(Debug)]
+pub struct FrozenBuf<T> {
+    inner: SharedBuf<Frozen<T>>
+}
+
+// The frozen buffer, which drops its elements.
+#[derive(Debug)]
+struct Frozen<T> {
+    buffer: UniqueBuf<T>,
+    len: usize
+}
+
+impl<T> Drop for Frozen<T> {
+    fn drop(&mut self) {
+        // Shorten the buffer first, so a panicking destructor leaks the rest
+        // of the elements instead of dropping them twice.
+        while self.len > 0 {
+            self.len -= 1;
+            unsafe { ptr::read(self.buffer.offset(self.len as isize)); }
+        }
+    }
+}
+
+impl<T> UniqueBuf<T> {
+    /// Freeze this buffer into a `FrozenBuf` holding its first `len`
+    /// elements, without copying them.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    pub unsafe fn freeze(self, len: usize) -> FrozenBuf<T> {
+        debug_assert!(len <= self.capacity());
+        FrozenBuf { inner: shared::single(Frozen { buffer: self, len: len }) }
+    }
+}
+
+impl<T> SliceBuf<T> {
+    /// Freeze this buffer into a `FrozenBuf` holding its initialized
+    /// elements, without copying them.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let frozen = SliceBuf::from_slice(b"frozen").freeze();
+    /// assert_eq!(&*frozen.clone(), b"frozen");
+    /// ```
+    pub fn freeze(self) -> FrozenBuf<T> {
+        let (buffer, len) = self.into_raw();
+        unsafe { buffer.freeze(len) }
+    }
+}
+
+impl<T> FrozenBuf<T> {
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.inner[0].len
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        let frozen = &self.inner[0];
+        unsafe { frozen.buffer.as_slice(frozen.len) }
+    }
+}
+
+impl<T> Clone for FrozenBuf<T> {
+    fn clone(&self) -> FrozenBuf<T> {
+        FrozenBuf { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Deref for FrozenBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use SliceBuf;
+
+    #[test]
+    fn test_freeze_keeps_allocation() {
+        let counted = Arc::new(());
+        let mut buffer: SliceBuf<Arc<()>> = SliceBuf::allocate(8);
+        buffer.write(0, counted.clone());
+        buffer.write(1, counted.clone());
+        unsafe { buffer.set_len(2); }
+
+        let address = buffer.as_ptr();
+        let frozen = buffer.freeze();
+        let other = frozen.clone();
+        assert_eq!((frozen.as_ptr(), frozen.len()), (address, 2));
+
+        drop(frozen);
+        assert_eq!(Arc::strong_count(&counted), 3);
+        drop(other);
+        assert_eq!(Arc::strong_count(&counted), 1);
+    }
+}
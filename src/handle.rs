@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::{fmt, mem};
+
+use UniqueBuf;
+
+/// The index of a T in a buffer, stored in place of a pointer to it.
+///
+/// Unlike a pointer, an index stays valid when the buffer is reallocated,
+/// so growable arenas and graphs can link their elements with indices and
+/// resolve them against the buffer when they are used.
+///
+/// ```
+/// # use membuf::{BufIndex, UniqueBuf};
+///
+/// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(2);
+/// let index = BufIndex::new(1);
+/// unsafe { *buffer.resolve(index) = 7; }
+///
+/// buffer.reallocate(1024);
+/// assert_eq!(unsafe { *buffer.resolve(index) }, 7);
+/// ```
+pub struct BufIndex<T> {
+    index: usize,
+    _marker: PhantomData<fn() -> T>
+}
+
+impl<T> BufIndex<T> {
+    /// Create an index referring to the element at `index`.
+    pub fn new(index: usize) -> BufIndex<T> {
+        BufIndex { index: index, _marker: PhantomData }
+    }
+
+    /// Get the index as a plain number.
+    pub fn get(&self) -> usize {
+        self.index
+    }
+}
+
+/// The byte offset of a T in a buffer, stored in place of a pointer to it.
+///
+/// This is `BufIndex` for buffers holding values of different types, such
+/// as serialized structures. The element type of the buffer only sets the
+/// alignment of its start, so the buffer below is allocated as `u64`s to
+/// hold a `u64` at byte offset 8.
+///
+/// ```
+/// # use membuf::{BufOffset, UniqueBuf};
+///
+/// let buffer: UniqueBuf<u64> = UniqueBuf::allocate(2);
+/// let header: BufOffset<u32> = BufOffset::new(0);
+/// let body: BufOffset<u64> = BufOffset::new(8);
+///
+/// unsafe {
+///     *buffer.resolve_offset(header) = 1;
+///     *buffer.resolve_offset(body) = 2;
+/// }
+/// ```
+pub struct BufOffset<T> {
+    offset: usize,
+    _marker: PhantomData<fn() -> T>
+}
+
+impl<T> BufOffset<T> {
+    /// Create an offset referring to the T starting `offset` bytes into the
+    /// buffer.
+    pub fn new(offset: usize) -> BufOffset<T> {
+        BufOffset { offset: offset, _marker: PhantomData }
+    }
+
+    /// Get the offset in bytes.
+    pub fn get(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<T> UniqueBuf<T> {
+    /// Get a pointer to the element referred to by `index`.
+    ///
+    /// ## Safety
+    ///
+    /// The index must be less than the capacity, which is checked in debug
+    /// builds.
+    pub unsafe fn resolve(&self, index: BufIndex<T>) -> *mut T {
+        debug_assert!(index.index < self.capacity(),
+                      "BufIndex {} out of bounds for buffer with capacity {}",
+                      index.index, self.capacity());
+        self.offset(index.index as isize)
+    }
+
+    /// Get a pointer to the value referred to by `offset`, counted in bytes
+    /// from the start of the buffer.
+    ///
+    /// ## Safety
+    ///
+    /// The whole value must fit in the buffer, and the pointer must be
+    /// suitably aligned for a U. Both are checked in debug builds.
+    pub unsafe fn resolve_offset<U>(&self, offset: BufOffset<U>) -> *mut U {
+        let bytes = self.capacity() * mem::size_of::<T>();
+        debug_assert!(offset.offset <= bytes &&
+                      mem::size_of::<U>() <= bytes - offset.offset,
+                      "BufOffset {} out of bounds for buffer of {} bytes",
+                      offset.offset, bytes);
+
+        let ptr = (**self as *mut u8).offset(offset.offset as isize) as *mut U;
+        debug_assert!(ptr as usize % mem::align_of::<U>() == 0,
+                      "BufOffset {} is misaligned", offset.offset);
+        ptr
+    }
+}
+
+// Handles are plain numbers whatever T is, so these are not derived, which
+// would require T to implement each trait.
+macro_rules! handle_impls {
+    ($handle:ident, $field:ident) => {
+        impl<T> Clone for $handle<T> {
+            fn clone(&self) -> $handle<T> { *self }
+        }
+
+        impl<T> Copy for $handle<T> {}
+
+        impl<T> PartialEq for $handle<T> {
+            fn eq(&self, other: &$handle<T>) -> bool { self.$field == other.$field }
+        }
+
+        impl<T> Eq for $handle<T> {}
+
+        impl<T> PartialOrd for $handle<T> {
+            fn partial_cmp(&self, other: &$handle<T>) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl<T> Ord for $handle<T> {
+            fn cmp(&self, other: &$handle<T>) -> Ordering { self.$field.cmp(&other.$field) }
+        }
+
+        impl<T> Hash for $handle<T> {
+            fn hash<H: Hasher>(&self, state: &mut H) { self.$field.hash(state) }
+        }
+
+        impl<T> fmt::Debug for $handle<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, concat!(stringify!($handle), "({})"), self.$field)
+            }
+        }
+    }
+}
+
+handle_impls!(BufIndex, index);
+handle_impls!(BufOffset, offset);
+
+#[cfg(test)]
+mod test {
+    use {BufIndex, BufOffset, UniqueBuf};
+
+    #[test]
+    fn test_handles_are_plain_numbers() {
+        struct NotClone;
+
+        let index: BufIndex<NotClone> = BufIndex::new(3);
+        let copy = index;
+        assert_eq!((index, copy.get()), (BufIndex::new(3), 3));
+        assert_eq!(format!("{:?}", BufOffset::<NotClone>::new(8)), "BufOffset(8)");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "BufOffset 4 out of bounds for buffer of 8 bytes"]
+    fn test_offset_out_of_bounds() {
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+        unsafe { buffer.resolve_offset(BufOffset::<u64>::new(4)); }
+    }
+}
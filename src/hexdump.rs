@@ -0,0 +1,95 @@
+//! Hexdumps of buffer contents, for debugging.
+//!
+//! `UniqueBuf::hexdump` and `SliceBuf::hexdump` format buffer contents with
+//! offsets and ASCII. Every byte of the elements is read, so they are unsafe
+//! for element types with padding.
+//!
+//! Bytes have no padding, so with the `hexdump-debug` feature the alternate
+//! `Debug` output of a `SliceBuf<u8>`, `{:#?}`, is safely its hexdump.
+
+use std::fmt;
+
+use UniqueBuf;
+
+/// A hexdump of a range of bytes, annotated with offsets and ASCII, in the
+/// style of `hexdump -C`.
+///
+/// Created by `UniqueBuf::hexdump`.
+#[derive(Copy, Clone)]
+pub struct HexDump<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> fmt::Display for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (line, chunk) in self.bytes.chunks(16).enumerate() {
+            try!(write!(f, "{:08x} ", line * 16));
+
+            for i in 0..16 {
+                if i % 8 == 0 { try!(f.write_str(" ")) }
+                match chunk.get(i) {
+                    Some(byte) => try!(write!(f, "{:02x} ", byte)),
+                    None => try!(f.write_str("   "))
+                }
+            }
+
+            try!(f.write_str(" |"));
+            for &byte in chunk {
+                let c = if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' };
+                try!(write!(f, "{}", c));
+            }
+            try!(f.write_str("|\n"));
+        }
+
+        write!(f, "{:08x}\n", self.bytes.len())
+    }
+}
+
+impl<'a> fmt::Debug for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HexDump").field("len", &self.bytes.len()).finish()
+    }
+}
+
+impl<T> UniqueBuf<T> {
+    /// Format the bytes of the first `len` elements as a hexdump.
+    ///
+    /// ## Safety
+    ///
+    /// As for `as_bytes`: `len` must be at most the capacity, and every byte
+    /// of the first `len` elements must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(20);
+    /// buffer.fill_bytes(b'a');
+    /// buffer.write_u32_be(16, 0x00010203);
+    ///
+    /// let dump = unsafe { buffer.hexdump(20) }.to_string();
+    /// assert_eq!(dump.lines().nth(1).unwrap(),
+    ///            "00000010  00 01 02 03                                       |....|");
+    /// ```
+    pub unsafe fn hexdump<'a>(&'a self, len: usize) -> HexDump<'a> {
+        HexDump { bytes: self.as_bytes(len) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use UniqueBuf;
+
+    #[test]
+    fn test_hexdump() {
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(17);
+        for i in 0..17 { unsafe { buffer.write_at(i, b'A' + i as u8); } }
+
+        let dump = unsafe { buffer.hexdump(17) }.to_string();
+        assert_eq!(dump, "\
+00000000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|
+00000010  51                                                |Q|
+00000011
+");
+        assert_eq!(unsafe { buffer.hexdump(0) }.to_string(), "00000000\n");
+    }
+}
@@ -0,0 +1,108 @@
+//! Vectored I/O over several byte buffers at once.
+//!
+//! These build the `iovec` arrays taken by `writev` and `readv`, so data
+//! gathered from, or scattered into, separate buffers can be passed to the
+//! operating system in one call without copying it together first.
+//!
+//! The `iovec`s point into the buffers, so the buffers must not be
+//! reallocated or dropped while they are in use.
+//!
+//! ```
+//! extern crate libc;
+//! # extern crate membuf;
+//! use membuf::{iovec, SliceBuf};
+//!
+//! # fn main() {
+//! let header = SliceBuf::from_slice(b"HTTP/1.1 200 OK\r\n\r\n");
+//! let body = SliceBuf::from_slice(b"hello");
+//!
+//! let mut fds = [0; 2];
+//! unsafe { assert_eq!(libc::pipe(fds.as_mut_ptr()), 0); }
+//!
+//! let iovecs = iovec::as_iovecs(&[&header, &body]);
+//! let written = unsafe { libc::writev(fds[1], iovecs.as_ptr(), iovecs.len() as libc::c_int) };
+//! assert_eq!(written, 24);
+//!
+//! let (mut first, mut second) = (SliceBuf::allocate(17), SliceBuf::allocate(64));
+//! let read = {
+//!     let mut buffers = [&mut first, &mut second];
+//!     let iovecs = iovec::spare_iovecs(&mut buffers);
+//!     let read = unsafe { libc::readv(fds[0], iovecs.as_ptr(), iovecs.len() as libc::c_int) };
+//!     unsafe { iovec::commit(&mut buffers, read as usize); }
+//!     read
+//! };
+//!
+//! assert_eq!(read, 24);
+//! assert_eq!(&*first, b"HTTP/1.1 200 OK\r\n");
+//! assert_eq!(&*second, b"\r\nhello");
+//! # unsafe { libc::close(fds[0]); libc::close(fds[1]); }
+//! # }
+//! ```
+
+use std::cmp;
+
+use libc::{c_void, iovec};
+
+use SliceBuf;
+
+/// Describe the initialized bytes of each buffer, for `writev`.
+pub fn as_iovecs(buffers: &[&SliceBuf<u8>]) -> Vec<iovec> {
+    buffers.iter().map(|buffer| {
+        iovec { iov_base: buffer.as_ptr() as *mut c_void, iov_len: buffer.len() }
+    }).collect()
+}
+
+/// Describe the spare capacity of each buffer, for `readv`.
+///
+/// After reading, the bytes read must be committed with `commit`.
+pub fn spare_iovecs(buffers: &mut [&mut SliceBuf<u8>]) -> Vec<iovec> {
+    buffers.iter_mut().map(|buffer| {
+        let mut spare = buffer.spare_capacity_mut();
+        iovec { iov_base: spare.as_mut_ptr() as *mut c_void, iov_len: spare.len() }
+    }).collect()
+}
+
+/// Extend the initialized bytes of the buffers by a total of `read` bytes,
+/// filling the spare capacity of each buffer in order, as `readv` does.
+///
+/// ## Safety
+///
+/// The first `read` bytes of the spare capacity of the buffers, taken in
+/// order, must have been initialized, for instance by `readv` with the
+/// `iovec`s from `spare_iovecs`.
+pub unsafe fn commit(buffers: &mut [&mut SliceBuf<u8>], mut read: usize) {
+    for buffer in buffers.iter_mut() {
+        let (len, cap) = (buffer.len(), buffer.capacity());
+        let filled = cmp::min(read, cap - len);
+
+        buffer.set_len(len + filled);
+        read -= filled;
+    }
+
+    debug_assert!(read == 0, "Committed {} bytes beyond the spare capacity", read);
+}
+
+#[cfg(test)]
+mod test {
+    use SliceBuf;
+
+    #[test]
+    fn test_commit_spans_buffers() {
+        let mut full: SliceBuf<u8> = SliceBuf::from_slice(b"ab");
+        let (mut first, mut second): (SliceBuf<u8>, SliceBuf<u8>) =
+            (SliceBuf::allocate(3), SliceBuf::allocate(3));
+
+        let lens: Vec<usize> = super::spare_iovecs(&mut [&mut full, &mut first, &mut second])
+            .iter().map(|iovec| iovec.iov_len).collect();
+        assert_eq!(lens, [0, 3, 3]);
+
+        // Fill the bytes about to be committed, as readv would.
+        for i in 0..3 { first.spare_capacity_mut().write(i, b'c' + i as u8); }
+        second.spare_capacity_mut().write(0, b'f');
+
+        unsafe { super::commit(&mut [&mut full, &mut first, &mut second], 4); }
+        assert_eq!((full.len(), first.len(), second.len()), (2, 3, 1));
+        assert_eq!(&*first, b"cde");
+        assert_eq!(&*second, b"f");
+    }
+}
@@ -0,0 +1,164 @@
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use UniqueBuf;
+
+/// Temporary raw access to a region of a `UniqueBuf`, from `UniqueBuf::lend`.
+///
+/// The region is given back when the guard is dropped. In debug builds,
+/// lending a region which overlaps one still lent from the same buffer
+/// panics, so code moving from raw pointers towards safe APIs can check that
+/// the regions it hands out never alias.
+pub struct BufGuard<'a, T: 'a> {
+    ptr: *mut T,
+    range: Range<usize>,
+    key: usize,
+    _marker: PhantomData<&'a UniqueBuf<T>>
+}
+
+impl<T> UniqueBuf<T> {
+    /// Lend out the elements in `range` for raw access, until the guard is
+    /// dropped.
+    ///
+    /// Panics if the range is beyond the capacity and, in debug builds, if
+    /// it overlaps a region of this buffer which is still lent out. A
+    /// forgotten guard keeps its region lent for good.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u32> = UniqueBuf::allocate(8);
+    ///
+    /// let (header, body) = (buffer.lend(0..2), buffer.lend(2..8));
+    /// unsafe {
+    ///     *header.as_mut_ptr() = 6;
+    ///     *body.as_mut_ptr().offset(5) = 1;
+    /// }
+    /// assert_eq!(body.len(), 6);
+    /// ```
+    pub fn lend<'a>(&'a self, range: Range<usize>) -> BufGuard<'a, T> {
+        assert!(range.start <= range.end && range.end <= self.capacity(),
+                "Lend of {}..{} out of bounds for buffer with capacity {}",
+                range.start, range.end, self.capacity());
+
+        // The buffer cannot move while it is borrowed by the guard, so its
+        // address identifies it even for zero-sized types.
+        let key = self as *const UniqueBuf<T> as usize;
+        if cfg!(debug_assertions) { acquire(key, &range) }
+
+        BufGuard {
+            ptr: unsafe { self.offset(range.start as isize) },
+            range: range,
+            key: key,
+            _marker: PhantomData
+        }
+    }
+}
+
+impl<'a, T> BufGuard<'a, T> {
+    /// Get the number of elements in the region.
+    pub fn len(&self) -> usize {
+        self.range.end - self.range.start
+    }
+
+    /// Get the range of the buffer which was lent.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Get a pointer to the first element of the region.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr
+    }
+
+    /// Get a mutable pointer to the first element of the region.
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.ptr
+    }
+}
+
+impl<'a, T> Drop for BufGuard<'a, T> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) { release(self.key, &self.range) }
+    }
+}
+
+// A lent region of the buffer at `key`.
+struct Lend {
+    key: usize,
+    start: usize,
+    end: usize
+}
+
+lazy_static! {
+    // The regions lent out by every buffer, which are only tracked in debug
+    // builds.
+    static ref LENDS: Mutex<Vec<Lend>> = Mutex::new(Vec::new());
+}
+
+fn with_lends<F: FnOnce(&mut Vec<Lend>) -> R, R>(f: F) -> R {
+    let mut lends = LENDS.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut lends)
+}
+
+fn acquire(key: usize, range: &Range<usize>) {
+    // Empty regions cannot alias anything.
+    if range.start == range.end { return }
+
+    let overlap = with_lends(|lends| {
+        let overlap = lends.iter()
+            .find(|lend| lend.key == key && lend.start < range.end && range.start < lend.end)
+            .map(|lend| (lend.start, lend.end));
+
+        if overlap.is_none() {
+            lends.push(Lend { key: key, start: range.start, end: range.end });
+        }
+        overlap
+    });
+
+    if let Some((start, end)) = overlap {
+        panic!("Lend of {}..{} overlaps the lent region {}..{}",
+               range.start, range.end, start, end)
+    }
+}
+
+fn release(key: usize, range: &Range<usize>) {
+    if range.start == range.end { return }
+
+    with_lends(|lends| {
+        let index = lends.iter()
+            .position(|lend| (lend.key, lend.start, lend.end) == (key, range.start, range.end));
+        if let Some(index) = index { lends.swap_remove(index); }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use UniqueBuf;
+
+    #[test]
+    fn test_regions_are_given_back() {
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+        let other: UniqueBuf<u8> = UniqueBuf::allocate(8);
+
+        {
+            let _first = buffer.lend(0..4);
+            let _second = buffer.lend(4..8);
+            let _elsewhere = other.lend(0..8);
+            let _empty = buffer.lend(2..2);
+        }
+
+        let whole = buffer.lend(0..8);
+        assert_eq!((whole.len(), whole.range()), (8, 0..8));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "Lend of 3..6 overlaps the lent region 0..4"]
+    fn test_overlapping_lend_panics() {
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+        let _first = buffer.lend(0..4);
+        buffer.lend(3..6);
+    }
+}
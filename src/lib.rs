@@ -1,4 +1,6 @@
-#![feature(core, nonzero, alloc, oom, heap_api)]
+#![feature(core, nonzero, alloc, oom, heap_api, link_llvm_intrinsics, thread_local_state)]
+#![cfg_attr(feature = "valgrind", feature(asm))]
+#![cfg_attr(feature = "hexdump-debug", feature(specialization))]
 #![cfg_attr(test, deny(warnings))]
 #![deny(missing_docs)]
 #![allow(raw_pointer_derive)]
@@ -9,15 +11,159 @@
 //!
 
 extern crate core;
+extern crate libc;
 
+#[macro_use]
+extern crate lazy_static;
+
+#[cfg(feature = "backtrace")]
+extern crate backtrace;
+
+#[cfg(feature = "time")]
+extern crate time;
+
+pub use alloc::{AllocError, CapacityOverflow};
 pub use unique::UniqueBuf;
+pub use secret::SecretBuf;
+pub use slice::{SliceBuf, SpareCapacity, UninitCapacity};
+pub use drop_buf::DropBuf;
+pub use uninit::UninitBuf;
+pub use pinned::PinnedBuf;
+pub use relocate::{RelocatingBuf, RelocateFn};
+pub use handle::{BufIndex, BufOffset};
+pub use relptr::RelPtr;
+pub use sync::{SendMemBuf, SyncMemBuf};
+pub use atomic::{AtomicBuf, AtomicBufGuard, ReclaimFn};
+pub use rcu::{RcuBuf, RcuGuard};
+pub use seqlock::SeqLockBuf;
+pub use append::{ConcurrentAppendBuf, Reservation};
+pub use arena::{Arena, ByteArena, ConcurrentArena};
+pub use sharded::ShardedPool;
+pub use pool::BufPool;
+pub use freelist::FreeList;
+pub use shared::{SharedBuf, WeakBuf, RcBuf};
+pub use cow::CowBuf;
+pub use frozen::FrozenBuf;
+pub use view::BufView;
+pub use lend::BufGuard;
+pub use branded::{BrandedBuf, BrandedIndex, BrandedIndices};
+pub use carve::CarvedBuf;
+pub use aligned::{AlignedBuf, AlignedByteBuf};
+pub use cursor::BufCursor;
+pub use cast::CastError;
+pub use raw_bytes::Endian;
+pub use hexdump::HexDump;
+pub use builder::{MemBufBuilder, Placement, AnyBuf};
+pub use reserve::{set_emergency_reserve, emergency_reserve};
+pub use oom::{set_oom_hook, clear_oom_hook, RetryDecision, MAX_OOM_RETRIES};
+
+#[cfg(feature = "backtraces")]
+pub use registry::dump_outstanding;
+
+#[cfg(feature = "stats")]
+pub use stats::{stats, Stats};
+
+#[cfg(feature = "tags")]
+pub use stats::stats_by_tag;
+
+#[cfg(feature = "budget")]
+pub use budget::{set_budget, clear_budget, budget, set_budget_policy};
+#[cfg(feature = "budget")]
+pub use budget::{set_soft_limit, clear_soft_limit, QuotaGuard};
+
+#[cfg(feature = "profile")]
+pub use profile::{write_profile, save_profile};
+
+#[cfg(feature = "quarantine")]
+pub use quarantine::{set_quarantine_size, quarantined_bytes, flush_quarantine};
+
+#[cfg(feature = "recycler")]
+pub use recycler::{recycler, Recycler, DEFAULT_RECYCLER_SIZE, DEFAULT_CLASS_CAP};
+
+#[cfg(unix)]
+pub use pages::{PageBuf, Advice};
+
+#[cfg(unix)]
+pub use locked::LockedBuf;
 
 use core::nonzero::NonZero;
-use std::ops::Deref;
-use std::mem;
+use std::ops::{Deref, Range};
+use std::{mem, ptr, usize};
+use std::intrinsics;
+
+use backend::{BackendHandle, DEFAULT_BACKEND};
+use registry::Generation;
+use stats::Tag;
 
 pub mod alloc;
+pub mod backend;
+pub mod ffi;
+mod aligned;
+mod append;
+mod arena;
+mod asan;
+mod atomic;
+mod branded;
+mod budget;
+mod builder;
+mod cache;
+mod canary;
+mod carve;
+mod cast;
+mod cow;
+mod cursor;
+mod drop_buf;
+mod freelist;
+mod frozen;
+mod handle;
+mod hexdump;
+mod lend;
+mod oom;
+mod pinned;
+mod poison;
+mod pool;
+mod profile;
+mod raw_bytes;
+mod rcu;
+mod registry;
+mod relocate;
+mod relptr;
+mod reserve;
+mod secret;
+mod seqlock;
+mod shadow;
+mod shared;
+mod sharded;
+mod slice;
+mod stats;
+mod sync;
+mod uninit;
 mod unique;
+mod valgrind;
+mod view;
+
+#[cfg(feature = "detect-double-free")]
+mod origin;
+
+mod os;
+#[cfg(unix)]
+mod pages;
+#[cfg(unix)]
+mod locked;
+#[cfg(unix)]
+pub mod iovec;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod pkey;
+
+#[cfg(feature = "quarantine")]
+mod quarantine;
+
+#[cfg(feature = "recycler")]
+mod recycler;
+
+#[cfg(feature = "replay")]
+pub mod replay;
 
 /// A safe wrapper around a heap allocated buffer of Ts, tracking capacity only.
 ///
@@ -36,7 +182,10 @@ mod unique;
 #[derive(Debug, Hash, PartialEq, Eq)]
 pub struct MemBuf<T> {
     buffer: NonZero<*mut T>,
-    cap: usize
+    cap: usize,
+    generation: Generation,
+    backend: BackendHandle,
+    tag: Tag
 }
 
 impl<T> Clone for MemBuf<T> { fn clone(&self) -> MemBuf<T> { *self } }
@@ -52,9 +201,17 @@ impl<T> MemBuf<T> {
     /// assert_eq!(buffer.capacity(), 0);
     /// ```
     pub fn new() -> MemBuf<T> {
+        MemBuf::new_in(DEFAULT_BACKEND)
+    }
+
+    // An empty buffer which allocates from `backend` once reallocated.
+    fn new_in(backend: BackendHandle) -> MemBuf<T> {
         MemBuf {
             buffer: alloc::empty(),
-            cap: 0
+            cap: 0,
+            generation: Generation::empty(),
+            backend: backend,
+            tag: Tag::none()
         }
     }
 
@@ -69,14 +226,137 @@ impl<T> MemBuf<T> {
     /// assert_eq!(buffer.capacity(), 128);
     /// ```
     pub fn allocate(cap: usize) -> MemBuf<T> {
-        if cap == 0 { return MemBuf::new() }
+        MemBuf::allocate_in(cap, DEFAULT_BACKEND)
+    }
+
+    /// Create a new buffer with space for cap Ts, allocated from `backend`.
+    ///
+    /// The buffer records its backend, and is always reallocated and
+    /// deallocated through it.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    /// use membuf::backend::HEAP_BACKEND;
+    ///
+    /// let mut buffer: MemBuf<u32> = MemBuf::allocate_in(16, HEAP_BACKEND);
+    /// unsafe { buffer.reallocate(64); }
+    /// assert_eq!(buffer.backend(), HEAP_BACKEND);
+    /// # unsafe { buffer.deallocate() }
+    /// ```
+    pub fn allocate_in(cap: usize, backend: BackendHandle) -> MemBuf<T> {
+        if cap == 0 { return MemBuf::new_in(backend) }
+
+        let buffer = unsafe { alloc::allocate_in(backend, NonZero::new(cap)) };
 
         MemBuf {
-            buffer: unsafe { alloc::allocate(NonZero::new(cap)) },
-            cap: cap
+            buffer: buffer,
+            cap: cap,
+            generation: Generation::of(*buffer as *mut u8),
+            backend: backend,
+            tag: Tag::none()
+        }
+    }
+
+    /// Create a new buffer with space for cap Ts, whose memory is counted
+    /// towards `tag` in `stats_by_tag`.
+    ///
+    /// The tag is ignored unless the `tags` feature is enabled, so it can be
+    /// passed unconditionally. The buffer remembers it, so it is carried over
+    /// by `reallocate`, even through a capacity of zero.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<u8> = MemBuf::allocate_tagged(1024, "http::body");
+    /// assert_eq!(buffer.capacity(), 1024);
+    /// # unsafe { buffer.deallocate() }
+    /// ```
+    pub fn allocate_tagged(cap: usize, tag: &'static str) -> MemBuf<T> {
+        MemBuf::allocate_tagged_in(cap, DEFAULT_BACKEND, tag)
+    }
+
+    /// Create a new buffer with space for cap Ts, allocated from `backend`,
+    /// whose memory is counted towards `tag` in `stats_by_tag`.
+    pub fn allocate_tagged_in(cap: usize, backend: BackendHandle,
+                              tag: &'static str) -> MemBuf<T> {
+        let mut buffer: MemBuf<T> = MemBuf::allocate_in(cap, backend);
+        buffer.set_tag(Tag::named(tag));
+        buffer
+    }
+
+    // Allocate a buffer with space for cap Ts from the same backend, and
+    // counted towards the same tag, as this one.
+    fn allocate_like(&self, cap: usize) -> MemBuf<T> {
+        let mut buffer: MemBuf<T> = MemBuf::allocate_in(cap, self.backend);
+        buffer.set_tag(self.tag);
+        buffer
+    }
+
+    // Remember `tag` and count the memory of this buffer towards it.
+    fn set_tag(&mut self, tag: Tag) {
+        self.tag = tag;
+        if self.cap != 0 && mem::size_of::<T>() != 0 {
+            tag.apply(*self.buffer as *mut u8, self.cap * mem::size_of::<T>());
         }
     }
 
+    /// Create a new buffer with space for cap Ts, returning an error instead
+    /// of panicking on capacity overflow.
+    ///
+    /// Running out of memory still aborts, see `try_allocate` to handle that
+    /// as well.
+    ///
+    /// ```
+    /// # use membuf::{CapacityOverflow, MemBuf};
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::checked_allocate(128).unwrap();
+    /// assert_eq!(buffer.capacity(), 128);
+    /// # unsafe { buffer.deallocate() }
+    ///
+    /// let result = MemBuf::<usize>::checked_allocate(10_000_000_000_000_000_000);
+    /// assert_eq!(result, Err(CapacityOverflow));
+    /// ```
+    pub fn checked_allocate(cap: usize) -> Result<MemBuf<T>, CapacityOverflow> {
+        if cap == 0 { return Ok(MemBuf::new()) }
+
+        let buffer = try!(unsafe { alloc::checked_allocate(NonZero::new(cap)) });
+        Ok(unsafe { MemBuf::from_raw(buffer, cap) })
+    }
+
+    /// Create a new buffer with space for cap Ts, returning an error instead
+    /// of panicking or aborting if the allocation fails.
+    ///
+    /// ```
+    /// # use membuf::{AllocError, MemBuf};
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::try_allocate(128).unwrap();
+    /// assert_eq!(buffer.capacity(), 128);
+    /// # unsafe { buffer.deallocate() }
+    ///
+    /// let result = MemBuf::<usize>::try_allocate(10_000_000_000_000_000_000);
+    /// assert_eq!(result, Err(AllocError::CapacityOverflow));
+    /// ```
+    pub fn try_allocate(cap: usize) -> Result<MemBuf<T>, AllocError> {
+        MemBuf::try_allocate_in(cap, DEFAULT_BACKEND)
+    }
+
+    /// Create a new buffer with space for cap Ts, allocated from `backend`,
+    /// returning an error instead of panicking or aborting if the allocation
+    /// fails.
+    pub fn try_allocate_in(cap: usize, backend: BackendHandle) -> Result<MemBuf<T>, AllocError> {
+        if cap == 0 { return Ok(MemBuf::new_in(backend)) }
+
+        let buffer = try!(unsafe { alloc::try_allocate_in(backend, NonZero::new(cap)) });
+
+        Ok(MemBuf {
+            buffer: buffer,
+            cap: cap,
+            generation: Generation::of(*buffer as *mut u8),
+            backend: backend,
+            tag: Tag::none()
+        })
+    }
+
     /// Reallocate this buffer to fit a new number of Ts.
     ///
     /// Unlike `std::rt::heap::reallocate`, cap == 0 is allowed.
@@ -102,7 +382,8 @@ impl<T> MemBuf<T> {
     /// ```
     pub unsafe fn reallocate(&mut self, cap: usize) {
         if self.cap == 0 || cap == 0 {
-            mem::replace(self, MemBuf::allocate(cap)).deallocate();
+            let buffer = self.allocate_like(cap);
+            mem::replace(self, buffer).deallocate();
         } else {
             // We need to set the capacity to 0 because if the capacity
             // overflows unwinding is triggered, which if we don't
@@ -110,23 +391,284 @@ impl<T> MemBuf<T> {
             let old_cap = mem::replace(&mut self.cap, 0);
             let buffer = mem::replace(&mut self.buffer, alloc::empty());
 
-            self.buffer = alloc::reallocate(buffer,
-                                            NonZero::new(old_cap),
-                                            NonZero::new(cap));
+            self.buffer = alloc::reallocate_in(self.backend,
+                                               buffer,
+                                               NonZero::new(old_cap),
+                                               NonZero::new(cap));
             self.cap = cap;
+            self.generation = Generation::of(*self.buffer as *mut u8);
         }
     }
 
+    /// Reallocate this buffer to fit a new number of Ts, returning an error
+    /// instead of panicking on capacity overflow.
+    ///
+    /// Running out of memory still aborts. On capacity overflow the buffer is
+    /// left untouched.
+    ///
+    /// ## Safety
+    ///
+    /// As with `reallocate`, a successful call invalidates the buffer in all
+    /// other `MemBuf`s which share it.
+    ///
+    /// ```
+    /// # use membuf::{CapacityOverflow, MemBuf};
+    ///
+    /// let mut buffer: MemBuf<usize> = MemBuf::allocate(128);
+    ///
+    /// unsafe {
+    ///     assert_eq!(buffer.checked_reallocate(10_000_000_000_000_000_000),
+    ///                Err(CapacityOverflow));
+    ///     assert_eq!(buffer.capacity(), 128);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn checked_reallocate(&mut self, cap: usize) -> Result<(), CapacityOverflow> {
+        match self.try_reallocate(cap) {
+            Ok(()) => Ok(()),
+            Err(AllocError::CapacityOverflow) => Err(CapacityOverflow),
+            Err(AllocError::OutOfMemory) => alloc::oom()
+        }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts, returning an error
+    /// instead of panicking or aborting if the allocation fails.
+    ///
+    /// On failure the buffer is left untouched.
+    ///
+    /// ## Safety
+    ///
+    /// As with `reallocate`, a successful call invalidates the buffer in all
+    /// other `MemBuf`s which share it.
+    ///
+    /// ```
+    /// # use membuf::{AllocError, MemBuf};
+    ///
+    /// let mut buffer: MemBuf<usize> = MemBuf::allocate(128);
+    ///
+    /// unsafe {
+    ///     buffer.try_reallocate(1024).unwrap();
+    ///     assert_eq!(buffer.try_reallocate(10_000_000_000_000_000_000),
+    ///                Err(AllocError::CapacityOverflow));
+    ///     assert_eq!(buffer.capacity(), 1024);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn try_reallocate(&mut self, cap: usize) -> Result<(), AllocError> {
+        if self.cap == 0 || cap == 0 {
+            let mut new: MemBuf<T> = try!(MemBuf::try_allocate_in(cap, self.backend));
+            new.set_tag(self.tag);
+            mem::replace(self, new).deallocate();
+            return Ok(())
+        }
+
+        let result = alloc::try_reallocate_in(self.backend,
+                                              self.buffer,
+                                              NonZero::new(self.cap),
+                                              NonZero::new(cap));
+
+        if let Ok(buffer) = result {
+            self.buffer = buffer;
+            self.cap = cap;
+        }
+
+        // A failed reallocation re-registers the old buffer.
+        self.generation = Generation::of(*self.buffer as *mut u8);
+        result.map(|_| ())
+    }
+
     /// Get the current capacity of the MemBuf.
     ///
+    /// As with `Vec`, buffers of zero-sized types never allocate, and always
+    /// have a capacity of `usize::MAX`.
+    ///
     /// ```
     /// # use membuf::MemBuf;
+    /// use std::usize;
     ///
     /// let buffer: MemBuf<usize> = MemBuf::allocate(128);
     /// assert_eq!(buffer.capacity(), 128);
+    ///
+    /// let buffer: MemBuf<()> = MemBuf::new();
+    /// assert_eq!(buffer.capacity(), usize::MAX);
     /// ```
     pub fn capacity(&self) -> usize {
-        self.cap
+        if mem::size_of::<T>() == 0 { usize::MAX } else { self.cap }
+    }
+
+    /// Get the largest capacity a MemBuf of Ts can be allocated with.
+    ///
+    /// No allocation may be larger than `isize::MAX` bytes, so this is
+    /// `isize::MAX / size_of::<T>()`, or `usize::MAX` for zero-sized types.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    /// use std::isize;
+    ///
+    /// assert_eq!(MemBuf::<u32>::max_capacity(), isize::MAX as usize / 4);
+    /// assert!(MemBuf::<u32>::checked_allocate(MemBuf::<u32>::max_capacity() + 1).is_err());
+    /// ```
+    pub fn max_capacity() -> usize {
+        alloc::max_capacity::<T>()
+    }
+
+    /// Get a pointer to the element at `index`.
+    ///
+    /// Unlike `offset`, which is undefined behaviour for offsets outside of
+    /// the allocation even if the result is never dereferenced, this panics
+    /// if `index` is not less than the capacity of the buffer.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::allocate(8);
+    /// assert_eq!(buffer.ptr_at(3), unsafe { buffer.offset(3) });
+    /// # unsafe { buffer.deallocate() }
+    /// ```
+    pub fn ptr_at(&self, index: usize) -> *mut T {
+        if index >= self.capacity() {
+            panic!("Index {} out of bounds for buffer with capacity {}",
+                   index, self.capacity())
+        }
+
+        unsafe { self.buffer.offset(index as isize) }
+    }
+
+    /// Write `value` into the element at `index`, without reading or dropping
+    /// the old value.
+    ///
+    /// With the `shadow-init` feature, this marks the element as initialized
+    /// for `read_at`.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity of the buffer, which is checked
+    /// in debug builds, and the buffer must not have been deallocated.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     buffer.write_at(3, 12);
+    ///     assert_eq!(buffer.read_at(3), 12);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn write_at(&self, index: usize, value: T) {
+        debug_assert!(index < self.capacity());
+
+        ptr::write(self.buffer.offset(index as isize), value);
+        self.assume_init_range(index..index + 1);
+    }
+
+    /// Read the element at `index`, without moving it out of the buffer.
+    ///
+    /// With the `shadow-init` feature, this panics if the element was not
+    /// previously written with `write_at` or marked with `assume_init_range`.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity of the buffer, which is checked
+    /// in debug builds, the element must be initialized and the buffer must
+    /// not have been deallocated.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     buffer.write_at(0, 4);
+    ///     assert_eq!(buffer.read_at(0), 4);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn read_at(&self, index: usize) -> T {
+        debug_assert!(index < self.capacity());
+
+        let size = mem::size_of::<T>();
+        let start = index * size;
+
+        if !registry::is_initialized(*self.buffer as *mut u8, start, start + size) {
+            panic!("Read of uninitialized element {} of buffer at {:p}", index, *self.buffer)
+        }
+
+        ptr::read(self.buffer.offset(index as isize))
+    }
+
+    /// Write `value` into the element at `index` with a volatile store, which
+    /// the compiler will not elide or reorder with other volatile accesses.
+    ///
+    /// This is for memory which is also accessed from outside the program,
+    /// such as by a device or another process.
+    ///
+    /// ## Safety
+    ///
+    /// As for `write_at`.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<u32> = MemBuf::allocate(4);
+    ///
+    /// unsafe {
+    ///     buffer.write_volatile_at(2, 7);
+    ///     assert_eq!(buffer.read_volatile_at(2), 7);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn write_volatile_at(&self, index: usize, value: T) {
+        debug_assert!(index < self.capacity());
+
+        intrinsics::volatile_store(self.buffer.offset(index as isize), value);
+        self.assume_init_range(index..index + 1);
+    }
+
+    /// Read the element at `index` with a volatile load, which the compiler
+    /// will not elide or reorder with other volatile accesses.
+    ///
+    /// ## Safety
+    ///
+    /// As for `read_at`.
+    pub unsafe fn read_volatile_at(&self, index: usize) -> T {
+        debug_assert!(index < self.capacity());
+
+        let size = mem::size_of::<T>();
+        let start = index * size;
+
+        if !registry::is_initialized(*self.buffer as *mut u8, start, start + size) {
+            panic!("Read of uninitialized element {} of buffer at {:p}", index, *self.buffer)
+        }
+
+        intrinsics::volatile_load(self.buffer.offset(index as isize))
+    }
+
+    /// Mark the elements in `range` as initialized for `read_at`, after they
+    /// were written through the raw pointer.
+    ///
+    /// This is a no-op unless the `shadow-init` feature is enabled.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    /// use std::ptr;
+    ///
+    /// let buffer: MemBuf<usize> = MemBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     ptr::write(buffer.offset(0), 4);
+    ///     buffer.assume_init_range(0..1);
+    ///     assert_eq!(buffer.read_at(0), 4);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn assume_init_range(&self, range: Range<usize>) {
+        debug_assert!(range.start <= range.end && range.end <= self.capacity());
+
+        let size = mem::size_of::<T>();
+        registry::mark_initialized(*self.buffer as *mut u8,
+                                   range.start * size, range.end * size);
     }
 
     /// Deallocate the memory contained within the buffer.
@@ -146,17 +688,132 @@ impl<T> MemBuf<T> {
     ///
     pub unsafe fn deallocate(self) {
         if self.cap == 0 { return }
-        alloc::deallocate(self.buffer, NonZero::new(self.cap));
+        alloc::deallocate_in(self.backend, self.buffer, NonZero::new(self.cap));
+    }
+
+    /// Tell AddressSanitizer and Valgrind that only the first `len` elements
+    /// of this buffer hold live data, so accesses to the rest of its capacity
+    /// are reported.
+    ///
+    /// This is a no-op unless the `asan` or `valgrind` feature is enabled, so
+    /// collections can call it unconditionally whenever their length changes.
+    /// The whole buffer is made accessible again when it is reallocated or
+    /// deallocated.
+    ///
+    /// ## Safety
+    ///
+    /// The buffer must not have been deallocated or reallocated, including
+    /// through another handle, and `len` must be at most the capacity, which
+    /// is checked in debug builds.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<u32> = MemBuf::allocate(16);
+    /// unsafe {
+    ///     buffer.set_unpoisoned_len(4);
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    pub unsafe fn set_unpoisoned_len(&self, len: usize) {
+        debug_assert!(len <= self.capacity());
+
+        if self.cap == 0 { return }
+        alloc::set_unpoisoned_len(self.buffer, NonZero::new(self.cap), len)
+    }
+
+    /// Check whether the canaries around this buffer are intact.
+    ///
+    /// Only available with the `canaries` feature, which surrounds every
+    /// buffer with canary words that are overwritten if code writes just
+    /// outside of the buffer. The canaries are also verified automatically
+    /// on `reallocate` and `deallocate`, which panic if they are damaged.
+    ///
+    /// ## Safety
+    ///
+    /// The buffer must not have been deallocated, including through another
+    /// handle.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<u32> = MemBuf::allocate(16);
+    /// unsafe {
+    ///     assert!(buffer.verify_canaries());
+    ///     buffer.deallocate();
+    /// }
+    /// ```
+    #[cfg(feature = "canaries")]
+    pub unsafe fn verify_canaries(&self) -> bool {
+        if self.cap == 0 { return true }
+        alloc::canaries_intact(self.buffer, NonZero::new(self.cap))
+    }
+
+    /// Check whether this handle still refers to the live allocation, rather
+    /// than one which has since been reallocated or deallocated through
+    /// another handle.
+    ///
+    /// Only available with the `generations` feature, which gives every
+    /// allocation a unique generation number remembered by its handles.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let mut buffer: MemBuf<u32> = MemBuf::allocate(16);
+    /// let copy = buffer;
+    ///
+    /// unsafe { buffer.reallocate(32); }
+    /// assert!(buffer.is_current());
+    /// assert!(!copy.is_current());
+    /// # unsafe { buffer.deallocate() }
+    /// ```
+    #[cfg(feature = "generations")]
+    pub fn is_current(&self) -> bool {
+        self.generation.is_current(*self.buffer as *mut u8)
+    }
+
+    /// Panic if this handle no longer refers to the live allocation.
+    ///
+    /// Only available with the `generations` feature, see `is_current`.
+    ///
+    /// ```
+    /// # use membuf::MemBuf;
+    ///
+    /// let buffer: MemBuf<u32> = MemBuf::allocate(16);
+    /// buffer.assert_current();
+    /// # unsafe { buffer.deallocate() }
+    /// ```
+    #[cfg(feature = "generations")]
+    pub fn assert_current(&self) {
+        if !self.is_current() {
+            panic!("Stale MemBuf: buffer at {:p} has been reallocated or deallocated",
+                   *self.buffer)
+        }
     }
 
     /// Create a MemBuf from a previously allocated data pointer and a
-    /// capacity.
+    /// capacity, allocated from the default backend.
     pub unsafe fn from_raw(data: NonZero<*mut T>, capacity: usize) -> MemBuf<T> {
+        MemBuf::from_raw_in(data, capacity, DEFAULT_BACKEND)
+    }
+
+    /// Create a MemBuf from a previously allocated data pointer and a
+    /// capacity, allocated from `backend`.
+    pub unsafe fn from_raw_in(data: NonZero<*mut T>, capacity: usize,
+                              backend: BackendHandle) -> MemBuf<T> {
         MemBuf {
             buffer: data,
-            cap: capacity
+            cap: capacity,
+            generation: Generation::of(*data as *mut u8),
+            backend: backend,
+            tag: Tag::none()
         }
     }
+
+    /// Get the backend this buffer is allocated from.
+    pub fn backend(&self) -> BackendHandle {
+        self.backend
+    }
 }
 
 impl<T> Deref for MemBuf<T> {
@@ -239,6 +896,103 @@ mod test {
         };
     }
 
+    #[test]
+    #[cfg(feature = "canaries")]
+    fn test_canaries_detect_overrun() {
+        let buffer: MemBuf<u8> = MemBuf::allocate(5);
+
+        unsafe {
+            assert!(buffer.verify_canaries());
+
+            let canary = ptr::read(buffer.offset(5));
+            ptr::write(buffer.offset(5), !canary);
+            assert!(!buffer.verify_canaries());
+
+            ptr::write(buffer.offset(5), canary);
+            assert!(buffer.verify_canaries());
+            buffer.deallocate();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "canaries")]
+    #[should_panic = "Canary overwritten"]
+    fn test_canaries_checked_on_reallocate() {
+        let mut buffer: MemBuf<u64> = MemBuf::allocate(4);
+
+        unsafe {
+            ptr::write(buffer.offset(-1), 0);
+            buffer.reallocate(8);
+        }
+    }
+
+    #[test]
+    fn test_zero_sized_capacity() {
+        let mut buffer: MemBuf<()> = MemBuf::allocate(8);
+        assert_eq!(buffer.capacity(), usize::max_value());
+        assert_eq!(buffer.buffer, empty());
+
+        unsafe {
+            buffer.reallocate(1024);
+            assert_eq!(buffer.capacity(), usize::max_value());
+            assert_eq!(buffer.buffer, empty());
+
+            buffer.write_at(usize::max_value() - 1, ());
+            buffer.read_at(usize::max_value() - 1);
+
+            buffer.reallocate(0);
+            assert_eq!(buffer.capacity(), usize::max_value());
+            buffer.deallocate();
+        }
+
+        let buffer: ::UniqueBuf<()> = ::UniqueBuf::new();
+        assert_eq!(buffer.capacity(), usize::max_value());
+    }
+
+    #[test]
+    fn test_max_capacity_boundary() {
+        use std::isize;
+        use CapacityOverflow;
+
+        assert_eq!(MemBuf::<u8>::max_capacity(), isize::MAX as usize);
+        assert_eq!(MemBuf::<u8>::checked_allocate(isize::MAX as usize + 1),
+                   Err(CapacityOverflow));
+        assert_eq!(MemBuf::<[u8; 3]>::max_capacity(), isize::MAX as usize / 3);
+        assert_eq!(MemBuf::<()>::max_capacity(), usize::max_value());
+
+        let mut buffer: MemBuf<u64> = MemBuf::allocate(4);
+        unsafe {
+            assert_eq!(buffer.checked_reallocate(MemBuf::<u64>::max_capacity() + 1),
+                       Err(CapacityOverflow));
+            assert_eq!(buffer.capacity(), 4);
+            buffer.deallocate();
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "32")]
+    fn test_max_capacity_32_bit() {
+        use AllocError;
+
+        assert_eq!(MemBuf::<u8>::max_capacity(), 0x7fff_ffff);
+        assert_eq!(MemBuf::<u32>::max_capacity(), 0x1fff_ffff);
+
+        // Without the limit these would pass the overflow check and reach
+        // the backend.
+        assert_eq!(MemBuf::<u8>::try_allocate(0x8000_0000).unwrap_err(),
+                   AllocError::CapacityOverflow);
+        assert_eq!(MemBuf::<u32>::try_allocate(0x2000_0000).unwrap_err(),
+                   AllocError::CapacityOverflow);
+    }
+
+    #[test]
+    #[should_panic = "Index 8 out of bounds for buffer with capacity 8"]
+    fn test_ptr_at_out_of_bounds() {
+        let buffer: MemBuf<usize> = MemBuf::allocate(8);
+        assert_eq!(buffer.ptr_at(7), unsafe { buffer.offset(7) });
+        buffer.ptr_at(8);
+    }
+
     #[test]
     #[should_panic = "Capacity overflow."]
     fn test_allocate_capacity_overflow() {
@@ -258,5 +1012,26 @@ mod test {
         let mut buffer: MemBuf<usize> = MemBuf::allocate(128);
         unsafe { buffer.reallocate(10_000_000_000_000_000_000); }
     }
-}
 
+    #[test]
+    fn test_backend_handle_routes_reallocation() {
+        use backend::{self, TrackingAlloc, TRACKING_ALLOC_INIT};
+
+        static TRACKER: TrackingAlloc = TRACKING_ALLOC_INIT;
+        let tracked = unsafe { backend::register_backend("test::tracked", &TRACKER) };
+
+        let mut buffer: MemBuf<u32> = MemBuf::allocate_in(0, tracked);
+        unsafe {
+            buffer.reallocate(16);
+            assert_eq!(TRACKER.live_allocations(), 1);
+
+            // With the quarantine, moved-from memory is only released later.
+            buffer.reallocate(1024);
+            assert!(TRACKER.live_bytes() >= 4096);
+            assert_eq!(buffer.backend(), tracked);
+            buffer.deallocate();
+        }
+
+        TRACKER.assert_no_leaks();
+    }
+}
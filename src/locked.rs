@@ -0,0 +1,124 @@
+use std::ops::Deref;
+use std::{cmp, io, mem, ptr};
+
+use {os, PageBuf};
+
+/// A buffer of Ts which is locked into physical memory, so it is never
+/// written to swap.
+///
+/// `LockedBuf` is useful for buffers holding sensitive data such as
+/// cryptographic keys. Its memory is locked with `mlock` for as long as the
+/// buffer lives, including across `reallocate`, and unlocked when it is
+/// dropped.
+///
+/// The memory is mapped directly from the operating system as whole pages,
+/// like a `PageBuf`, so locking and unlocking never affects other buffers
+/// which would otherwise share a page with this one.
+///
+/// ```
+/// # use membuf::LockedBuf;
+///
+/// let mut buffer: LockedBuf<u8> = LockedBuf::allocate(32).unwrap();
+/// assert_eq!(buffer.capacity(), 32);
+///
+/// buffer.reallocate(4096).unwrap();
+/// assert_eq!(buffer.capacity(), 4096);
+/// ```
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct LockedBuf<T> {
+    inner: PageBuf<T>
+}
+
+impl<T> LockedBuf<T> {
+    /// Create a new, empty LockedBuf.
+    ///
+    /// ```
+    /// # use membuf::LockedBuf;
+    ///
+    /// let buffer: LockedBuf<u8> = LockedBuf::new();
+    /// assert_eq!(buffer.capacity(), 0);
+    /// ```
+    pub fn new() -> LockedBuf<T> {
+        LockedBuf { inner: PageBuf::new() }
+    }
+
+    /// Create a new buffer with space for cap Ts, locked into physical
+    /// memory.
+    ///
+    /// Returns the error from the operating system if the pages cannot be
+    /// mapped or locked, for example when the process would exceed its
+    /// `RLIMIT_MEMLOCK`.
+    pub fn allocate(cap: usize) -> io::Result<LockedBuf<T>> {
+        let inner = try!(PageBuf::try_allocate(cap));
+        try!(lock(&inner));
+        Ok(LockedBuf { inner: inner })
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts.
+    ///
+    /// The new memory is locked before the contents are copied over, so they
+    /// are never in swappable memory. On error the buffer is left untouched.
+    pub fn reallocate(&mut self, cap: usize) -> io::Result<()> {
+        let new = try!(LockedBuf::allocate(cap));
+
+        unsafe {
+            ptr::copy_nonoverlapping(*self.inner, *new.inner,
+                                     cmp::min(self.capacity(), cap));
+        }
+
+        drop(mem::replace(self, new));
+        Ok(())
+    }
+
+    /// Get the current capacity of the LockedBuf.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<T> Drop for LockedBuf<T> {
+    fn drop(&mut self) {
+        let size = self.inner.capacity() * mem::size_of::<T>();
+        if size != 0 {
+            // The pages are only ever locked by this buffer, and unmapping
+            // them straight after unlocks them anyway.
+            let _ = unsafe { os::unlock(*self.inner as *mut u8, size) };
+        }
+    }
+}
+
+impl<T> Deref for LockedBuf<T> {
+    type Target = *mut T;
+
+    fn deref(&self) -> &*mut T { &*self.inner }
+}
+
+fn lock<T>(buffer: &PageBuf<T>) -> io::Result<()> {
+    let size = buffer.capacity() * mem::size_of::<T>();
+    if size == 0 { return Ok(()) }
+
+    unsafe { os::lock(**buffer as *mut u8, size) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use LockedBuf;
+
+    #[test]
+    fn test_reallocate_keeps_data() {
+        let mut buffer: LockedBuf<u64> = LockedBuf::allocate(4).unwrap();
+
+        unsafe {
+            ptr::write(buffer.offset(0), 0xdead);
+            ptr::write(buffer.offset(3), 0xbeef);
+        }
+
+        buffer.reallocate(1024).unwrap();
+
+        unsafe {
+            assert_eq!(ptr::read(buffer.offset(0)), 0xdead);
+            assert_eq!(ptr::read(buffer.offset(3)), 0xbeef);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! A hook to recover from allocation failure.
+//!
+//! When an allocation through `alloc` fails, the hook set with
+//! `set_oom_hook` is invoked with the layout of the failed request. It can
+//! free memory, for instance by dropping caches, and ask for the allocation
+//! to be retried. After `MAX_OOM_RETRIES` retries, or once the hook gives
+//! up, the emergency reserve is released if there is one, and failing that
+//! the allocation fails.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use alloc::Layout;
+
+/// The maximum number of times a single allocation is retried at the
+/// request of the hook.
+pub const MAX_OOM_RETRIES: usize = 4;
+
+/// What to do after the hook has run.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Try the allocation again, since memory has been freed.
+    Retry,
+    /// Give up on the allocation.
+    Fail
+}
+
+static HOOK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set a hook invoked with the layout of every failed allocation, before it
+/// is given up on.
+///
+/// The hook may itself allocate and deallocate, but a failed allocation
+/// within the hook invokes it again.
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+/// use membuf::{RetryDecision, UniqueBuf};
+/// use membuf::alloc::Layout;
+/// use membuf::backend::{self, FailingAlloc, FAILING_ALLOC_INIT};
+///
+/// static FAILING: FailingAlloc = FAILING_ALLOC_INIT;
+/// static CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+///
+/// fn drop_tiles(_: Layout) -> RetryDecision {
+///     CALLS.fetch_add(1, Ordering::SeqCst);
+///     RetryDecision::Retry
+/// }
+///
+/// unsafe { backend::set_backend(&FAILING) }
+/// membuf::set_oom_hook(drop_tiles);
+///
+/// FAILING.fail_nth_allocation(1);
+/// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(4096);
+/// assert_eq!(buffer.capacity(), 4096);
+/// assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+/// ```
+pub fn set_oom_hook(hook: fn(Layout) -> RetryDecision) {
+    HOOK.store(hook as usize, Ordering::SeqCst);
+}
+
+/// Remove the hook set with `set_oom_hook`.
+pub fn clear_oom_hook() {
+    HOOK.store(0, Ordering::SeqCst);
+}
+
+/// Should an allocation of `layout` which has failed `attempts` times be
+/// retried?
+pub fn should_retry(layout: Layout, attempts: usize) -> bool {
+    if attempts > MAX_OOM_RETRIES { return false }
+
+    match HOOK.load(Ordering::SeqCst) {
+        0 => false,
+        hook => {
+            let hook: fn(Layout) -> RetryDecision = unsafe { mem::transmute(hook) };
+            hook(layout) == RetryDecision::Retry
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Capture of where allocations were made.
+//!
+//! With the `backtraces` feature enabled, the registry records a backtrace
+//! for every allocation when it is allocated or reallocated, and
+//! `membuf::dump_outstanding` prints the origin of every live allocation,
+//! which makes tracking down leaked buffers much easier.
+//!
+//! Without the feature `Origin` is zero-sized and records nothing.
+
+#[cfg(feature = "backtraces")]
+use std::fmt;
+
+#[cfg(feature = "backtraces")]
+use backtrace::Backtrace;
+
+/// Where an allocation was made.
+#[cfg(feature = "backtraces")]
+pub struct Origin {
+    backtrace: Backtrace
+}
+
+#[cfg(feature = "backtraces")]
+impl Origin {
+    /// Capture the origin of an allocation being made right now.
+    pub fn capture() -> Origin {
+        Origin { backtrace: Backtrace::new() }
+    }
+}
+
+#[cfg(feature = "backtraces")]
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.backtrace, f)
+    }
+}
+
+/// Where an allocation was made.
+#[cfg(not(feature = "backtraces"))]
+pub struct Origin;
+
+#[cfg(not(feature = "backtraces"))]
+impl Origin {
+    /// Capture the origin of an allocation being made right now.
+    #[inline(always)]
+    pub fn capture() -> Origin { Origin }
+}
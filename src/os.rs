@@ -0,0 +1,95 @@
+//! Thin wrappers around the operating system's virtual memory APIs.
+//!
+//! Everything in here works in terms of raw bytes and whole pages, the typed
+//! buffers built on top are responsible for size calculations.
+
+#[cfg(unix)]
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use std::io;
+
+#[cfg(unix)]
+use libc;
+
+#[cfg(unix)]
+static PAGE_SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The size of a page of virtual memory on this system.
+#[cfg(unix)]
+pub fn page_size() -> usize {
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            PAGE_SIZE.store(size, Ordering::Relaxed);
+            size
+        },
+        size => size
+    }
+}
+
+/// Round `size` up to the next multiple of the page size.
+#[cfg(unix)]
+pub fn round_to_page(size: usize) -> usize {
+    let page = page_size();
+    size.checked_add(page - 1).expect("Capacity overflow") & !(page - 1)
+}
+
+/// Map `size` bytes of fresh, zeroed, readable and writable memory.
+///
+/// Returns a null pointer on failure.
+#[cfg(unix)]
+pub unsafe fn map(size: usize) -> *mut u8 {
+    let ptr = libc::mmap(ptr::null_mut(), size,
+                         libc::PROT_READ | libc::PROT_WRITE,
+                         libc::MAP_PRIVATE | libc::MAP_ANON,
+                         -1, 0);
+
+    if ptr == libc::MAP_FAILED { ptr::null_mut() } else { ptr as *mut u8 }
+}
+
+/// Unmap a region previously mapped with `map`.
+#[cfg(unix)]
+pub unsafe fn unmap(ptr: *mut u8, size: usize) {
+    libc::munmap(ptr as *mut libc::c_void, size);
+}
+
+/// Make a page-aligned region completely inaccessible.
+#[cfg(unix)]
+pub unsafe fn protect_none(ptr: *mut u8, size: usize) -> io::Result<()> {
+    cvt(libc::mprotect(ptr as *mut libc::c_void, size, libc::PROT_NONE))
+}
+
+/// Advise the kernel about how a page-aligned region will be accessed.
+#[cfg(unix)]
+pub unsafe fn advise(ptr: *mut u8, size: usize, advice: libc::c_int) -> io::Result<()> {
+    cvt(libc::madvise(ptr as *mut libc::c_void, size, advice))
+}
+
+/// Lock the pages containing `size` bytes at `ptr` into physical memory.
+#[cfg(unix)]
+pub unsafe fn lock(ptr: *mut u8, size: usize) -> io::Result<()> {
+    let (start, len) = page_range(ptr, size);
+    cvt(libc::mlock(start as *const libc::c_void, len))
+}
+
+/// Unlock pages previously locked with `lock`.
+#[cfg(unix)]
+pub unsafe fn unlock(ptr: *mut u8, size: usize) -> io::Result<()> {
+    let (start, len) = page_range(ptr, size);
+    cvt(libc::munlock(start as *const libc::c_void, len))
+}
+
+/// Expand a region to the smallest enclosing range of whole pages.
+#[cfg(unix)]
+fn page_range(ptr: *mut u8, size: usize) -> (usize, usize) {
+    let start = ptr as usize & !(page_size() - 1);
+    let end = round_to_page(ptr as usize + size);
+    (start, end - start)
+}
+
+#[cfg(unix)]
+fn cvt(ret: libc::c_int) -> io::Result<()> {
+    if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
@@ -0,0 +1,361 @@
+use core::nonzero::NonZero;
+use std::ops::Deref;
+use std::{cmp, io, mem, ptr};
+use std::intrinsics;
+
+use libc;
+
+use alloc;
+use os;
+
+/// Hints about how the memory of a `PageBuf` is going to be accessed.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Advice {
+    /// No special treatment, the default.
+    Normal,
+    /// Pages will be accessed in order, so can be read ahead aggressively and
+    /// dropped soon after they are accessed.
+    Sequential,
+    /// Pages will be accessed in random order, so read ahead is not useful.
+    Random,
+    /// Pages will be accessed soon, so should be faulted in ahead of time.
+    WillNeed,
+    /// Pages will not be accessed soon, so their memory can be released.
+    ///
+    /// On Linux the contents of the buffer are discarded, and read back as
+    /// zeroes.
+    DontNeed
+}
+
+impl Advice {
+    fn to_raw(self) -> libc::c_int {
+        match self {
+            Advice::Normal => libc::MADV_NORMAL,
+            Advice::Sequential => libc::MADV_SEQUENTIAL,
+            Advice::Random => libc::MADV_RANDOM,
+            Advice::WillNeed => libc::MADV_WILLNEED,
+            Advice::DontNeed => libc::MADV_DONTNEED
+        }
+    }
+}
+
+/// A buffer of Ts backed directly by pages mapped from the operating system.
+///
+/// Like `UniqueBuf`, `PageBuf` makes no promises about the contents of its
+/// memory and does not run destructors, but because it bypasses the heap
+/// its memory is always page-aligned, starts out zeroed, and can optionally
+/// be surrounded by guard pages.
+///
+/// A guarded `PageBuf` has an inaccessible page mapped directly before and
+/// after its usable region, so pointer arithmetic which strays outside of
+/// the buffer faults immediately instead of silently corrupting a neighbour.
+/// Note that the usable region is rounded up to a whole number of pages, so
+/// accesses slightly past `capacity()` may still land in accessible memory.
+///
+/// Building with the `guard-pages` feature makes every `PageBuf` guarded.
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct PageBuf<T> {
+    buffer: NonZero<*mut T>,
+    cap: usize,
+    guarded: bool
+}
+
+unsafe impl<T: Send> Send for PageBuf<T> {}
+unsafe impl<T: Sync> Sync for PageBuf<T> {}
+
+impl<T> PageBuf<T> {
+    /// Create a new, empty PageBuf.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::new();
+    /// assert_eq!(buffer.capacity(), 0);
+    /// ```
+    pub fn new() -> PageBuf<T> {
+        PageBuf {
+            buffer: alloc::empty(),
+            cap: 0,
+            guarded: cfg!(feature = "guard-pages")
+        }
+    }
+
+    /// Create a new buffer with space for cap Ts.
+    ///
+    /// The buffer is guarded only if the `guard-pages` feature is enabled.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::allocate(128);
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn allocate(cap: usize) -> PageBuf<T> {
+        PageBuf::allocate_with(cap, cfg!(feature = "guard-pages"))
+    }
+
+    /// Create a new buffer with space for cap Ts, surrounded by guard pages.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::allocate_guarded(128);
+    /// assert_eq!(buffer.capacity(), 128);
+    /// assert!(buffer.is_guarded());
+    /// ```
+    pub fn allocate_guarded(cap: usize) -> PageBuf<T> {
+        PageBuf::allocate_with(cap, true)
+    }
+
+    /// Create a new buffer with space for cap Ts, returning the error from
+    /// the operating system if the pages cannot be mapped.
+    ///
+    /// The buffer is guarded only if the `guard-pages` feature is enabled.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::try_allocate(128).unwrap();
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn try_allocate(cap: usize) -> io::Result<PageBuf<T>> {
+        PageBuf::try_allocate_with(cap, cfg!(feature = "guard-pages"))
+    }
+
+    /// Create a new buffer with space for cap Ts, surrounded by guard pages,
+    /// returning the error from the operating system if the pages cannot be
+    /// mapped or protected.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::try_allocate_guarded(128).unwrap();
+    /// assert!(buffer.is_guarded());
+    /// ```
+    pub fn try_allocate_guarded(cap: usize) -> io::Result<PageBuf<T>> {
+        PageBuf::try_allocate_with(cap, true)
+    }
+
+    fn allocate_with(cap: usize, guarded: bool) -> PageBuf<T> {
+        PageBuf::try_allocate_with(cap, guarded).unwrap_or_else(|_| alloc::oom())
+    }
+
+    fn try_allocate_with(cap: usize, guarded: bool) -> io::Result<PageBuf<T>> {
+        Ok(PageBuf {
+            buffer: try!(unsafe { map::<T>(cap, guarded) }),
+            cap: cap,
+            guarded: guarded
+        })
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts.
+    ///
+    /// The contents are copied into a fresh mapping, which keeps the guard
+    /// pages of the old buffer if it had any.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let mut buffer: PageBuf<usize> = PageBuf::allocate(128);
+    /// assert_eq!(buffer.capacity(), 128);
+    ///
+    /// buffer.reallocate(1024);
+    /// assert_eq!(buffer.capacity(), 1024);
+    /// ```
+    pub fn reallocate(&mut self, cap: usize) {
+        let new = PageBuf::allocate_with(cap, self.guarded);
+
+        unsafe {
+            ptr::copy_nonoverlapping(*self.buffer, *new.buffer,
+                                     cmp::min(self.cap, cap));
+        }
+
+        drop(mem::replace(self, new));
+    }
+
+    /// Get the current capacity of the PageBuf.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::allocate(128);
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Is this buffer surrounded by guard pages?
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let buffer: PageBuf<usize> = PageBuf::allocate_guarded(128);
+    /// assert!(buffer.is_guarded());
+    /// ```
+    pub fn is_guarded(&self) -> bool {
+        self.guarded
+    }
+
+    /// Write `value` into the element at `index` with a volatile store, which
+    /// the compiler will not elide or reorder with other volatile accesses.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity, which is checked in debug
+    /// builds. The old value is not dropped.
+    ///
+    /// ```
+    /// # use membuf::PageBuf;
+    ///
+    /// let mut buffer: PageBuf<u32> = PageBuf::allocate(1024);
+    ///
+    /// unsafe {
+    ///     buffer.write_volatile_at(1000, 7);
+    ///     assert_eq!(buffer.read_volatile_at(1000), 7);
+    /// }
+    /// ```
+    pub unsafe fn write_volatile_at(&mut self, index: usize, value: T) {
+        debug_assert!(index < self.cap);
+        intrinsics::volatile_store(self.buffer.offset(index as isize), value)
+    }
+
+    /// Read the element at `index` with a volatile load.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity, which is checked in debug
+    /// builds, and the element must be initialized.
+    pub unsafe fn read_volatile_at(&self, index: usize) -> T {
+        debug_assert!(index < self.cap);
+        intrinsics::volatile_load(self.buffer.offset(index as isize))
+    }
+
+    /// Advise the operating system about how this buffer will be accessed.
+    ///
+    /// The advice is not carried over by `reallocate`.
+    ///
+    /// ```
+    /// # use membuf::{Advice, PageBuf};
+    ///
+    /// let buffer: PageBuf<u64> = PageBuf::allocate(4096);
+    /// buffer.advise(Advice::Sequential).unwrap();
+    /// ```
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        let size = os::round_to_page(self.cap * mem::size_of::<T>());
+        if size == 0 { return Ok(()) }
+
+        unsafe { os::advise(*self.buffer as *mut u8, size, advice.to_raw()) }
+    }
+}
+
+impl<T> Drop for PageBuf<T> {
+    fn drop(&mut self) {
+        unsafe { unmap(self.buffer, self.cap, self.guarded) }
+    }
+}
+
+impl<T> Deref for PageBuf<T> {
+    type Target = *mut T;
+
+    fn deref(&self) -> &*mut T { &*self.buffer }
+}
+
+/// The number of bytes on either side of a mapping used for guard pages.
+fn guard_size(guarded: bool) -> usize {
+    if guarded { os::page_size() } else { 0 }
+}
+
+/// The total number of bytes to map for cap Ts, including guard pages.
+fn mapping_size<T>(cap: usize, guarded: bool) -> usize {
+    let size = mem::size_of::<T>().checked_mul(cap).expect("Capacity overflow");
+    os::round_to_page(size).checked_add(2 * guard_size(guarded))
+        .expect("Capacity overflow")
+}
+
+unsafe fn map<T>(cap: usize, guarded: bool) -> io::Result<NonZero<*mut T>> {
+    if cap == 0 || mem::size_of::<T>() == 0 { return Ok(alloc::empty()) }
+
+    let size = mapping_size::<T>(cap, guarded);
+    let guard = guard_size(guarded);
+
+    let base = os::map(size);
+    if base.is_null() { return Err(io::Error::last_os_error()) }
+
+    if guarded {
+        let protected = os::protect_none(base, guard).and_then(|()| {
+            os::protect_none(base.offset((size - guard) as isize), guard)
+        });
+
+        if let Err(error) = protected {
+            os::unmap(base, size);
+            return Err(error)
+        }
+    }
+
+    Ok(NonZero::new(base.offset(guard as isize) as *mut T))
+}
+
+unsafe fn unmap<T>(buffer: NonZero<*mut T>, cap: usize, guarded: bool) {
+    if cap == 0 || mem::size_of::<T>() == 0 { return }
+
+    let guard = guard_size(guarded);
+    let base = (*buffer as *mut u8).offset(-(guard as isize));
+
+    release(base, mapping_size::<T>(cap, guarded))
+}
+
+#[cfg(not(feature = "quarantine"))]
+unsafe fn release(base: *mut u8, size: usize) {
+    os::unmap(base, size)
+}
+
+#[cfg(feature = "quarantine")]
+unsafe fn release(base: *mut u8, size: usize) {
+    ::quarantine::quarantine_pages(base, size)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use os;
+    use {Advice, PageBuf};
+
+    #[test]
+    fn test_page_aligned() {
+        let buffer: PageBuf<u8> = PageBuf::allocate_guarded(3);
+        assert_eq!(*buffer as usize % os::page_size(), 0);
+
+        let buffer: PageBuf<u8> = PageBuf::allocate(3);
+        assert_eq!(*buffer as usize % os::page_size(), 0);
+    }
+
+    #[test]
+    fn test_reallocate_keeps_data() {
+        let mut buffer: PageBuf<usize> = PageBuf::allocate_guarded(8);
+
+        unsafe {
+            ptr::write(buffer.offset(0), 8);
+            ptr::write(buffer.offset(7), 6);
+        }
+
+        buffer.reallocate(4096);
+        assert!(buffer.is_guarded());
+
+        unsafe {
+            assert_eq!(ptr::read(buffer.offset(0)), 8);
+            assert_eq!(ptr::read(buffer.offset(7)), 6);
+            ptr::write(buffer.offset(4095), 12);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_advise_dont_need_zeroes() {
+        let buffer: PageBuf<usize> = PageBuf::allocate(4096);
+
+        unsafe { ptr::write(buffer.offset(0), 8); }
+        buffer.advise(Advice::DontNeed).unwrap();
+        unsafe { assert_eq!(ptr::read(buffer.offset(0)), 0); }
+    }
+}
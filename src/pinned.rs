@@ -0,0 +1,113 @@
+use std::cell::Cell;
+use std::{ptr, slice};
+
+use UniqueBuf;
+
+/// A buffer of Ts whose elements never move.
+///
+/// `PinnedBuf` has a fixed capacity and no way to reallocate: once it is
+/// full, `push` fails and gives the value back, so the caller can chain on
+/// another `PinnedBuf`. Because the memory is never moved, raw pointers and
+/// the references returned by `push` stay valid for as long as the buffer
+/// lives, which is what intrusive and self-referential structures need.
+///
+/// The pushed elements are dropped along with the buffer.
+///
+/// ```
+/// # use membuf::PinnedBuf;
+///
+/// let nodes: PinnedBuf<(u32, *const u32)> = PinnedBuf::allocate(2);
+///
+/// let first = nodes.push((1, 0 as *const u32)).unwrap();
+/// let second = nodes.push((2, &first.0)).unwrap();
+/// assert!(nodes.push((3, &second.0)).is_err());
+///
+/// assert_eq!(unsafe { *second.1 }, 1);
+/// ```
+#[derive(Debug)]
+pub struct PinnedBuf<T> {
+    buffer: UniqueBuf<T>,
+    len: Cell<usize>
+}
+
+impl<T> PinnedBuf<T> {
+    /// Create a new buffer with space for exactly cap Ts.
+    pub fn allocate(cap: usize) -> PinnedBuf<T> {
+        PinnedBuf { buffer: UniqueBuf::allocate(cap), len: Cell::new(0) }
+    }
+
+    /// Get the number of elements which have been pushed.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Get the capacity, which never changes.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Move `value` into the next free element, returning a reference to it
+    /// which lives as long as the buffer.
+    ///
+    /// Returns the value back if the buffer is full.
+    pub fn push(&self, value: T) -> Result<&mut T, T> {
+        let len = self.len.get();
+        if len == self.capacity() { return Err(value) }
+
+        self.len.set(len + 1);
+        unsafe {
+            let slot = self.buffer.offset(len as isize);
+            ptr::write(slot, value);
+            Ok(&mut *slot)
+        }
+    }
+
+    /// Get a pointer to the element at `index`, which stays valid for as
+    /// long as the buffer lives.
+    ///
+    /// Panics if `index` is not less than the capacity.
+    pub fn ptr_at(&self, index: usize) -> *mut T {
+        self.buffer.ptr_at(index)
+    }
+
+    /// View the pushed elements as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(*self.buffer, self.len.get()) }
+    }
+}
+
+impl<T> Drop for PinnedBuf<T> {
+    fn drop(&mut self) {
+        // Shorten the buffer first, so a panicking destructor leaks the rest
+        // of the elements instead of dropping them twice.
+        while self.len.get() > 0 {
+            let len = self.len.get() - 1;
+            self.len.set(len);
+            unsafe { ptr::read(self.buffer.offset(len as isize)); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use PinnedBuf;
+
+    #[test]
+    fn test_elements_stay_put_and_drop() {
+        let counted = Rc::new(());
+        let buffer: PinnedBuf<Rc<()>> = PinnedBuf::allocate(3);
+
+        let first = buffer.push(counted.clone()).unwrap() as *const Rc<()>;
+        buffer.push(counted.clone()).unwrap();
+        assert_eq!(first, buffer.ptr_at(0) as *const Rc<()>);
+        assert_eq!(buffer.len(), 2);
+
+        let mut buffer = buffer;
+        assert_eq!(buffer.as_mut_slice().len(), 2);
+        assert_eq!(Rc::strong_count(&counted), 3);
+        drop(buffer);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+}
@@ -0,0 +1,49 @@
+//! Poisoning of released heap memory.
+//!
+//! With the `poison` or `quarantine` features enabled, heap memory is filled
+//! with a repeating `0xDEADBEEF` pattern just before it is released, so a
+//! stale handle which reads it after it was deallocated or moved by
+//! `reallocate` sees obviously bogus data instead of plausible old values.
+//!
+//! Without either feature every function in here is a no-op.
+
+use std::intrinsics;
+
+/// The pattern released memory is filled with, starting from its first byte.
+pub const PATTERN: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+/// Is poisoning enabled?
+pub fn enabled() -> bool {
+    cfg!(any(feature = "poison", feature = "quarantine"))
+}
+
+/// Fill `size` bytes at `ptr` with the poison pattern.
+pub unsafe fn fill(ptr: *mut u8, size: usize) {
+    if !enabled() { return }
+
+    // Use volatile stores so the writes are not optimized out as dead
+    // stores to memory which is about to be freed.
+    for i in 0..size {
+        intrinsics::volatile_store(ptr.offset(i as isize), PATTERN[i % 4]);
+    }
+}
+
+/// Check whether `size` bytes at `ptr` are filled with the poison pattern.
+#[cfg(test)]
+pub unsafe fn is_poisoned(ptr: *const u8, size: usize) -> bool {
+    (0..size).all(|i| *ptr.offset(i as isize) == PATTERN[i % 4])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{enabled, fill, is_poisoned};
+
+    #[test]
+    fn test_fill() {
+        let mut bytes = [0u8; 7];
+        unsafe {
+            fill(bytes.as_mut_ptr(), 7);
+            assert_eq!(is_poisoned(bytes.as_ptr(), 7), enabled());
+        }
+    }
+}
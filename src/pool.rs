@@ -0,0 +1,219 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use UniqueBuf;
+
+/// A bounded, lock-free pool of buffers, shared by any number of threads.
+///
+/// Released buffers are kept in a fixed-size ring, which threads push to
+/// and pop from with compare-and-swap alone, so neither `acquire` nor
+/// `release` ever blocks or touches the allocator while the pool has room
+/// and buffers to spare.
+///
+/// Every buffer handed out has at least the pool's buffer capacity. Users
+/// may grow buffers while they hold them; on release, buffers smaller than
+/// the buffer capacity or larger than the maximum capacity are freed rather
+/// than pooled, so one oversized request does not pin its memory forever.
+///
+/// ```
+/// # use membuf::BufPool;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let pool: Arc<BufPool<u8>> = Arc::new(BufPool::new(16, 4096));
+///
+/// let threads: Vec<_> = (0..4).map(|_| {
+///     let pool = pool.clone();
+///     thread::spawn(move || for _ in 0..100 {
+///         let buffer = pool.acquire();
+///         assert!(buffer.capacity() >= 4096);
+///         pool.release(buffer);
+///     })
+/// }).collect();
+/// for thread in threads { thread.join().unwrap(); }
+///
+/// assert!(pool.pooled() <= 4);
+/// ```
+pub struct BufPool<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    push: AtomicUsize,
+    pop: AtomicUsize,
+    buffer_cap: usize,
+    max_cap: usize
+}
+
+unsafe impl<T: Send> Send for BufPool<T> {}
+unsafe impl<T: Send> Sync for BufPool<T> {}
+
+// A place in the ring. The sequence number says whose turn it is: a pusher
+// at position `pos` waits for `pos`, and a popper for `pos + 1`.
+struct Slot<T> {
+    seq: AtomicUsize,
+    buffer: UnsafeCell<Option<UniqueBuf<T>>>
+}
+
+impl<T> BufPool<T> {
+    /// Create a pool which keeps up to `slots` buffers, rounded up to a power
+    /// of two, and hands out buffers with capacity for `buffer_cap` Ts.
+    ///
+    /// Buffers larger than `buffer_cap` are freed on release, unless
+    /// `set_max_capacity` allows them.
+    pub fn new(slots: usize, buffer_cap: usize) -> BufPool<T> {
+        let slots = slots.next_power_of_two();
+
+        BufPool {
+            slots: (0..slots).map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                buffer: UnsafeCell::new(None)
+            }).collect::<Vec<_>>().into_boxed_slice(),
+            mask: slots - 1,
+            push: AtomicUsize::new(0),
+            pop: AtomicUsize::new(0),
+            buffer_cap: buffer_cap,
+            max_cap: buffer_cap
+        }
+    }
+
+    /// Keep released buffers with capacity for up to `max` Ts, instead of
+    /// freeing those which grew beyond the buffer capacity.
+    pub fn set_max_capacity(&mut self, max: usize) {
+        self.max_cap = max;
+    }
+
+    /// Take a buffer from the pool, allocating one if it is empty.
+    ///
+    /// The contents of a reused buffer are whatever its last user left.
+    pub fn acquire(&self) -> UniqueBuf<T> {
+        self.pop().unwrap_or_else(|| UniqueBuf::allocate(self.buffer_cap))
+    }
+
+    /// Return a buffer to the pool.
+    ///
+    /// The buffer is freed if its capacity is outside the pool's limits, or
+    /// if the pool is full.
+    pub fn release(&self, buffer: UniqueBuf<T>) {
+        let cap = buffer.capacity();
+        if cap >= self.buffer_cap && cap <= self.max_cap { self.push(buffer) }
+    }
+
+    /// Get the number of buffers in the pool.
+    ///
+    /// The count is only a snapshot while other threads use the pool.
+    pub fn pooled(&self) -> usize {
+        let pop = self.pop.load(Ordering::SeqCst);
+        self.push.load(Ordering::SeqCst).wrapping_sub(pop)
+    }
+
+    fn push(&self, buffer: UniqueBuf<T>) {
+        let mut pos = self.push.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            if seq == pos {
+                let next = pos.wrapping_add(1);
+                let current = self.push.compare_and_swap(pos, next, Ordering::Relaxed);
+                if current == pos {
+                    unsafe { *slot.buffer.get() = Some(buffer) }
+                    slot.seq.store(next, Ordering::Release);
+                    return
+                }
+                pos = current;
+            } else if (seq.wrapping_sub(pos) as isize) < 0 {
+                // The slot still holds a buffer from a lap ago: the pool is
+                // full, so the buffer is freed.
+                return
+            } else {
+                pos = self.push.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<UniqueBuf<T>> {
+        let mut pos = self.pop.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let next = pos.wrapping_add(1);
+
+            if seq == next {
+                let current = self.pop.compare_and_swap(pos, next, Ordering::Relaxed);
+                if current == pos {
+                    let buffer = unsafe { (*slot.buffer.get()).take() };
+                    slot.seq.store(pos.wrapping_add(self.mask + 1), Ordering::Release);
+                    return buffer
+                }
+                pos = current;
+            } else if (seq.wrapping_sub(next) as isize) < 0 {
+                // Nothing has been pushed to this slot yet: the pool is empty.
+                return None
+            } else {
+                pos = self.pop.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use {BufPool, UniqueBuf};
+
+    #[test]
+    fn test_fifo_and_bounds() {
+        let mut pool: BufPool<u32> = BufPool::new(3, 8);
+        pool.set_max_capacity(16);
+        assert_eq!(pool.pooled(), 0);
+
+        let addresses: Vec<usize> = (0..5).map(|i| {
+            let buffer = UniqueBuf::allocate(8 + i);
+            let address = *buffer as usize;
+            pool.release(buffer);
+            address
+        }).collect();
+
+        // Four slots, so the fifth buffer was freed.
+        assert_eq!(pool.pooled(), 4);
+        for address in &addresses[..4] { assert_eq!(*pool.acquire() as usize, *address); }
+
+        pool.release(UniqueBuf::allocate(4));
+        pool.release(UniqueBuf::allocate(17));
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn test_no_buffer_is_handed_out_twice() {
+        let pool: Arc<BufPool<u8>> = Arc::new(BufPool::new(64, 1));
+        for _ in 0..64 { pool.release(UniqueBuf::allocate(1)); }
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let mut held = Vec::new();
+                for round in 0..1000 {
+                    if round % 3 != 2 {
+                        held.push(pool.acquire());
+                    } else if let Some(buffer) = held.pop() {
+                        pool.release(buffer);
+                    }
+                }
+                held
+            })
+        }).collect();
+
+        // Keep every buffer alive until all are checked, so no address is
+        // freed and handed out again meanwhile.
+        let held: Vec<Vec<UniqueBuf<u8>>> = threads.into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+
+        let mut seen = HashSet::new();
+        for buffer in held.iter().flat_map(|held| held.iter()) {
+            assert!(seen.insert(**buffer as usize));
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Heap profiling in the format of DHAT.
+//!
+//! With the `profile` feature enabled, every allocation made through `alloc`
+//! is attributed to the call stack it was made from, and the total, peak and
+//! final bytes and blocks, and the lifetimes of blocks, are accumulated for
+//! each call stack. `write_profile` serializes the result as JSON which can
+//! be loaded into DHAT's viewer, `dh_view.html`, for offline analysis.
+//!
+//! Capturing a backtrace for every allocation is slow, so this is meant for
+//! profiling runs rather than production.
+//!
+//! Without the feature every function in here is a no-op.
+
+#[cfg(feature = "profile")]
+use std::collections::HashMap;
+#[cfg(feature = "profile")]
+use std::fs::File;
+#[cfg(feature = "profile")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "profile")]
+use std::path::Path;
+#[cfg(feature = "profile")]
+use std::sync::Mutex;
+#[cfg(feature = "profile")]
+use std::env;
+
+#[cfg(feature = "profile")]
+use backtrace;
+#[cfg(feature = "profile")]
+use libc;
+#[cfg(feature = "profile")]
+use time;
+
+/// Statistics for all allocations made from one call stack.
+#[cfg(feature = "profile")]
+#[derive(Default)]
+struct Point {
+    frames: Vec<usize>,
+    total_bytes: usize,
+    total_blocks: usize,
+    total_lifetime: u64,
+    live_bytes: usize,
+    live_blocks: usize,
+    max_bytes: usize,
+    max_blocks: usize,
+    peak_bytes: usize,
+    peak_blocks: usize
+}
+
+#[cfg(feature = "profile")]
+impl Point {
+    fn grow(&mut self, size: usize, blocks: usize) {
+        self.live_bytes += size;
+        self.live_blocks += blocks;
+
+        if self.live_bytes > self.max_bytes {
+            self.max_bytes = self.live_bytes;
+            self.max_blocks = self.live_blocks;
+        }
+    }
+}
+
+/// A live allocation.
+#[cfg(feature = "profile")]
+struct Block {
+    point: usize,
+    size: usize,
+    start: u64
+}
+
+#[cfg(feature = "profile")]
+struct Profile {
+    start: u64,
+    points: Vec<Point>,
+    index: HashMap<Vec<usize>, usize>,
+    live: HashMap<usize, Block>,
+    live_bytes: usize,
+    peak_bytes: usize,
+    peak_time: u64
+}
+
+#[cfg(feature = "profile")]
+impl Profile {
+    fn point(&mut self, frames: Vec<usize>) -> usize {
+        if let Some(&point) = self.index.get(&frames) { return point }
+
+        let point = self.points.len();
+        self.index.insert(frames.clone(), point);
+        self.points.push(Point { frames: frames, ..Point::default() });
+        point
+    }
+
+    fn grow(&mut self, point: usize, size: usize, blocks: usize, now: u64) {
+        self.points[point].grow(size, blocks);
+        self.live_bytes += size;
+
+        // Snapshot every point at the global peak.
+        if self.live_bytes > self.peak_bytes {
+            self.peak_bytes = self.live_bytes;
+            self.peak_time = now;
+
+            for point in self.points.iter_mut() {
+                point.peak_bytes = point.live_bytes;
+                point.peak_blocks = point.live_blocks;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "profile")]
+lazy_static! {
+    static ref PROFILE: Mutex<Profile> = Mutex::new(Profile {
+        start: now(),
+        points: Vec::new(),
+        index: HashMap::new(),
+        live: HashMap::new(),
+        live_bytes: 0,
+        peak_bytes: 0,
+        peak_time: 0
+    });
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(feature = "profile")]
+pub fn allocated(ptr: *mut u8, size: usize) {
+    let frames = capture();
+    let now = now();
+    let mut profile = PROFILE.lock().unwrap();
+    let point = profile.point(frames);
+
+    profile.points[point].total_bytes += size;
+    profile.points[point].total_blocks += 1;
+    profile.grow(point, size, 1, now);
+    profile.live.insert(ptr as usize, Block { point: point, size: size, start: now });
+}
+
+/// Record the allocation at `old_ptr` being resized from `old_size` to
+/// `size` bytes at `ptr`.
+///
+/// The block keeps the call stack it was first allocated from.
+#[cfg(feature = "profile")]
+pub fn reallocated(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize) {
+    let now = now();
+    let mut profile = PROFILE.lock().unwrap();
+
+    if let Some(block) = profile.live.remove(&(old_ptr as usize)) {
+        let point = block.point;
+
+        if size > old_size {
+            profile.points[point].total_bytes += size - old_size;
+            profile.grow(point, size - old_size, 0, now);
+        } else {
+            profile.points[point].live_bytes -= old_size - size;
+            profile.live_bytes -= old_size - size;
+        }
+
+        profile.live.insert(ptr as usize, Block { size: size, ..block });
+    }
+}
+
+/// Record the allocation of `size` bytes at `ptr` being deallocated.
+#[cfg(feature = "profile")]
+pub fn deallocated(ptr: *mut u8, _: usize) {
+    let now = now();
+    let mut profile = PROFILE.lock().unwrap();
+
+    if let Some(block) = profile.live.remove(&(ptr as usize)) {
+        profile.live_bytes -= block.size;
+
+        let point = &mut profile.points[block.point];
+        point.live_bytes -= block.size;
+        point.live_blocks -= 1;
+        point.total_lifetime += now - block.start;
+    }
+}
+
+/// Write the heap profile collected so far as DHAT JSON.
+///
+/// Only available with the `profile` feature. Blocks which are still live
+/// are counted as living until now.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+///
+/// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(1024);
+///
+/// let mut json = Vec::new();
+/// membuf::write_profile(&mut json).unwrap();
+/// assert!(String::from_utf8(json).unwrap().contains("\"dhatFileVersion\":2"));
+/// # drop(buffer);
+/// ```
+#[cfg(feature = "profile")]
+pub fn write_profile<W: Write>(out: &mut W) -> io::Result<()> {
+    let now = now();
+    let profile = PROFILE.lock().unwrap();
+
+    let mut lifetimes = vec![0; profile.points.len()];
+    for block in profile.live.values() {
+        lifetimes[block.point] += now - block.start;
+    }
+
+    let cmd = env::args().collect::<Vec<_>>().join(" ");
+    let pid = unsafe { libc::getpid() };
+
+    try!(write!(out, "{{\"dhatFileVersion\":2,\"mode\":\"rust-heap\",\"verb\":\"Allocated\",\
+                       \"bklt\":true,\"bkacc\":false,\"tu\":\"µs\",\"Mtu\":\"s\",\"tuth\":10,\
+                       \"cmd\":{},\"pid\":{},\"tg\":{},\"te\":{},\"pps\":[",
+                escape(&cmd), pid,
+                micros(profile.peak_time.saturating_sub(profile.start)),
+                micros(now - profile.start)));
+
+    let mut frames = FrameTable::new();
+
+    for (i, point) in profile.points.iter().enumerate() {
+        let fs = point.frames.iter().skip_while(|&&ip| is_internal(ip))
+            .map(|&ip| frames.index(ip).to_string())
+            .collect::<Vec<_>>();
+
+        try!(write!(out, "{}{{\"tb\":{},\"tbk\":{},\"tl\":{},\"mb\":{},\"mbk\":{},\
+                           \"gb\":{},\"gbk\":{},\"eb\":{},\"ebk\":{},\"fs\":[{}]}}",
+                    if i == 0 { "" } else { "," },
+                    point.total_bytes, point.total_blocks,
+                    micros(point.total_lifetime + lifetimes[i]),
+                    point.max_bytes, point.max_blocks,
+                    point.peak_bytes, point.peak_blocks,
+                    point.live_bytes, point.live_blocks,
+                    fs.join(",")));
+    }
+
+    try!(write!(out, "],\"ftbl\":["));
+    for (i, frame) in frames.names.iter().enumerate() {
+        try!(write!(out, "{}{}", if i == 0 { "" } else { "," }, escape(frame)));
+    }
+    write!(out, "]}}")
+}
+
+/// Save the heap profile collected so far as DHAT JSON to the file at
+/// `path`, which is conventionally named `dhat-heap.json`.
+///
+/// Only available with the `profile` feature, see `write_profile`.
+#[cfg(feature = "profile")]
+pub fn save_profile<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let mut out = BufWriter::new(try!(File::create(path)));
+    try!(write_profile(&mut out));
+    out.flush()
+}
+
+/// Interned descriptions of stack frames, with the root at index 0.
+#[cfg(feature = "profile")]
+struct FrameTable {
+    names: Vec<String>,
+    index: HashMap<usize, usize>
+}
+
+#[cfg(feature = "profile")]
+impl FrameTable {
+    fn new() -> FrameTable {
+        FrameTable { names: vec!["[root]".to_string()], index: HashMap::new() }
+    }
+
+    fn index(&mut self, ip: usize) -> usize {
+        if let Some(&index) = self.index.get(&ip) { return index }
+
+        let index = self.names.len();
+        self.names.push(describe(ip));
+        self.index.insert(ip, index);
+        index
+    }
+}
+
+#[cfg(feature = "profile")]
+fn capture() -> Vec<usize> {
+    let mut frames = Vec::new();
+    backtrace::trace(|frame| {
+        frames.push(frame.ip() as usize);
+        true
+    });
+    frames
+}
+
+#[cfg(feature = "profile")]
+fn describe(ip: usize) -> String {
+    let mut description = None;
+
+    backtrace::resolve(ip as *mut libc::c_void, |symbol| {
+        if description.is_some() { return }
+
+        let name = symbol.name().map(|name| name.to_string())
+            .unwrap_or_else(|| "???".to_string());
+
+        description = Some(match (symbol.filename(), symbol.lineno()) {
+            (Some(file), Some(line)) =>
+                format!("{:#x}: {} ({}:{})", ip, name, file.display(), line),
+            _ => format!("{:#x}: {}", ip, name)
+        });
+    });
+
+    description.unwrap_or_else(|| format!("{:#x}: ???", ip))
+}
+
+/// Is the frame at `ip` part of capturing the backtrace or of membuf
+/// itself, rather than the code which allocated?
+#[cfg(feature = "profile")]
+fn is_internal(ip: usize) -> bool {
+    let mut internal = false;
+
+    backtrace::resolve(ip as *mut libc::c_void, |symbol| {
+        if let Some(name) = symbol.name() {
+            let name = name.to_string();
+            internal = internal || name.starts_with("backtrace::") ||
+                name.starts_with("membuf::alloc::") || name.starts_with("membuf::profile::");
+        }
+    });
+
+    internal
+}
+
+#[cfg(feature = "profile")]
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(feature = "profile")]
+fn now() -> u64 {
+    time::precise_time_ns()
+}
+
+#[cfg(feature = "profile")]
+fn micros(ns: u64) -> u64 {
+    ns / 1000
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(not(feature = "profile"))]
+#[inline(always)]
+pub fn allocated(_: *mut u8, _: usize) {}
+
+/// Record the allocation at `old_ptr` being resized from `old_size` to
+/// `size` bytes at `ptr`.
+#[cfg(not(feature = "profile"))]
+#[inline(always)]
+pub fn reallocated(_: *mut u8, _: *mut u8, _: usize, _: usize) {}
+
+/// Record the allocation of `size` bytes at `ptr` being deallocated.
+#[cfg(not(feature = "profile"))]
+#[inline(always)]
+pub fn deallocated(_: *mut u8, _: usize) {}
+
+#[cfg(all(test, feature = "profile"))]
+mod test {
+    use super::{escape, write_profile};
+    use MemBuf;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\\\u000a\"");
+    }
+
+    #[test]
+    fn test_profile_counts_buffer() {
+        let mut buffer: MemBuf<u8> = MemBuf::allocate(12345);
+        unsafe { buffer.reallocate(23456); }
+
+        let mut json = Vec::new();
+        write_profile(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        assert!(json.contains("\"tb\":23456,\"tbk\":1,"));
+        assert!(json.contains("\"eb\":23456,\"ebk\":1,"));
+        assert!(json.ends_with("]}"));
+
+        unsafe { buffer.deallocate(); }
+    }
+}
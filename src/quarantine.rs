@@ -0,0 +1,170 @@
+//! A bounded quarantine for freed memory.
+//!
+//! With the `quarantine` feature enabled, deallocated memory is not returned
+//! to the allocator straight away. Heap memory is poisoned (see `poison`) and
+//! page-backed memory is made inaccessible, then both are held here until
+//! the quarantine grows beyond its maximum size, at which point the oldest
+//! memory is finally released. A stale copy of a `MemBuf` which is used after
+//! its buffer was freed then reads obvious garbage, or faults outright,
+//! instead of silently aliasing a new allocation.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use backend::BackendHandle;
+
+#[cfg(unix)]
+use os;
+
+/// The default maximum number of bytes held in quarantine.
+pub const DEFAULT_QUARANTINE_SIZE: usize = 64 * 1024 * 1024;
+
+enum Entry {
+    Heap { ptr: usize, size: usize, align: usize, backend: BackendHandle },
+    #[cfg(unix)]
+    Pages { ptr: usize, size: usize }
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        match *self {
+            Entry::Heap { size, .. } => size,
+            #[cfg(unix)]
+            Entry::Pages { size, .. } => size
+        }
+    }
+
+    unsafe fn release(self) {
+        match self {
+            Entry::Heap { ptr, size, align, backend } =>
+                backend.get().deallocate(ptr as *mut u8, size, align),
+            #[cfg(unix)]
+            Entry::Pages { ptr, size } =>
+                os::unmap(ptr as *mut u8, size)
+        }
+    }
+}
+
+struct Quarantine {
+    entries: VecDeque<Entry>,
+    bytes: usize,
+    max_bytes: usize
+}
+
+impl Quarantine {
+    fn push(&mut self, entry: Entry) {
+        self.bytes += entry.size();
+        self.entries.push_back(entry);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.max_bytes {
+            let entry = self.entries.pop_front().unwrap();
+            self.bytes -= entry.size();
+            unsafe { entry.release() }
+        }
+    }
+}
+
+lazy_static! {
+    static ref QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine {
+        entries: VecDeque::new(),
+        bytes: 0,
+        max_bytes: DEFAULT_QUARANTINE_SIZE
+    });
+}
+
+// Held by the tests which read memory in quarantine, and around every flush
+// made by other tests, so the memory is not released while it is read.
+#[cfg(test)]
+lazy_static! {
+    pub static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Set the maximum number of bytes held in quarantine.
+///
+/// If the quarantine currently holds more than `bytes`, the oldest memory is
+/// released until it fits.
+pub fn set_quarantine_size(bytes: usize) {
+    let mut quarantine = QUARANTINE.lock().unwrap();
+    quarantine.max_bytes = bytes;
+    quarantine.evict();
+}
+
+/// Get the number of bytes currently held in quarantine.
+pub fn quarantined_bytes() -> usize {
+    QUARANTINE.lock().unwrap().bytes
+}
+
+/// Release all memory held in quarantine.
+pub fn flush_quarantine() {
+    let mut quarantine = QUARANTINE.lock().unwrap();
+    for entry in quarantine.entries.drain(..) {
+        unsafe { entry.release() }
+    }
+    quarantine.bytes = 0;
+}
+
+/// Quarantine poisoned heap memory instead of deallocating it.
+pub unsafe fn quarantine_heap(backend: BackendHandle, ptr: *mut u8, size: usize, align: usize) {
+    QUARANTINE.lock().unwrap().push(Entry::Heap {
+        ptr: ptr as usize,
+        size: size,
+        align: align,
+        backend: backend
+    });
+}
+
+/// Protect and quarantine a page mapping instead of unmapping it.
+#[cfg(unix)]
+pub unsafe fn quarantine_pages(ptr: *mut u8, size: usize) {
+    if os::protect_none(ptr, size).is_err() {
+        return os::unmap(ptr, size)
+    }
+
+    QUARANTINE.lock().unwrap().push(Entry::Pages {
+        ptr: ptr as usize,
+        size: size
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use MemBuf;
+    use poison::is_poisoned;
+    use super::{quarantined_bytes, TEST_LOCK};
+
+    #[test]
+    fn test_deallocate_poisons() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let buffer: MemBuf<u8> = MemBuf::allocate(64);
+        let stale = buffer;
+
+        unsafe {
+            ptr::write(buffer.offset(0), 1);
+            buffer.deallocate();
+            assert!(is_poisoned(*stale, 64));
+        }
+
+        assert!(quarantined_bytes() >= 64);
+    }
+
+    #[test]
+    fn test_reallocate_moves() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut buffer: MemBuf<u8> = MemBuf::allocate(64);
+        let stale = buffer;
+
+        unsafe {
+            ptr::write(buffer.offset(0), 1);
+            buffer.reallocate(32);
+
+            assert!(*buffer != *stale);
+            assert_eq!(ptr::read(buffer.offset(0)), 1);
+            assert!(is_poisoned(*stale, 64));
+
+            buffer.deallocate();
+        }
+    }
+}
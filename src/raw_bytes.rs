@@ -0,0 +1,337 @@
+use std::ops::Range;
+use std::{mem, ptr};
+
+use libc;
+
+use {registry, SliceBuf, UniqueBuf};
+
+/// The byte order of an integer read from or written to a byte buffer.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Endian {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big
+}
+
+macro_rules! scalar_accessors {
+    ($ty:ident, $read_le:ident, $read_be:ident, $write_le:ident, $write_be:ident) => {
+        /// Read a little endian integer from the bytes starting at `offset`,
+        /// which must be initialized.
+        ///
+        /// Panics if the bytes are not within the capacity of the buffer.
+        pub unsafe fn $read_le(&self, offset: usize) -> $ty {
+            $ty::from_le(self.read_unaligned_at(offset))
+        }
+
+        /// Read a big endian integer from the bytes starting at `offset`,
+        /// which must be initialized.
+        ///
+        /// Panics if the bytes are not within the capacity of the buffer.
+        pub unsafe fn $read_be(&self, offset: usize) -> $ty {
+            $ty::from_be(self.read_unaligned_at(offset))
+        }
+
+        /// Write a little endian integer to the bytes starting at `offset`.
+        ///
+        /// Panics if the bytes are not within the capacity of the buffer.
+        pub fn $write_le(&mut self, offset: usize, value: $ty) {
+            unsafe { self.write_unaligned_at(offset, value.to_le()) }
+        }
+
+        /// Write a big endian integer to the bytes starting at `offset`.
+        ///
+        /// Panics if the bytes are not within the capacity of the buffer.
+        pub fn $write_be(&mut self, offset: usize, value: $ty) {
+            unsafe { self.write_unaligned_at(offset, value.to_be()) }
+        }
+    }
+}
+
+/// Accessors at byte offsets, for encoding and decoding binary formats
+/// directly in a byte buffer.
+///
+/// Offsets need not be aligned. Every accessor panics if the bytes it touches
+/// are not within the capacity of the buffer, and with the `shadow-init`
+/// feature reads also panic if the bytes were never written.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+/// use membuf::Endian;
+///
+/// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(16);
+/// buffer.write_u16_be(0, 0x0102);
+/// buffer.write_u32_le(3, 0xdeadbeef);
+/// buffer.write_int(7, 0x030405, 3, Endian::Big);
+///
+/// unsafe {
+///     assert_eq!(buffer.as_slice(2), &[1, 2]);
+///     assert_eq!(buffer.read_u32_le(3), 0xdeadbeef);
+///     assert_eq!(buffer.read_u32_be(3), 0xefbeadde);
+///     assert_eq!(buffer.read_int(7, 3, Endian::Little), 0x050403);
+/// }
+/// ```
+impl UniqueBuf<u8> {
+    scalar_accessors!(u16, read_u16_le, read_u16_be, write_u16_le, write_u16_be);
+    scalar_accessors!(u32, read_u32_le, read_u32_be, write_u32_le, write_u32_be);
+    scalar_accessors!(u64, read_u64_le, read_u64_be, write_u64_le, write_u64_be);
+
+    /// Read an unsigned integer of `nbytes` bytes, starting at `offset`.
+    ///
+    /// Panics if `nbytes` is greater than 8, or if the bytes are not within
+    /// the capacity of the buffer.
+    pub unsafe fn read_int(&self, offset: usize, nbytes: usize, endian: Endian) -> u64 {
+        assert!(nbytes <= 8, "Integer of {} bytes does not fit in a u64", nbytes);
+        self.check_bytes(offset, nbytes, true);
+
+        let mut value = 0;
+        for i in 0..nbytes {
+            let byte = *self.offset((offset + i) as isize) as u64;
+            value |= match endian {
+                Endian::Little => byte << (8 * i),
+                Endian::Big => byte << (8 * (nbytes - 1 - i))
+            };
+        }
+        value
+    }
+
+    /// Write the low `nbytes` bytes of `value` as an unsigned integer,
+    /// starting at `offset`.
+    ///
+    /// Panics if `nbytes` is greater than 8, or if the bytes are not within
+    /// the capacity of the buffer.
+    pub fn write_int(&mut self, offset: usize, value: u64, nbytes: usize, endian: Endian) {
+        assert!(nbytes <= 8, "Integer of {} bytes does not fit in a u64", nbytes);
+        self.check_bytes(offset, nbytes, false);
+
+        for i in 0..nbytes {
+            let shift = match endian {
+                Endian::Little => 8 * i,
+                Endian::Big => 8 * (nbytes - 1 - i)
+            };
+            unsafe { *self.offset((offset + i) as isize) = (value >> shift) as u8; }
+        }
+        registry::mark_initialized(**self, offset, offset + nbytes);
+    }
+
+    /// Read a value of type `R` from the bytes starting at `offset`, which
+    /// need not be aligned for `R`.
+    ///
+    /// This copies the bytes out rather than dereferencing a misaligned
+    /// pointer, which would be undefined behaviour. Panics if the bytes are
+    /// not within the capacity of the buffer.
+    ///
+    /// ## Safety
+    ///
+    /// The bytes must be initialized, and hold a valid `R`.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(32);
+    ///
+    /// unsafe {
+    ///     buffer.write_unaligned_at(3, (1u64, 2u32));
+    ///     assert_eq!(buffer.read_unaligned_at::<(u64, u32)>(3), (1, 2));
+    /// }
+    /// ```
+    pub unsafe fn read_unaligned_at<R>(&self, offset: usize) -> R {
+        let size = mem::size_of::<R>();
+        self.check_bytes(offset, size, true);
+
+        let mut value: R = mem::uninitialized();
+        ptr::copy_nonoverlapping(self.offset(offset as isize),
+                                 &mut value as *mut R as *mut u8, size);
+        value
+    }
+
+    /// Write `value` to the bytes starting at `offset`, which need not be
+    /// aligned for `R`.
+    ///
+    /// The value is moved into the buffer, and will not be dropped. Panics if
+    /// the bytes are not within the capacity of the buffer.
+    ///
+    /// ## Safety
+    ///
+    /// Any padding bytes of `R` are left uninitialized in the buffer.
+    pub unsafe fn write_unaligned_at<R>(&mut self, offset: usize, value: R) {
+        let size = mem::size_of::<R>();
+        self.check_bytes(offset, size, false);
+
+        ptr::copy_nonoverlapping(&value as *const R as *const u8,
+                                 self.offset(offset as isize), size);
+        mem::forget(value);
+        registry::mark_initialized(**self, offset, offset + size);
+    }
+
+    /// Find the first occurrence of `needle` among the bytes in `range`,
+    /// returning its offset from the start of the buffer.
+    ///
+    /// This uses the platform's `memchr`, which is vectorized on most targets.
+    /// Panics if `range` is not within the capacity of the buffer.
+    ///
+    /// ## Safety
+    ///
+    /// The bytes in `range` must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+    /// buffer.fill_bytes(b'a');
+    /// buffer.write_u16_le(5, 0x0a0d);
+    ///
+    /// unsafe {
+    ///     assert_eq!(buffer.find_byte(b'\n', 0..8), Some(6));
+    ///     assert_eq!(buffer.find_byte(b'\n', 0..6), None);
+    /// }
+    /// ```
+    pub unsafe fn find_byte(&self, needle: u8, range: Range<usize>) -> Option<usize> {
+        let haystack = self.checked_bytes(range.clone());
+        find_byte(needle, haystack).map(|i| range.start + i)
+    }
+
+    /// Find the first occurrence of the sequence `needle` among the bytes in
+    /// `range`, returning its offset from the start of the buffer.
+    ///
+    /// Panics if `range` is not within the capacity of the buffer.
+    ///
+    /// ## Safety
+    ///
+    /// The bytes in `range` must be initialized.
+    pub unsafe fn find_bytes(&self, needle: &[u8], range: Range<usize>) -> Option<usize> {
+        let haystack = self.checked_bytes(range.clone());
+        find_bytes(needle, haystack).map(|i| range.start + i)
+    }
+
+    unsafe fn checked_bytes(&self, range: Range<usize>) -> &[u8] {
+        assert!(range.start <= range.end, "Range {}..{} is decreasing", range.start, range.end);
+        self.check_bytes(range.start, range.end - range.start, true);
+        &self.as_slice(range.end)[range.start..]
+    }
+
+    fn check_bytes(&self, offset: usize, len: usize, read: bool) {
+        if offset > self.capacity() || len > self.capacity() - offset {
+            panic!("Bytes {}..{} out of bounds for buffer with capacity {}",
+                   offset, offset.saturating_add(len), self.capacity())
+        }
+
+        if read && !registry::is_initialized(**self, offset, offset + len) {
+            panic!("Read of uninitialized bytes {}..{} of buffer at {:p}",
+                   offset, offset + len, **self)
+        }
+    }
+}
+
+impl SliceBuf<u8> {
+    /// Find the first occurrence of `needle` among the initialized bytes.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let mut buffer: SliceBuf<u8> = SliceBuf::allocate(8);
+    /// for (i, &b) in b"key=value".iter().take(8).enumerate() { buffer.write(i, b); }
+    /// unsafe { buffer.set_len(8); }
+    ///
+    /// assert_eq!(buffer.find_byte(b'='), Some(3));
+    /// assert_eq!(buffer.find_bytes(b"val"), Some(4));
+    /// assert_eq!(buffer.find_bytes(b"value"), None);
+    /// ```
+    pub fn find_byte(&self, needle: u8) -> Option<usize> {
+        find_byte(needle, self.as_slice())
+    }
+
+    /// Find the first occurrence of the sequence `needle` among the
+    /// initialized bytes.
+    pub fn find_bytes(&self, needle: &[u8]) -> Option<usize> {
+        find_bytes(needle, self.as_slice())
+    }
+}
+
+fn find_byte(needle: u8, haystack: &[u8]) -> Option<usize> {
+    if haystack.is_empty() { return None }
+
+    let found = unsafe {
+        libc::memchr(haystack.as_ptr() as *const libc::c_void,
+                     needle as libc::c_int, haystack.len())
+    };
+
+    if found.is_null() {
+        None
+    } else {
+        Some(found as usize - haystack.as_ptr() as usize)
+    }
+}
+
+fn find_bytes(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    if needle.is_empty() { return Some(0) }
+
+    // Jump between candidate first bytes with memchr, and only compare the
+    // rest of the needle there.
+    let last = match haystack.len().checked_sub(needle.len()) {
+        Some(last) => last,
+        None => return None
+    };
+
+    let mut start = 0;
+    while let Some(i) = find_byte(needle[0], &haystack[start..last + 1]) {
+        let candidate = start + i;
+        if &haystack[candidate..candidate + needle.len()] == needle {
+            return Some(candidate)
+        }
+        start = candidate + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use {Endian, UniqueBuf};
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(9);
+        buffer.write_u64_be(1, 0x0102030405060708);
+
+        unsafe {
+            assert_eq!(buffer.as_slice(9)[1..], [1, 2, 3, 4, 5, 6, 7, 8]);
+            assert_eq!(buffer.read_u64_le(1), 0x0807060504030201);
+            assert_eq!(buffer.read_u16_be(7), 0x0708);
+            assert_eq!(buffer.read_int(1, 8, Endian::Big), 0x0102030405060708);
+            assert_eq!(buffer.read_int(1, 0, Endian::Big), 0);
+        }
+    }
+
+    #[test]
+    fn test_unaligned_records() {
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(96);
+
+        unsafe {
+            for i in 0..5 {
+                buffer.write_unaligned_at(1 + i * 16, (i as u64, i as u32 * 2));
+            }
+            for i in 0..5 {
+                let record: (u64, u32) = buffer.read_unaligned_at(1 + i * 16);
+                assert_eq!(record, (i as u64, i as u32 * 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_bytes() {
+        let haystack = b"abcabdabe";
+        assert_eq!(super::find_bytes(b"abd", haystack), Some(3));
+        assert_eq!(super::find_bytes(b"abe", haystack), Some(6));
+        assert_eq!(super::find_bytes(b"abf", haystack), None);
+        assert_eq!(super::find_bytes(b"", haystack), Some(0));
+        assert_eq!(super::find_bytes(b"abcabdabex", haystack), None);
+        assert_eq!(super::find_byte(b'a', b""), None);
+    }
+
+    #[test]
+    #[should_panic = "Bytes 6..10 out of bounds for buffer with capacity 8"]
+    fn test_out_of_bounds() {
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+        buffer.write_u32_le(6, 0);
+    }
+}
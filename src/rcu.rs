@@ -0,0 +1,179 @@
+use std::ops::Deref;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use DropBuf;
+
+/// A buffer of Ts which many threads read while writers replace it, in the
+/// style of read-copy-update.
+///
+/// Readers never block: `read` registers the reader in the current epoch
+/// and returns a guard for the current buffer. A writer builds a whole new
+/// buffer out of line and publishes it with a single atomic store, then
+/// advances the epoch and waits for the readers of the old epoch to drain
+/// before dropping the old buffer. Writers are serialized with each other.
+///
+/// This suits data which is read constantly and replaced rarely, such as
+/// configuration or routing tables.
+///
+/// ```
+/// # use membuf::{DropBuf, RcuBuf};
+///
+/// fn table(routes: &[&str]) -> DropBuf<String> {
+///     let mut buffer = DropBuf::allocate(routes.len());
+///     for (i, route) in routes.iter().enumerate() { buffer.write(i, route.to_string()); }
+///     unsafe { buffer.set_len(routes.len()); }
+///     buffer
+/// }
+///
+/// let routes = RcuBuf::new(table(&["/a"]));
+/// assert_eq!(&*routes.read(), &["/a".to_string()]);
+///
+/// routes.update(|old| {
+///     let mut routes: Vec<&str> = old.iter().map(|route| &route[..]).collect();
+///     routes.push("/b");
+///     table(&routes)
+/// });
+/// assert_eq!(routes.read().len(), 2);
+/// ```
+pub struct RcuBuf<T> {
+    current: AtomicPtr<DropBuf<T>>,
+    epoch: AtomicUsize,
+    // Readers registered in even and odd epochs.
+    readers: [AtomicUsize; 2],
+    writer: Mutex<()>
+}
+
+unsafe impl<T: Send + Sync> Send for RcuBuf<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuBuf<T> {}
+
+/// Read access to the buffer which was current when `RcuBuf::read` was
+/// called. The buffer is not dropped until the guard is.
+pub struct RcuGuard<'a, T: 'a> {
+    parent: &'a RcuBuf<T>,
+    buffer: &'a DropBuf<T>,
+    slot: usize
+}
+
+impl<T> RcuBuf<T> {
+    /// Create a new RcuBuf publishing `buffer`.
+    pub fn new(buffer: DropBuf<T>) -> RcuBuf<T> {
+        RcuBuf {
+            current: AtomicPtr::new(Box::into_raw(Box::new(buffer))),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Mutex::new(())
+        }
+    }
+
+    /// Get read access to the current buffer.
+    pub fn read<'a>(&'a self) -> RcuGuard<'a, T> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let slot = epoch % 2;
+            self.readers[slot].fetch_add(1, Ordering::SeqCst);
+
+            // If the epoch moved on before we were counted, a writer may not
+            // wait for us, so register again in the new epoch.
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                let buffer = unsafe { &*self.current.load(Ordering::SeqCst) };
+                return RcuGuard { parent: self, buffer: buffer, slot: slot }
+            }
+
+            self.readers[slot].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Publish `buffer` in place of the current buffer, then wait for the
+    /// readers of the old one to finish and drop it.
+    ///
+    /// This must not be called while the calling thread holds a guard, or it
+    /// waits forever.
+    pub fn publish(&self, buffer: DropBuf<T>) {
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        self.replace(buffer);
+    }
+
+    /// Build a new buffer from the current one with `f`, publish it, then
+    /// wait for the readers of the old one to finish and drop it.
+    ///
+    /// Other writers are held off while `f` runs, so no update is lost. As
+    /// with `publish`, the calling thread must not hold a guard.
+    pub fn update<F: FnOnce(&[T]) -> DropBuf<T>>(&self, f: F) {
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let buffer = f(unsafe { &*self.current.load(Ordering::SeqCst) });
+        self.replace(buffer);
+    }
+
+    // Called with the writer lock held.
+    fn replace(&self, buffer: DropBuf<T>) {
+        let new = Box::into_raw(Box::new(buffer));
+        let old = unsafe { Box::from_raw(self.current.swap(new, Ordering::SeqCst)) };
+
+        // Readers which register in the new epoch load the new buffer, so
+        // only those counted in the old epoch can still hold the old one.
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+        while self.readers[epoch % 2].load(Ordering::SeqCst) != 0 {
+            thread::yield_now();
+        }
+
+        drop(old);
+    }
+}
+
+impl<T> Drop for RcuBuf<T> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(*self.current.get_mut())); }
+    }
+}
+
+impl<'a, T> Deref for RcuGuard<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.buffer.as_slice() }
+}
+
+impl<'a, T> Drop for RcuGuard<'a, T> {
+    fn drop(&mut self) {
+        self.parent.readers[self.slot].fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use {DropBuf, RcuBuf};
+
+    fn filled(value: usize, len: usize) -> DropBuf<Arc<usize>> {
+        let mut buffer = DropBuf::allocate(len);
+        for i in 0..len { buffer.write(i, Arc::new(value)); }
+        unsafe { buffer.set_len(len); }
+        buffer
+    }
+
+    #[test]
+    fn test_readers_never_see_dropped_buffers() {
+        let rcu = Arc::new(RcuBuf::new(filled(0, 8)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let (rcu, done) = (rcu.clone(), done.clone());
+            thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                let buffer = rcu.read();
+                let first = *buffer[0];
+                assert!(buffer.iter().all(|value| **value == first));
+            })
+        }).collect();
+
+        for i in 1..200 { rcu.publish(filled(i, 8)); }
+        rcu.update(|old| filled(*old[0] + 1, 8));
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers { reader.join().unwrap(); }
+        assert_eq!(*rcu.read()[7], 200);
+    }
+}
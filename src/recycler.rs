@@ -0,0 +1,249 @@
+//! A process-wide recycler for freed heap blocks.
+//!
+//! With the `recycler` feature enabled, deallocated heap memory is kept here
+//! instead of being returned to its backend, and later allocations of the
+//! same size and alignment from the same backend reuse it without touching
+//! the backend at all. This suits programs which repeatedly allocate and
+//! free buffers of a handful of sizes, such as per-request scratch space.
+//!
+//! Blocks are grouped into power-of-two size classes. The recycler holds at
+//! most `set_max_bytes` bytes in total, and at most `set_class_cap` blocks of
+//! each class; blocks freed beyond either limit go straight to the backend.
+//! `trim` returns everything the recycler holds, for instance when the
+//! system is low on memory.
+//!
+//! The quarantine takes precedence: with the `quarantine` feature also
+//! enabled, freed memory is quarantined and never recycled.
+
+use std::sync::{Mutex, MutexGuard};
+
+use backend::BackendHandle;
+
+/// The default maximum number of bytes held by the recycler.
+pub const DEFAULT_RECYCLER_SIZE: usize = 16 * 1024 * 1024;
+
+/// The default maximum number of blocks held in each size class.
+pub const DEFAULT_CLASS_CAP: usize = 64;
+
+// One class per power of two a size can be rounded up to.
+const CLASSES: usize = 64;
+
+/// The process-wide recycler, returned by `recycler`.
+pub struct Recycler {
+    _priv: ()
+}
+
+static RECYCLER: Recycler = Recycler { _priv: () };
+
+struct Block {
+    ptr: usize,
+    size: usize,
+    align: usize,
+    backend: BackendHandle
+}
+
+struct Blocks {
+    classes: Vec<Vec<Block>>,
+    caps: Vec<usize>,
+    bytes: usize,
+    max_bytes: usize
+}
+
+impl Blocks {
+    // Release the newest blocks of `class` until it holds at most `cap`.
+    fn shrink_class(&mut self, class: usize, cap: usize) {
+        while self.classes[class].len() > cap {
+            let block = self.classes[class].pop().unwrap();
+            self.bytes -= block.size;
+            unsafe { block.backend.get().deallocate(block.ptr as *mut u8, block.size, block.align) }
+        }
+    }
+
+    // Release the largest blocks until at most `max_bytes` are held.
+    fn shrink_bytes(&mut self) {
+        for class in (0..CLASSES).rev() {
+            if self.bytes <= self.max_bytes { return }
+            self.shrink_class(class, 0);
+        }
+    }
+}
+
+lazy_static! {
+    static ref BLOCKS: Mutex<Blocks> = Mutex::new(Blocks {
+        classes: (0..CLASSES).map(|_| Vec::new()).collect(),
+        caps: vec![DEFAULT_CLASS_CAP; CLASSES],
+        bytes: 0,
+        max_bytes: DEFAULT_RECYCLER_SIZE
+    });
+}
+
+/// Get the process-wide recycler.
+///
+/// ```
+/// # fn main() {
+/// # if cfg!(feature = "quarantine") { return }
+/// # use membuf::UniqueBuf;
+/// let recycler = membuf::recycler();
+///
+/// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(1000);
+/// let address = *buffer as usize;
+/// drop(buffer);
+///
+/// // The block is reused rather than freed and allocated again.
+/// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(1000);
+/// assert_eq!(*buffer as usize, address);
+/// drop(buffer);
+///
+/// recycler.trim();
+/// assert_eq!(recycler.retained_bytes(), 0);
+/// # }
+/// ```
+pub fn recycler() -> &'static Recycler {
+    &RECYCLER
+}
+
+impl Recycler {
+    /// Set the maximum number of bytes held by the recycler.
+    ///
+    /// If it currently holds more than `bytes`, blocks of the largest size
+    /// classes are released until it fits.
+    pub fn set_max_bytes(&self, bytes: usize) {
+        let mut blocks = lock();
+        blocks.max_bytes = bytes;
+        blocks.shrink_bytes();
+    }
+
+    /// Get the maximum number of bytes held by the recycler.
+    pub fn max_bytes(&self) -> usize {
+        lock().max_bytes
+    }
+
+    /// Set the maximum number of blocks held in the size class of `size`,
+    /// which holds the sizes larger than the previous power of two up to
+    /// `size.next_power_of_two()`.
+    ///
+    /// A cap of zero stops the class from being recycled.
+    pub fn set_class_cap(&self, size: usize, cap: usize) {
+        let class = size_class(size);
+        let mut blocks = lock();
+        blocks.caps[class] = cap;
+        blocks.shrink_class(class, cap);
+    }
+
+    /// Get the number of bytes currently held by the recycler.
+    pub fn retained_bytes(&self) -> usize {
+        lock().bytes
+    }
+
+    /// Return every block held by the recycler to its backend.
+    pub fn trim(&self) {
+        let mut blocks = lock();
+        for class in 0..CLASSES { blocks.shrink_class(class, 0); }
+    }
+}
+
+/// Take a block of exactly `size` bytes aligned to `align` which was freed
+/// to `backend`, if the recycler holds one.
+///
+/// Unused with the quarantine, which takes every freed block instead.
+#[cfg_attr(feature = "quarantine", allow(dead_code))]
+pub fn take(backend: BackendHandle, size: usize, align: usize) -> Option<*mut u8> {
+    let mut blocks = lock();
+    let class = size_class(size);
+
+    let found = blocks.classes[class].iter().rposition(|block| {
+        block.size == size && block.align == align && block.backend == backend
+    });
+
+    found.map(|index| {
+        let block = blocks.classes[class].swap_remove(index);
+        blocks.bytes -= block.size;
+        block.ptr as *mut u8
+    })
+}
+
+/// Keep a freed block for reuse, or return it to `backend` if the recycler
+/// is full.
+#[cfg_attr(feature = "quarantine", allow(dead_code))]
+pub unsafe fn keep(backend: BackendHandle, ptr: *mut u8, size: usize, align: usize) {
+    let mut blocks = lock();
+    let class = size_class(size);
+
+    if blocks.classes[class].len() >= blocks.caps[class] ||
+       size > blocks.max_bytes - blocks.bytes {
+        drop(blocks);
+        return backend.get().deallocate(ptr, size, align)
+    }
+
+    blocks.bytes += size;
+    blocks.classes[class].push(Block {
+        ptr: ptr as usize,
+        size: size,
+        align: align,
+        backend: backend
+    });
+}
+
+// The index of the smallest power of two at least `size`.
+fn size_class(size: usize) -> usize {
+    match size.checked_next_power_of_two() {
+        Some(class) => class.trailing_zeros() as usize,
+        None => CLASSES - 1
+    }
+}
+
+fn lock() -> MutexGuard<'static, Blocks> {
+    BLOCKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(all(test, not(feature = "quarantine")))]
+mod test {
+    use UniqueBuf;
+    use backend::DEFAULT_BACKEND;
+    use super::{recycler, keep, take, size_class};
+
+    // Sizes are picked so no other test, which shares the recycler, uses them.
+
+    #[test]
+    fn test_size_classes() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(1), 0);
+        assert_eq!(size_class(3), 2);
+        assert_eq!(size_class(4096), 12);
+        assert_eq!(size_class(4097), 13);
+    }
+
+    #[test]
+    fn test_only_exact_sizes_are_reused() {
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(40000);
+        let address = *buffer as usize;
+        drop(buffer);
+
+        let other: UniqueBuf<u8> = UniqueBuf::allocate(40001);
+        assert!(*other as usize != address);
+
+        let buffer: UniqueBuf<u8> = UniqueBuf::allocate(40000);
+        assert_eq!(*buffer as usize, address);
+    }
+
+    #[test]
+    fn test_class_caps() {
+        let (size, align) = (3000000, 8);
+        recycler().set_class_cap(size, 1);
+
+        unsafe {
+            let first = DEFAULT_BACKEND.get().allocate(size, align);
+            let second = DEFAULT_BACKEND.get().allocate(size, align);
+            keep(DEFAULT_BACKEND, first, size, align);
+            keep(DEFAULT_BACKEND, second, size, align);
+
+            assert_eq!(take(DEFAULT_BACKEND, size, 16), None);
+            assert_eq!(take(DEFAULT_BACKEND, size, align), Some(first));
+            assert_eq!(take(DEFAULT_BACKEND, size, align), None);
+
+            recycler().set_class_cap(size, 0);
+            keep(DEFAULT_BACKEND, first, size, align);
+            assert_eq!(take(DEFAULT_BACKEND, size, align), None);
+        }
+    }
+}
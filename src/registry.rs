@@ -0,0 +1,326 @@
+//! A registry of live heap allocations.
+//!
+//! With the `detect-double-free` feature enabled, every allocation made
+//! through `alloc` is recorded here until it is deallocated. Since `MemBuf`
+//! is `Copy`, it is easy to deallocate or reallocate the same buffer through
+//! two handles; with the registry this panics with a clear message instead
+//! of corrupting the heap.
+//!
+//! Once an address has been freed it may be handed out again by a later
+//! allocation, at which point a stale handle is indistinguishable from a
+//! live one. The `quarantine` feature delays reuse of freed memory, and so
+//! makes detection much more reliable. Only the most recently freed
+//! addresses are remembered, so a double free long after the first one is
+//! reported as a free of memory which was never allocated.
+//!
+//! The `generations` feature additionally gives every allocation a unique
+//! generation number, which `MemBuf` handles remember, so stale handles can
+//! be told apart from live ones even when an address is reused.
+//!
+//! Without the feature every function in here is a no-op.
+
+#[cfg(feature = "detect-double-free")]
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "detect-double-free")]
+use std::sync::Mutex;
+
+#[cfg(feature = "backtraces")]
+use std::io::{self, Write};
+
+#[cfg(feature = "detect-double-free")]
+use origin::Origin;
+use shadow::Shadow;
+
+/// The generation of an allocation, which is zero-sized unless the
+/// `generations` feature is enabled.
+#[cfg(feature = "generations")]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Generation(usize);
+
+/// The generation of an allocation, which is zero-sized unless the
+/// `generations` feature is enabled.
+#[cfg(not(feature = "generations"))]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Generation;
+
+#[cfg(feature = "generations")]
+impl Generation {
+    /// The generation of empty buffers, which are never stale.
+    pub fn empty() -> Generation { Generation(0) }
+
+    /// The current generation of the allocation at `ptr`.
+    pub fn of(ptr: *mut u8) -> Generation {
+        REGISTRY.lock().unwrap().live.get(&(ptr as usize))
+            .map(|record| Generation(record.generation))
+            .unwrap_or(Generation::empty())
+    }
+
+    /// Is this still the current generation of the allocation at `ptr`?
+    pub fn is_current(self, ptr: *mut u8) -> bool {
+        self == Generation::empty() || self == Generation::of(ptr)
+    }
+}
+
+#[cfg(not(feature = "generations"))]
+impl Generation {
+    /// The generation of empty buffers, which are never stale.
+    #[inline(always)]
+    pub fn empty() -> Generation { Generation }
+
+    /// The current generation of the allocation at `ptr`.
+    #[inline(always)]
+    pub fn of(_: *mut u8) -> Generation { Generation }
+}
+
+#[cfg(feature = "detect-double-free")]
+struct Record {
+    #[allow(dead_code)]
+    size: usize,
+    generation: usize,
+    #[allow(dead_code)]
+    shadow: Shadow,
+    #[allow(dead_code)]
+    origin: Origin
+}
+
+// The number of freed addresses remembered, beyond which the oldest are
+// forgotten so the registry does not grow for the life of the process.
+#[cfg(feature = "detect-double-free")]
+const FREED_CAPACITY: usize = 64 * 1024;
+
+#[cfg(feature = "detect-double-free")]
+struct Registry {
+    live: HashMap<usize, Record>,
+    // The generation each freed address was last freed in, and the order in
+    // which they were freed, oldest first.
+    freed: HashMap<usize, usize>,
+    freed_order: VecDeque<(usize, usize)>,
+    next_generation: usize
+}
+
+#[cfg(feature = "detect-double-free")]
+impl Registry {
+    fn free(&mut self, ptr: usize, generation: usize) {
+        self.freed.insert(ptr, generation);
+        self.freed_order.push_back((ptr, generation));
+
+        if self.freed_order.len() > FREED_CAPACITY {
+            let (ptr, generation) = self.freed_order.pop_front().unwrap();
+
+            // The address may have been reused and freed again since.
+            if self.freed.get(&ptr) == Some(&generation) {
+                self.freed.remove(&ptr);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "detect-double-free")]
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        live: HashMap::new(),
+        freed: HashMap::new(),
+        freed_order: VecDeque::new(),
+        next_generation: 1
+    });
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(feature = "detect-double-free")]
+pub fn insert(ptr: *mut u8, size: usize, shadow: Shadow) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let generation = registry.next_generation;
+
+    registry.next_generation += 1;
+    registry.freed.remove(&(ptr as usize));
+    registry.live.insert(ptr as usize, Record {
+        size: size,
+        generation: generation,
+        shadow: shadow,
+        origin: Origin::capture()
+    });
+}
+
+/// Remove the allocation at `ptr`, which is about to be released by `action`,
+/// returning the initialization state of its bytes.
+///
+/// Panics if `ptr` is not a live allocation.
+#[cfg(feature = "detect-double-free")]
+pub fn remove(ptr: *mut u8, action: &str) -> Shadow {
+    let double_free = {
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(record) = registry.live.remove(&(ptr as usize)) {
+            registry.free(ptr as usize, record.generation);
+            return record.shadow
+        }
+        registry.freed.contains_key(&(ptr as usize))
+    };
+
+    if double_free {
+        panic!("Buffer at {:p} passed to {} after it was already deallocated", ptr, action)
+    } else {
+        panic!("Buffer at {:p} passed to {} was never allocated", ptr, action)
+    }
+}
+
+/// Mark the bytes in `start..end` of the allocation at `ptr` as initialized.
+#[cfg(feature = "shadow-init")]
+pub fn mark_initialized(ptr: *mut u8, start: usize, end: usize) {
+    if let Some(record) = REGISTRY.lock().unwrap().live.get_mut(&(ptr as usize)) {
+        record.shadow.mark(start, end)
+    }
+}
+
+/// Are the bytes in `start..end` of the allocation at `ptr` initialized?
+///
+/// Memory which was not allocated through `alloc` is assumed initialized.
+#[cfg(feature = "shadow-init")]
+pub fn is_initialized(ptr: *mut u8, start: usize, end: usize) -> bool {
+    REGISTRY.lock().unwrap().live.get(&(ptr as usize))
+        .map(|record| record.shadow.is_marked(start, end))
+        .unwrap_or(true)
+}
+
+/// Print every live allocation, and where it was allocated or last
+/// reallocated, to standard error.
+///
+/// Only available with the `backtraces` feature. This is useful for tracking
+/// down which code forgot to deallocate its buffers, for instance by calling
+/// it at the end of a test.
+///
+/// ```
+/// # use membuf::MemBuf;
+///
+/// let buffer: MemBuf<u8> = MemBuf::allocate(64);
+/// membuf::dump_outstanding();
+/// # unsafe { buffer.deallocate() }
+/// ```
+#[cfg(feature = "backtraces")]
+pub fn dump_outstanding() {
+    let registry = REGISTRY.lock().unwrap();
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+
+    let _ = writeln!(out, "{} outstanding allocations", registry.live.len());
+    for (ptr, record) in registry.live.iter() {
+        let _ = writeln!(out, "{} bytes at {:#x}, allocated at:\n{}",
+                         record.size, ptr, record.origin);
+    }
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(not(feature = "detect-double-free"))]
+#[inline(always)]
+pub fn insert(_: *mut u8, _: usize, _: Shadow) {}
+
+/// Remove the allocation at `ptr`, which is about to be released by `action`.
+#[cfg(not(feature = "detect-double-free"))]
+#[inline(always)]
+pub fn remove(_: *mut u8, _: &str) -> Shadow { Shadow }
+
+/// Mark the bytes in `start..end` of the allocation at `ptr` as initialized.
+#[cfg(not(feature = "shadow-init"))]
+#[inline(always)]
+pub fn mark_initialized(_: *mut u8, _: usize, _: usize) {}
+
+/// Are the bytes in `start..end` of the allocation at `ptr` initialized?
+#[cfg(not(feature = "shadow-init"))]
+#[inline(always)]
+pub fn is_initialized(_: *mut u8, _: usize, _: usize) -> bool { true }
+
+#[cfg(all(test, feature = "detect-double-free"))]
+mod test {
+    use {MemBuf, UniqueBuf};
+    use super::{FREED_CAPACITY, REGISTRY};
+
+    #[test]
+    #[cfg(feature = "generations")]
+    fn test_generations() {
+        let mut buffer: MemBuf<u8> = MemBuf::allocate(8);
+        let copy = buffer;
+        assert!(copy.is_current());
+
+        unsafe { buffer.reallocate(16); }
+        assert!(buffer.is_current());
+        assert!(!copy.is_current());
+
+        unsafe { buffer.deallocate(); }
+        assert!(!buffer.is_current());
+        assert!(MemBuf::<u8>::new().is_current());
+    }
+
+    #[test]
+    #[cfg(feature = "shadow-init")]
+    fn test_shadow_survives_reallocate() {
+        let mut buffer: MemBuf<u16> = MemBuf::allocate(4);
+
+        unsafe {
+            buffer.write_at(1, 7);
+            buffer.assume_init_range(2..4);
+            buffer.reallocate(8);
+
+            assert_eq!(buffer.read_at(1), 7);
+            buffer.read_at(3);
+            buffer.deallocate();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "shadow-init")]
+    #[should_panic = "Read of uninitialized element 4"]
+    fn test_shadow_read_uninitialized() {
+        let mut buffer: MemBuf<u16> = MemBuf::allocate(4);
+
+        unsafe {
+            buffer.write_at(3, 7);
+            buffer.reallocate(8);
+            buffer.read_at(4);
+        }
+    }
+
+    #[test]
+    fn test_vec_round_trip_is_registered() {
+        let (mut buffer, len) = UniqueBuf::from_vec(vec![1u32, 2, 3]);
+        buffer.reallocate(64);
+
+        let vec = unsafe { buffer.into_vec(len) };
+        assert_eq!(vec, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_freed_addresses_are_bounded() {
+        let buffers: Vec<MemBuf<u8>> = (0..FREED_CAPACITY + 100)
+            .map(|_| MemBuf::allocate(1))
+            .collect();
+
+        for buffer in buffers { unsafe { buffer.deallocate() } }
+
+        let registry = REGISTRY.lock().unwrap();
+        assert!(registry.freed.len() <= FREED_CAPACITY);
+        assert_eq!(registry.freed_order.len(), FREED_CAPACITY);
+    }
+
+    #[test]
+    #[should_panic = "passed to deallocate after it was already deallocated"]
+    fn test_double_free() {
+        let buffer: MemBuf<u8> = MemBuf::allocate(8);
+        let copy = buffer;
+
+        unsafe {
+            buffer.deallocate();
+            copy.deallocate();
+        }
+    }
+
+    #[test]
+    #[should_panic = "passed to reallocate after it was already deallocated"]
+    fn test_reallocate_after_free() {
+        let buffer: MemBuf<u8> = MemBuf::allocate(8);
+        let mut copy = buffer;
+
+        unsafe {
+            buffer.deallocate();
+            copy.reallocate(16);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::ops::Deref;
+
+use {AllocError, CapacityOverflow, UniqueBuf};
+
+/// A function called when a buffer is moved by reallocation, with the old
+/// address, the new address and the new capacity.
+///
+/// The old address has already been freed, so it must not be dereferenced;
+/// it is only there to rebase pointers with.
+pub type RelocateFn<T> = fn(old: *mut T, new: *mut T, cap: usize);
+
+/// A `UniqueBuf` which calls a function whenever reallocation moves it, so
+/// structures holding pointers into the buffer can fix them up instead of
+/// forbidding growth.
+///
+/// The buffer can be read through `Deref`, but only reallocated through the
+/// methods here, so the function sees every move.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+/// use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+///
+/// static MOVES: AtomicUsize = ATOMIC_USIZE_INIT;
+///
+/// fn moved(old: *mut u64, new: *mut u64, cap: usize) {
+///     assert!(old != new);
+///     assert_eq!(cap, 1 << 20);
+///     MOVES.fetch_add(1, Ordering::SeqCst);
+/// }
+///
+/// let mut buffer = UniqueBuf::<u64>::allocate(4).on_relocate(moved);
+/// buffer.reallocate(1 << 20);
+///
+/// assert_eq!(MOVES.load(Ordering::SeqCst), 1);
+/// ```
+#[derive(Debug)]
+pub struct RelocatingBuf<T> {
+    buffer: UniqueBuf<T>,
+    on_relocate: RelocateFn<T>
+}
+
+impl<T> UniqueBuf<T> {
+    /// Register `on_relocate` to be called whenever reallocation moves this
+    /// buffer.
+    pub fn on_relocate(self, on_relocate: RelocateFn<T>) -> RelocatingBuf<T> {
+        RelocatingBuf { buffer: self, on_relocate: on_relocate }
+    }
+}
+
+impl<T> RelocatingBuf<T> {
+    /// Reallocate the buffer to fit a new number of Ts.
+    ///
+    /// See `UniqueBuf::reallocate`.
+    pub fn reallocate(&mut self, cap: usize) {
+        let old = *self.buffer;
+        self.buffer.reallocate(cap);
+        self.relocated(old);
+    }
+
+    /// Reallocate the buffer to fit a new number of Ts, preserving only the
+    /// first `len` elements.
+    ///
+    /// See `UniqueBuf::reallocate_with_len`.
+    pub fn reallocate_with_len(&mut self, cap: usize, len: usize) {
+        let old = *self.buffer;
+        self.buffer.reallocate_with_len(cap, len);
+        self.relocated(old);
+    }
+
+    /// Reallocate the buffer to fit a new number of Ts, returning an error
+    /// instead of panicking on capacity overflow.
+    ///
+    /// See `UniqueBuf::checked_reallocate`.
+    pub fn checked_reallocate(&mut self, cap: usize) -> Result<(), CapacityOverflow> {
+        let old = *self.buffer;
+        try!(self.buffer.checked_reallocate(cap));
+        self.relocated(old);
+        Ok(())
+    }
+
+    /// Reallocate the buffer to fit a new number of Ts, returning an error
+    /// instead of panicking or aborting if the allocation fails.
+    ///
+    /// See `UniqueBuf::try_reallocate`.
+    pub fn try_reallocate(&mut self, cap: usize) -> Result<(), AllocError> {
+        let old = *self.buffer;
+        try!(self.buffer.try_reallocate(cap));
+        self.relocated(old);
+        Ok(())
+    }
+
+    /// Get the underlying UniqueBuf, unregistering the function.
+    pub fn into_inner(self) -> UniqueBuf<T> {
+        self.buffer
+    }
+
+    fn relocated(&self, old: *mut T) {
+        let new = *self.buffer;
+        if old != new { (self.on_relocate)(old, new, self.buffer.capacity()) }
+    }
+}
+
+impl<T> Deref for RelocatingBuf<T> {
+    type Target = UniqueBuf<T>;
+
+    fn deref(&self) -> &UniqueBuf<T> { &self.buffer }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    use UniqueBuf;
+
+    static OFFSET: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    // Rebase the pointer stored in the first element of the buffer.
+    fn rebase(old: *mut usize, new: *mut usize, _: usize) {
+        unsafe {
+            let interior = *new as *mut usize;
+            let index = (interior as usize - old as usize) / mem::size_of::<usize>();
+            *new = new.offset(index as isize) as usize;
+            OFFSET.store(index, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_interior_pointer_follows_buffer() {
+        let mut buffer = UniqueBuf::<usize>::allocate(4).on_relocate(rebase);
+        unsafe {
+            *buffer.offset(3) = 42;
+            *buffer.offset(0) = buffer.offset(3) as usize;
+        }
+
+        buffer.reallocate(1 << 16);
+        buffer.try_reallocate(1 << 17).unwrap();
+
+        let buffer = buffer.into_inner();
+        assert_eq!(unsafe { *(*buffer.offset(0) as *const usize) }, 42);
+        assert_eq!(OFFSET.load(Ordering::SeqCst), 3);
+    }
+}
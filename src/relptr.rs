@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+use std::{fmt, mem};
+
+/// A pointer to a T stored as a byte offset from the start of a region of
+/// memory, such as a shared mapping.
+///
+/// A region mapped into several processes usually lands at a different
+/// address in each, so pointers stored inside it are only meaningful to the
+/// process which wrote them. A `RelPtr` stored in the region means the same
+/// thing in every process, and is resolved against the local mapping, with
+/// the result checked to lie inside it.
+///
+/// ```
+/// # use membuf::{RelPtr, UniqueBuf};
+///
+/// let mut first: UniqueBuf<u8> = UniqueBuf::allocate(64);
+/// let mut second: UniqueBuf<u8> = UniqueBuf::allocate(64);
+/// first.fill_bytes(0);
+/// second.fill_bytes(0);
+///
+/// let (region, other) = unsafe { (first.as_bytes_mut(64), second.as_bytes_mut(64)) };
+/// let ptr: RelPtr<u32> = RelPtr::new(region, region[16..].as_ptr() as *const u32).unwrap();
+///
+/// // Resolving against another copy of the region points into that copy.
+/// assert_eq!(ptr.resolve(other), Some(other[16..].as_ptr() as *const u32));
+/// assert_eq!(ptr.resolve(&other[..16]), None);
+/// ```
+#[repr(C)]
+pub struct RelPtr<T> {
+    offset: usize,
+    _marker: PhantomData<*const T>
+}
+
+unsafe impl<T: Send> Send for RelPtr<T> {}
+unsafe impl<T: Sync> Sync for RelPtr<T> {}
+
+impl<T> RelPtr<T> {
+    /// Create a relative pointer to `ptr`, which points into `region`.
+    ///
+    /// Returns `None` if the T at `ptr` does not lie entirely within the
+    /// region.
+    pub fn new(region: &[u8], ptr: *const T) -> Option<RelPtr<T>> {
+        let (start, ptr) = (region.as_ptr() as usize, ptr as usize);
+        if ptr < start { return None }
+
+        let relative = RelPtr::from_offset(ptr - start);
+        if relative.fits(region.len()) { Some(relative) } else { None }
+    }
+
+    /// Create a relative pointer to the T `offset` bytes from the start of a
+    /// region.
+    pub fn from_offset(offset: usize) -> RelPtr<T> {
+        RelPtr { offset: offset, _marker: PhantomData }
+    }
+
+    /// Get the offset in bytes from the start of the region.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get a pointer to the T in `region`, the local mapping of the region
+    /// this pointer was made in.
+    ///
+    /// Returns `None` if the T would not lie entirely within `region`, or
+    /// would be misaligned.
+    pub fn resolve(&self, region: &[u8]) -> Option<*const T> {
+        if !self.valid_in(region.as_ptr(), region.len()) { return None }
+        Some(unsafe { region.as_ptr().offset(self.offset as isize) as *const T })
+    }
+
+    /// Get a mutable pointer to the T in `region`, the local mapping of the
+    /// region this pointer was made in.
+    ///
+    /// Returns `None` if the T would not lie entirely within `region`, or
+    /// would be misaligned.
+    pub fn resolve_mut(&self, region: &mut [u8]) -> Option<*mut T> {
+        if !self.valid_in(region.as_ptr(), region.len()) { return None }
+        Some(unsafe { region.as_mut_ptr().offset(self.offset as isize) as *mut T })
+    }
+
+    // Would the T lie entirely within, and be aligned in, the region at
+    // `start`?
+    fn valid_in(&self, start: *const u8, len: usize) -> bool {
+        self.fits(len) && (start as usize + self.offset) % mem::align_of::<T>() == 0
+    }
+
+    fn fits(&self, len: usize) -> bool {
+        self.offset <= len && mem::size_of::<T>() <= len - self.offset
+    }
+}
+
+impl<T> Clone for RelPtr<T> {
+    fn clone(&self) -> RelPtr<T> { *self }
+}
+
+impl<T> Copy for RelPtr<T> {}
+
+impl<T> PartialEq for RelPtr<T> {
+    fn eq(&self, other: &RelPtr<T>) -> bool { self.offset == other.offset }
+}
+
+impl<T> Eq for RelPtr<T> {}
+
+impl<T> fmt::Debug for RelPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RelPtr(+{})", self.offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::slice;
+
+    use RelPtr;
+
+    #[test]
+    fn test_linked_list_in_region() {
+        #[repr(C)]
+        struct Node {
+            value: u64,
+            next: RelPtr<Node>
+        }
+
+        let (mut region, mut other) = ([0u64; 8], [0u64; 8]);
+        let (bytes, copy) = unsafe {
+            (slice::from_raw_parts_mut(region.as_mut_ptr() as *mut u8, 64),
+             slice::from_raw_parts_mut(other.as_mut_ptr() as *mut u8, 64))
+        };
+
+        let tail: RelPtr<Node> = RelPtr::from_offset(32);
+        unsafe {
+            *tail.resolve_mut(bytes).unwrap() = Node { value: 2, next: RelPtr::from_offset(0) };
+            *RelPtr::from_offset(0).resolve_mut(bytes).unwrap() = Node { value: 1, next: tail };
+        }
+
+        // Walk the list through a copy of the region, as another process
+        // would see it at another address.
+        copy.copy_from_slice(bytes);
+        let head: RelPtr<Node> = RelPtr::new(copy, copy.as_ptr() as *const Node).unwrap();
+        let first = unsafe { &*head.resolve(copy).unwrap() };
+        let second = unsafe { &*first.next.resolve(copy).unwrap() };
+        assert_eq!((first.value, second.value), (1, 2));
+
+        assert!(RelPtr::<Node>::from_offset(56).resolve(copy).is_none());
+        assert!(RelPtr::<Node>::from_offset(4).resolve(bytes).is_none());
+    }
+}
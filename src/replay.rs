@@ -0,0 +1,406 @@
+//! Recording and replaying allocations, for reproducing bugs which depend on
+//! what the allocator does.
+//!
+//! Only available with the `replay` feature. `ReplayAlloc` is a backend
+//! which allocates from the standard heap. While recording, it logs every
+//! request it serves and its result. While replaying a log, it checks that
+//! the same requests arrive in the same order, and forces the same outcome:
+//! requests which failed fail again, and `reallocate` moves the memory
+//! exactly when it moved in the recording.
+//!
+//! Addresses cannot be forced, since they are chosen by the heap, so
+//! recorded addresses are translated to the ones handed out during the
+//! replay. A recorded in-place reallocation which the heap cannot repeat is
+//! counted by `divergences`.
+//!
+//! Requests from different threads interleave differently on every run, so
+//! only single-threaded code can be replayed faithfully.
+//!
+//! ```
+//! use membuf::UniqueBuf;
+//! use membuf::backend;
+//! use membuf::replay::{self, ReplayAlloc};
+//!
+//! static REPLAY: ReplayAlloc = ReplayAlloc;
+//!
+//! let handle = unsafe { backend::register_backend("replay", &REPLAY) };
+//! let run = || {
+//!     let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate_in(16, handle);
+//!     buffer.reallocate(4096);
+//! };
+//!
+//! replay::start_recording();
+//! run();
+//! let log = replay::stop_recording();
+//!
+//! replay::start_replay(log);
+//! run();
+//! assert_eq!(replay::stop_replay(), 0);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::sync::{Mutex, MutexGuard};
+use std::{cmp, fmt, ptr};
+use std::rt::heap;
+
+use backend::{Backend, Heap};
+
+/// An allocation request served by `ReplayAlloc`, and its result.
+///
+/// Addresses are recorded as integers, with 0 for a failed request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A call to `Backend::allocate`.
+    Allocate {
+        /// The size requested.
+        size: usize,
+        /// The alignment requested.
+        align: usize,
+        /// The address returned.
+        result: usize
+    },
+    /// A call to `Backend::reallocate`.
+    Reallocate {
+        /// The address of the allocation.
+        ptr: usize,
+        /// The size of the allocation.
+        old_size: usize,
+        /// The size requested.
+        size: usize,
+        /// The alignment of the allocation.
+        align: usize,
+        /// The address returned.
+        result: usize
+    },
+    /// A call to `Backend::deallocate`.
+    Deallocate {
+        /// The address of the allocation.
+        ptr: usize,
+        /// The size of the allocation.
+        size: usize,
+        /// The alignment of the allocation.
+        align: usize
+    }
+}
+
+/// Events are written as a single line, such as `allocate 64 8 => 140523`.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Allocate { size, align, result } =>
+                write!(f, "allocate {} {} => {}", size, align, result),
+            Event::Reallocate { ptr, old_size, size, align, result } =>
+                write!(f, "reallocate {} {} {} {} => {}", ptr, old_size, size, align, result),
+            Event::Deallocate { ptr, size, align } =>
+                write!(f, "deallocate {} {} {}", ptr, size, align)
+        }
+    }
+}
+
+impl Event {
+    // Parse a line written by `Display`.
+    fn parse(line: &str) -> Option<Event> {
+        let words: Vec<&str> = line.split_whitespace().filter(|&word| word != "=>").collect();
+        let mut numbers = Vec::new();
+        for word in &words[cmp::min(1, words.len())..] {
+            match word.parse() {
+                Ok(number) => numbers.push(number),
+                Err(_) => return None
+            }
+        }
+
+        match (words.first().cloned(), &numbers[..]) {
+            (Some("allocate"), &[size, align, result]) =>
+                Some(Event::Allocate { size: size, align: align, result: result }),
+            (Some("reallocate"), &[ptr, old_size, size, align, result]) =>
+                Some(Event::Reallocate {
+                    ptr: ptr, old_size: old_size, size: size, align: align, result: result
+                }),
+            (Some("deallocate"), &[ptr, size, align]) =>
+                Some(Event::Deallocate { ptr: ptr, size: size, align: align }),
+            _ => None
+        }
+    }
+}
+
+/// Write a log to `out`, one event per line.
+pub fn write_log<W: Write>(log: &[Event], mut out: W) -> io::Result<()> {
+    for event in log {
+        try!(writeln!(out, "{}", event));
+    }
+    Ok(())
+}
+
+/// Read a log written by `write_log`.
+pub fn read_log<R: BufRead>(input: R) -> io::Result<Vec<Event>> {
+    let mut log = Vec::new();
+    for line in input.lines() {
+        let line = try!(line);
+        match Event::parse(&line) {
+            Some(event) => log.push(event),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("Invalid replay event: {}", line)))
+        }
+    }
+    Ok(log)
+}
+
+enum Mode {
+    Idle,
+    Recording(Vec<Event>),
+    Replaying {
+        events: VecDeque<Event>,
+        replayed: usize,
+        // Recorded addresses to the addresses handed out in this run.
+        addresses: HashMap<usize, usize>,
+        divergences: usize
+    }
+}
+
+lazy_static! {
+    static ref MODE: Mutex<Mode> = Mutex::new(Mode::Idle);
+}
+
+// Lock the mode. A diverged replay panics with the lock held, which must not
+// break every later allocation through `ReplayAlloc`.
+fn mode<'a>() -> MutexGuard<'a, Mode> {
+    MODE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Start recording the requests served by `ReplayAlloc`, discarding any
+/// recording or replay in progress.
+pub fn start_recording() {
+    *mode() = Mode::Recording(Vec::new());
+}
+
+/// Stop recording, returning the log.
+///
+/// Returns an empty log if nothing was being recorded.
+pub fn stop_recording() -> Vec<Event> {
+    match ::std::mem::replace(&mut *mode(), Mode::Idle) {
+        Mode::Recording(log) => log,
+        _ => Vec::new()
+    }
+}
+
+/// Start replaying `log`, discarding any recording or replay in progress.
+///
+/// Until `stop_replay`, `ReplayAlloc` panics on any request which does not
+/// match the next event in the log, including requests beyond its end.
+pub fn start_replay(log: Vec<Event>) {
+    *mode() = Mode::Replaying {
+        events: log.into_iter().collect(),
+        replayed: 0,
+        addresses: HashMap::new(),
+        divergences: 0
+    };
+}
+
+/// Stop replaying, returning the number of events in the log which were
+/// never replayed.
+pub fn stop_replay() -> usize {
+    match ::std::mem::replace(&mut *mode(), Mode::Idle) {
+        Mode::Replaying { events, .. } => events.len(),
+        _ => 0
+    }
+}
+
+/// Get the number of recorded in-place reallocations which had to move
+/// during the current replay.
+pub fn divergences() -> usize {
+    match *mode() {
+        Mode::Replaying { divergences, .. } => divergences,
+        _ => 0
+    }
+}
+
+/// A backend which allocates from the standard heap, and records or replays
+/// its requests as directed by `start_recording` and `start_replay`.
+///
+/// When neither is in progress, it passes requests straight to the heap.
+/// Memory allocated while recording or replaying can be released at any
+/// time, but memory allocated before a replay must not be reallocated or
+/// released during it.
+#[derive(Copy, Clone, Debug)]
+pub struct ReplayAlloc;
+
+// Check that a request matches the next event of a replay, and take it.
+fn expect(events: &mut VecDeque<Event>, replayed: &mut usize, request: Event,
+          matches: &Fn(&Event) -> bool) -> Event {
+    match events.pop_front() {
+        Some(event) if matches(&event) => {
+            *replayed += 1;
+            event
+        },
+        Some(event) => panic!("Replay diverged at event {}: expected {}, got {}",
+                              *replayed, event, request),
+        None => panic!("Replay diverged at event {}: log ended, got {}", *replayed, request)
+    }
+}
+
+// The recorded address `ptr` stands for in this run.
+fn recorded(addresses: &HashMap<usize, usize>, ptr: *mut u8) -> usize {
+    addresses.iter().find(|&(_, &actual)| actual == ptr as usize)
+        .map(|(&recorded, _)| recorded)
+        .unwrap_or(ptr as usize)
+}
+
+impl Backend for ReplayAlloc {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        let mut mode = mode();
+        match *mode {
+            Mode::Idle => Heap.allocate(size, align),
+            Mode::Recording(ref mut log) => {
+                let ptr = Heap.allocate(size, align);
+                log.push(Event::Allocate { size: size, align: align, result: ptr as usize });
+                ptr
+            },
+            Mode::Replaying { ref mut events, ref mut replayed, ref mut addresses, .. } => {
+                let request = Event::Allocate { size: size, align: align, result: 0 };
+                let event = expect(events, replayed, request, &|event| match *event {
+                    Event::Allocate { size: s, align: a, .. } => (s, a) == (size, align),
+                    _ => false
+                });
+
+                let result = match event { Event::Allocate { result, .. } => result, _ => 0 };
+                if result == 0 { return ptr::null_mut() }
+
+                let ptr = Heap.allocate(size, align);
+                if !ptr.is_null() { addresses.insert(result, ptr as usize); }
+                ptr
+            }
+        }
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize,
+                         align: usize) -> *mut u8 {
+        let mut mode = mode();
+        match *mode {
+            Mode::Idle => Heap.reallocate(ptr, old_size, size, align),
+            Mode::Recording(ref mut log) => {
+                let new = Heap.reallocate(ptr, old_size, size, align);
+                log.push(Event::Reallocate {
+                    ptr: ptr as usize,
+                    old_size: old_size,
+                    size: size,
+                    align: align,
+                    result: new as usize
+                });
+                new
+            },
+            Mode::Replaying { ref mut events, ref mut replayed, ref mut addresses,
+                              ref mut divergences } => {
+                let old = recorded(addresses, ptr);
+                let request = Event::Reallocate {
+                    ptr: old, old_size: old_size, size: size, align: align, result: 0
+                };
+                let event = expect(events, replayed, request, &|event| match *event {
+                    Event::Reallocate { ptr: p, old_size: o, size: s, align: a, .. } =>
+                        (p, o, s, a) == (old, old_size, size, align),
+                    _ => false
+                });
+
+                let result = match event { Event::Reallocate { result, .. } => result, _ => 0 };
+                if result == 0 { return ptr::null_mut() }
+
+                let new = if result == old {
+                    if heap::reallocate_inplace(ptr, old_size, size, align) >= size {
+                        ptr
+                    } else {
+                        *divergences += 1;
+                        Heap.reallocate(ptr, old_size, size, align)
+                    }
+                } else {
+                    // Move even where the heap could have resized in place.
+                    let new = Heap.allocate(size, align);
+                    if !new.is_null() {
+                        ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+                        Heap.deallocate(ptr, old_size, align);
+                    }
+                    new
+                };
+
+                if !new.is_null() {
+                    addresses.remove(&old);
+                    addresses.insert(result, new as usize);
+                }
+                new
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, old_size: usize, align: usize) {
+        let mut mode = mode();
+        match *mode {
+            Mode::Idle => {},
+            Mode::Recording(ref mut log) =>
+                log.push(Event::Deallocate { ptr: ptr as usize, size: old_size, align: align }),
+            Mode::Replaying { ref mut events, ref mut replayed, ref mut addresses, .. } => {
+                let old = recorded(addresses, ptr);
+                let request = Event::Deallocate { ptr: old, size: old_size, align: align };
+                expect(events, replayed, request, &|event| *event == request);
+                addresses.remove(&old);
+            }
+        }
+
+        Heap.deallocate(ptr, old_size, align)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use backend::Backend;
+    use super::{Event, ReplayAlloc};
+
+    // Recording and replaying share a global log, so they run in one test.
+    #[test]
+    fn test_record_and_replay() {
+        unsafe {
+            super::start_recording();
+            let ptr = ReplayAlloc.allocate(16, 8);
+            let grown = ReplayAlloc.reallocate(ptr, 16, 64, 8);
+            assert!(ReplayAlloc.allocate(1 << 62, 8).is_null());
+            ReplayAlloc.deallocate(grown, 64, 8);
+        }
+
+        let log = super::stop_recording();
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[2], Event::Allocate { size: 1 << 62, align: 8, result: 0 });
+
+        let mut text = Vec::new();
+        super::write_log(&log, &mut text).unwrap();
+        assert_eq!(super::read_log(Cursor::new(text)).unwrap(), log);
+
+        // A failure is forced even when memory is available, and a move even
+        // when the heap could resize in place.
+        super::start_replay(vec![
+            Event::Allocate { size: 16, align: 8, result: 0x1000 },
+            Event::Reallocate { ptr: 0x1000, old_size: 16, size: 8, align: 8, result: 0x2000 },
+            Event::Allocate { size: 16, align: 8, result: 0 },
+            Event::Deallocate { ptr: 0x2000, size: 8, align: 8 }
+        ]);
+
+        unsafe {
+            let ptr = ReplayAlloc.allocate(16, 8);
+            let shrunk = ReplayAlloc.reallocate(ptr, 16, 8, 8);
+            assert!(!shrunk.is_null() && shrunk != ptr);
+            assert!(ReplayAlloc.allocate(16, 8).is_null());
+            ReplayAlloc.deallocate(shrunk, 8, 8);
+        }
+
+        assert_eq!(super::divergences(), 0);
+        assert_eq!(super::stop_replay(), 0);
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert_eq!(Event::parse("deallocate 1 2 3"),
+                   Some(Event::Deallocate { ptr: 1, size: 2, align: 3 }));
+        assert_eq!(Event::parse("deallocate 1 2"), None);
+        assert_eq!(Event::parse("free 1 2 3"), None);
+        assert_eq!(Event::parse(""), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! An emergency reserve of memory released when allocation fails.
+//!
+//! `set_emergency_reserve` sets aside a block of memory from the backend.
+//! When an allocation through `alloc` fails, and the hook set with
+//! `set_oom_hook` cannot recover, the reserve is released back to the
+//! backend and the allocation is retried once, giving the process
+//! enough headroom to log, flush and shut down cleanly rather than aborting
+//! straight away. The reserve is only released once; call
+//! `set_emergency_reserve` again to replenish it.
+
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use std::{cmp, mem, ptr};
+
+use alloc::AllocError;
+use backend::{backend, Backend};
+
+/// The reserve block, which starts with a `Header`, or zero.
+static RESERVE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The size of the reserve block, kept apart from the block so it can be
+/// read while another thread releases the block.
+static RESERVE_SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// The start of the reserve block. The backend it came from is recorded since
+// the default backend may be replaced before the reserve is released.
+struct Header {
+    size: usize,
+    backend: &'static Backend
+}
+
+/// Set aside `bytes` of memory to be released when an allocation fails,
+/// replacing any existing reserve.
+///
+/// The memory is zeroed so the operating system has to commit it up front.
+/// A reserve of zero bytes releases the existing reserve without setting
+/// aside a new one.
+///
+/// ```
+/// use membuf::UniqueBuf;
+/// use membuf::backend::{self, FailingAlloc, FAILING_ALLOC_INIT};
+///
+/// static FAILING: FailingAlloc = FAILING_ALLOC_INIT;
+///
+/// unsafe { backend::set_backend(&FAILING) }
+///
+/// membuf::set_emergency_reserve(1024 * 1024).unwrap();
+/// assert_eq!(membuf::emergency_reserve(), 1024 * 1024);
+///
+/// // The first attempt fails, so the reserve is released and it is retried.
+/// FAILING.fail_nth_allocation(1);
+/// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(4096);
+/// assert_eq!(buffer.capacity(), 4096);
+/// assert_eq!(membuf::emergency_reserve(), 0);
+/// ```
+///
+/// The memory comes from the current default backend, and is returned to
+/// that same backend even if `set_backend` replaces it in the meantime.
+///
+/// ```
+/// use membuf::backend::{self, TrackingAlloc, TRACKING_ALLOC_INIT, HEAP_BACKEND};
+///
+/// static TRACKER: TrackingAlloc = TRACKING_ALLOC_INIT;
+///
+/// unsafe { backend::set_backend(&TRACKER) }
+/// membuf::set_emergency_reserve(4096).unwrap();
+///
+/// unsafe { backend::set_backend(HEAP_BACKEND.get()) }
+/// membuf::set_emergency_reserve(0).unwrap();
+/// TRACKER.assert_no_leaks();
+/// ```
+pub fn set_emergency_reserve(bytes: usize) -> Result<(), AllocError> {
+    let (block, size) = if bytes == 0 {
+        (ptr::null_mut(), 0)
+    } else {
+        let size = cmp::max(bytes, mem::size_of::<Header>());
+        let backend = backend();
+
+        unsafe {
+            let block = backend.allocate(size, mem::align_of::<Header>());
+            if block.is_null() { return Err(AllocError::OutOfMemory) }
+
+            ptr::write_bytes(block, 0, size);
+            ptr::write(block as *mut Header, Header { size: size, backend: backend });
+            (block, size)
+        }
+    };
+
+    RESERVE_SIZE.store(size, Ordering::SeqCst);
+    unsafe { free(RESERVE.swap(block as usize, Ordering::SeqCst)); }
+    Ok(())
+}
+
+/// Get the number of bytes currently set aside by `set_emergency_reserve`,
+/// which is zero once the reserve has been released.
+pub fn emergency_reserve() -> usize {
+    RESERVE_SIZE.load(Ordering::SeqCst)
+}
+
+/// Release the reserve to the backend, returning whether there was one.
+pub fn release() -> bool {
+    match RESERVE.swap(0, Ordering::SeqCst) {
+        0 => false,
+        block => {
+            // Leave the size alone if a new reserve was set in the meantime.
+            let size = unsafe { free(block) };
+            RESERVE_SIZE.compare_and_swap(size, 0, Ordering::SeqCst);
+            true
+        }
+    }
+}
+
+/// Return a reserve block taken out of `RESERVE` to its backend, returning
+/// its size.
+unsafe fn free(block: usize) -> usize {
+    if block == 0 { return 0 }
+
+    let header = ptr::read(block as *const Header);
+    header.backend.deallocate(block as *mut u8, header.size, mem::align_of::<Header>());
+    header.size
+}
+
+#[cfg(test)]
+mod test {
+    use super::{release, set_emergency_reserve, emergency_reserve};
+
+    #[test]
+    fn test_release() {
+        set_emergency_reserve(4096).unwrap();
+        assert!(release());
+        assert_eq!(emergency_reserve(), 0);
+        assert!(!release());
+    }
+}
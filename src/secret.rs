@@ -0,0 +1,124 @@
+use std::intrinsics;
+use std::ops::Deref;
+use std::sync::atomic::{self, Ordering};
+use std::{cmp, mem, ptr};
+
+use MemBuf;
+
+/// A buffer of Ts which is securely zeroed before its memory is released.
+///
+/// `SecretBuf` behaves like `UniqueBuf`, but its destructor overwrites the
+/// entire capacity with zeroes using volatile writes followed by a fence, so
+/// the compiler cannot elide the writes and secrets do not linger in freed
+/// memory.
+///
+/// `reallocate` never lets the allocator move the data on its own; it
+/// allocates a new buffer, copies the contents over and then zeroes the old
+/// buffer before freeing it, so no stale copies are left behind either.
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct SecretBuf<T> {
+    inner: MemBuf<T>
+}
+
+unsafe impl<T: Send> Send for SecretBuf<T> {}
+unsafe impl<T: Sync> Sync for SecretBuf<T> {}
+
+impl<T> SecretBuf<T> {
+    /// Create a new, empty SecretBuf.
+    ///
+    /// ```
+    /// # use membuf::SecretBuf;
+    ///
+    /// let buffer: SecretBuf<u8> = SecretBuf::new();
+    /// assert_eq!(buffer.capacity(), 0);
+    /// ```
+    pub fn new() -> SecretBuf<T> {
+        SecretBuf { inner: MemBuf::new() }
+    }
+
+    /// Create a new buffer with space for cap Ts.
+    ///
+    /// ```
+    /// # use membuf::SecretBuf;
+    ///
+    /// let buffer: SecretBuf<u8> = SecretBuf::allocate(32);
+    /// assert_eq!(buffer.capacity(), 32);
+    /// ```
+    pub fn allocate(cap: usize) -> SecretBuf<T> {
+        SecretBuf { inner: MemBuf::allocate(cap) }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts.
+    ///
+    /// The old buffer is zeroed before being deallocated.
+    ///
+    /// ```
+    /// # use membuf::SecretBuf;
+    ///
+    /// let mut buffer: SecretBuf<u8> = SecretBuf::allocate(32);
+    /// buffer.reallocate(64);
+    /// assert_eq!(buffer.capacity(), 64);
+    /// ```
+    pub fn reallocate(&mut self, cap: usize) {
+        let new = MemBuf::allocate_in(cap, self.inner.backend());
+
+        unsafe {
+            ptr::copy_nonoverlapping(*self.inner, *new,
+                                     cmp::min(self.inner.capacity(), cap));
+            zero_and_deallocate(mem::replace(&mut self.inner, new));
+        }
+    }
+
+    /// Get the current capacity of the SecretBuf.
+    ///
+    /// ```
+    /// # use membuf::SecretBuf;
+    ///
+    /// let buffer: SecretBuf<u8> = SecretBuf::allocate(32);
+    /// assert_eq!(buffer.capacity(), 32);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<T> Drop for SecretBuf<T> {
+    fn drop(&mut self) {
+        unsafe { zero_and_deallocate(self.inner) }
+    }
+}
+
+impl<T> Deref for SecretBuf<T> {
+    type Target = *mut T;
+
+    fn deref(&self) -> &*mut T { &*self.inner }
+}
+
+unsafe fn zero_and_deallocate<T>(buffer: MemBuf<T>) {
+    intrinsics::volatile_set_memory(*buffer, 0, buffer.capacity());
+    atomic::fence(Ordering::SeqCst);
+    buffer.deallocate();
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use SecretBuf;
+
+    #[test]
+    fn test_reallocate_keeps_data() {
+        let mut buffer: SecretBuf<u64> = SecretBuf::allocate(4);
+
+        unsafe {
+            ptr::write(buffer.offset(0), 0xdead);
+            ptr::write(buffer.offset(3), 0xbeef);
+        }
+
+        buffer.reallocate(1024);
+
+        unsafe {
+            assert_eq!(ptr::read(buffer.offset(0)), 0xdead);
+            assert_eq!(ptr::read(buffer.offset(3)), 0xbeef);
+        }
+    }
+}
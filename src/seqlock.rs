@@ -0,0 +1,168 @@
+use std::ops::Range;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::intrinsics;
+
+use UniqueBuf;
+
+/// A buffer of Ts which is updated under a sequence lock, so readers take
+/// consistent snapshots without ever blocking the writer.
+///
+/// A writer makes the sequence number odd while it writes and even again
+/// when it is done. A reader copies the elements it wants, and retries if
+/// the sequence number was odd or changed meanwhile, since the copy may be
+/// torn. Writers are serialized with each other by the same number.
+///
+/// The elements are copied with volatile reads and writes, as the reader may
+/// race with the writer, so they must be `Copy`. This suits small blocks of
+/// statistics or telemetry which are written often and read occasionally.
+///
+/// ```
+/// # use membuf::SeqLockBuf;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// // A pair of counters which must always be read together.
+/// let stats = Arc::new(SeqLockBuf::from_slice(&[0u64, 0]));
+///
+/// let writer = stats.clone();
+/// thread::spawn(move || for i in 1..1000 { writer.write(0, &[i, i * 2]); });
+///
+/// let mut snapshot = [0; 2];
+/// stats.read(0, &mut snapshot);
+/// assert_eq!(snapshot[1], snapshot[0] * 2);
+/// ```
+pub struct SeqLockBuf<T: Copy> {
+    seq: AtomicUsize,
+    buffer: UniqueBuf<T>,
+    len: usize
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLockBuf<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLockBuf<T> {}
+
+impl<T: Copy> SeqLockBuf<T> {
+    /// Create a new buffer holding a copy of the elements of `slice`.
+    pub fn from_slice(slice: &[T]) -> SeqLockBuf<T> {
+        SeqLockBuf {
+            seq: AtomicUsize::new(0),
+            buffer: UniqueBuf::from_slice(slice),
+            len: slice.len()
+        }
+    }
+
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the sequence number, which is odd while a write is in progress
+    /// and grows by two with every write.
+    pub fn sequence(&self) -> usize {
+        self.seq.load(Ordering::Acquire)
+    }
+
+    /// Copy `values` into the elements starting at `offset`, as one update.
+    ///
+    /// Panics if the values do not fit.
+    pub fn write(&self, offset: usize, values: &[T]) {
+        let range = self.check_range(offset..offset + values.len());
+
+        let seq = self.lock();
+        unsafe {
+            for (i, value) in range.zip(values.iter()) {
+                intrinsics::volatile_store(self.buffer.offset(i as isize), *value);
+            }
+        }
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Copy a consistent snapshot of the elements starting at `offset` into
+    /// `dst`, retrying until no write overlaps the copy.
+    ///
+    /// Panics if `dst` does not fit.
+    pub fn read(&self, offset: usize, dst: &mut [T]) {
+        let range = self.check_range(offset..offset + dst.len());
+
+        self.retry(|| unsafe {
+            for (i, slot) in range.clone().zip(dst.iter_mut()) {
+                *slot = intrinsics::volatile_load(self.buffer.offset(i as isize));
+            }
+        })
+    }
+
+    /// Get a consistent snapshot of the element at `index`.
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn get(&self, index: usize) -> T {
+        self.check_range(index..index + 1);
+        self.retry(|| unsafe { intrinsics::volatile_load(self.buffer.offset(index as isize)) })
+    }
+
+    // Run `copy` until it does not overlap a write.
+    fn retry<R, F: FnMut() -> R>(&self, mut copy: F) -> R {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 { continue }
+
+            let result = copy();
+
+            // Keep the copy from moving after the second load.
+            atomic::fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq { return result }
+        }
+    }
+
+    // Make the sequence number odd, waiting for any other writer to finish.
+    fn lock(&self) -> usize {
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq % 2 == 0 && self.seq.compare_and_swap(seq, seq + 1, Ordering::Acquire) == seq {
+                // Keep the writes from moving before the odd number.
+                atomic::fence(Ordering::Release);
+                return seq
+            }
+        }
+    }
+
+    fn check_range(&self, range: Range<usize>) -> Range<usize> {
+        if range.end > self.len {
+            panic!("Range {}..{} out of bounds for buffer with length {}",
+                   range.start, range.end, self.len)
+        }
+        range
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SeqLockBuf;
+
+    #[test]
+    fn test_snapshots_are_never_torn() {
+        let block = Arc::new(SeqLockBuf::from_slice(&[0u32; 64]));
+
+        let writers: Vec<_> = (0..2).map(|_| {
+            let block = block.clone();
+            thread::spawn(move || for i in 0..2000 { block.write(0, &[i; 64]); })
+        }).collect();
+
+        let mut snapshot = [0; 64];
+        for _ in 0..2000 {
+            block.read(0, &mut snapshot);
+            assert!(snapshot.iter().all(|&value| value == snapshot[0]));
+        }
+
+        for writer in writers { writer.join().unwrap(); }
+        assert_eq!(block.get(63), 1999);
+        assert_eq!(block.sequence(), 8000);
+    }
+
+    #[test]
+    #[should_panic = "Range 3..5 out of bounds for buffer with length 4"]
+    fn test_write_out_of_bounds() {
+        SeqLockBuf::from_slice(&[0u8; 4]).write(3, &[1, 2]);
+    }
+}
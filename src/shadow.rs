@@ -0,0 +1,81 @@
+//! Shadow tracking of which bytes of an allocation have been initialized.
+//!
+//! With the `shadow-init` feature enabled, the registry keeps a bit per byte
+//! of every live allocation, set by `MemBuf::write_at` and
+//! `MemBuf::assume_init_range`, and `MemBuf::read_at` panics if it would
+//! read bytes which were never written.
+//!
+//! Without the feature `Shadow` is zero-sized and tracks nothing.
+
+/// The initialization state of the bytes of an allocation.
+#[cfg(feature = "shadow-init")]
+pub struct Shadow {
+    bits: Vec<u64>
+}
+
+#[cfg(feature = "shadow-init")]
+impl Shadow {
+    /// The state of a fresh allocation of `size` bytes, none of them initialized.
+    pub fn new(size: usize) -> Shadow {
+        Shadow { bits: vec![0; words(size)] }
+    }
+
+    /// The state after reallocating to `size` bytes, which keeps the state
+    /// of the bytes carried over.
+    pub fn resize(mut self, size: usize) -> Shadow {
+        self.bits.resize(words(size), 0);
+
+        // Forget the bytes cut off by shrinking, which share the last word.
+        if size % 64 != 0 {
+            self.bits[size / 64] &= (1 << (size % 64)) - 1;
+        }
+        self
+    }
+
+    /// Mark the bytes in `start..end` as initialized.
+    pub fn mark(&mut self, start: usize, end: usize) {
+        for byte in start..end {
+            self.bits[byte / 64] |= 1 << (byte % 64);
+        }
+    }
+
+    /// Are all the bytes in `start..end` initialized?
+    pub fn is_marked(&self, start: usize, end: usize) -> bool {
+        (start..end).all(|byte| self.bits[byte / 64] & (1 << (byte % 64)) != 0)
+    }
+}
+
+#[cfg(feature = "shadow-init")]
+fn words(size: usize) -> usize {
+    (size + 63) / 64
+}
+
+/// The initialization state of the bytes of an allocation.
+#[cfg(not(feature = "shadow-init"))]
+pub struct Shadow;
+
+#[cfg(not(feature = "shadow-init"))]
+impl Shadow {
+    /// The state of a fresh allocation of `size` bytes, none of them initialized.
+    #[inline(always)]
+    pub fn new(_: usize) -> Shadow { Shadow }
+
+    /// The state after reallocating to `size` bytes.
+    #[inline(always)]
+    pub fn resize(self, _: usize) -> Shadow { Shadow }
+}
+
+#[cfg(all(test, feature = "shadow-init"))]
+mod test {
+    use super::Shadow;
+
+    #[test]
+    fn test_shrinking_forgets_cut_off_bytes() {
+        let mut shadow = Shadow::new(100);
+        shadow.mark(0, 100);
+
+        let shadow = shadow.resize(10).resize(100);
+        assert!(shadow.is_marked(0, 10));
+        assert!((10..100).all(|byte| !shadow.is_marked(byte, byte + 1)));
+    }
+}
@@ -0,0 +1,199 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use UniqueBuf;
+
+/// A pool of buffers of one capacity, split into shards so threads rarely
+/// contend for the same lock.
+///
+/// Each thread is assigned a home shard the first time it uses any sharded
+/// pool, round-robin, and acquires and releases buffers there. When its
+/// shard runs dry, it steals half the buffers of the first other shard which
+/// has any, so buffers released on one thread flow to threads which need
+/// them. Only if every shard is empty is a new buffer allocated.
+///
+/// ```
+/// # use membuf::ShardedPool;
+///
+/// let pool: ShardedPool<u8> = ShardedPool::new(4, 4096);
+///
+/// let buffer = pool.acquire();
+/// assert_eq!(buffer.capacity(), 4096);
+/// pool.release(buffer);
+///
+/// assert_eq!(pool.pooled(), 1);
+/// ```
+#[derive(Debug)]
+pub struct ShardedPool<T> {
+    shards: Vec<Shard<T>>,
+    buffer_cap: usize,
+    max_per_shard: usize
+}
+
+// One shard's lock, followed by padding so the locks of neighbouring shards
+// never share a cache line, whose ping-ponging between cores would undo the
+// point of sharding.
+#[derive(Debug)]
+struct Shard<T> {
+    buffers: Mutex<Vec<UniqueBuf<T>>>,
+    _pad: [u64; CACHE_LINE / 8]
+}
+
+// The cache line size of common CPUs, in bytes.
+const CACHE_LINE: usize = 64;
+
+// The number of buffers kept in each shard unless `set_max_per_shard` is
+// called.
+const DEFAULT_MAX_PER_SHARD: usize = 64;
+
+static NEXT_THREAD: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local!(static THREAD_INDEX: Cell<Option<usize>> = Cell::new(None));
+
+// A number unique to the calling thread, used to pick its shard.
+fn thread_index() -> usize {
+    THREAD_INDEX.with(|index| match index.get() {
+        Some(index) => index,
+        None => {
+            let next = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+            index.set(Some(next));
+            next
+        }
+    })
+}
+
+impl<T> ShardedPool<T> {
+    /// Create a new pool with `shards` shards, handing out buffers with
+    /// capacity for `buffer_cap` Ts.
+    ///
+    /// One shard per CPU is a good starting point.
+    pub fn new(shards: usize, buffer_cap: usize) -> ShardedPool<T> {
+        assert!(shards > 0, "ShardedPool needs at least one shard");
+
+        ShardedPool {
+            shards: (0..shards).map(|_| {
+                Shard { buffers: Mutex::new(Vec::new()), _pad: [0; CACHE_LINE / 8] }
+            }).collect(),
+            buffer_cap: buffer_cap,
+            max_per_shard: DEFAULT_MAX_PER_SHARD
+        }
+    }
+
+    /// Set the number of buffers kept in each shard. Buffers released to a
+    /// full shard are freed.
+    pub fn set_max_per_shard(&mut self, max: usize) {
+        self.max_per_shard = max;
+    }
+
+    /// Get the capacity of the buffers in the pool.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffer_cap
+    }
+
+    /// Take a buffer from the calling thread's shard, stealing from another
+    /// shard or allocating if it is empty.
+    ///
+    /// The contents of a reused buffer are whatever its last user left.
+    pub fn acquire(&self) -> UniqueBuf<T> {
+        let home = thread_index() % self.shards.len();
+        if let Some(buffer) = lock(&self.shards[home].buffers).pop() { return buffer }
+
+        for i in 1..self.shards.len() {
+            let victim = &self.shards[(home + i) % self.shards.len()].buffers;
+
+            // Skip shards which are busy, rather than queueing behind them.
+            let stolen = match victim.try_lock() {
+                Ok(mut victim) => {
+                    let keep = victim.len() / 2;
+                    victim.split_off(keep)
+                },
+                Err(_) => continue
+            };
+
+            if !stolen.is_empty() {
+                let mut home = lock(&self.shards[home].buffers);
+                home.extend(stolen);
+                return home.pop().unwrap()
+            }
+        }
+
+        UniqueBuf::allocate(self.buffer_cap)
+    }
+
+    /// Return a buffer to the calling thread's shard.
+    ///
+    /// Buffers of another capacity, and buffers released to a full shard,
+    /// are freed instead.
+    pub fn release(&self, buffer: UniqueBuf<T>) {
+        if buffer.capacity() != self.buffer_cap { return }
+
+        let mut shard = lock(&self.shards[thread_index() % self.shards.len()].buffers);
+        if shard.len() < self.max_per_shard { shard.push(buffer) }
+    }
+
+    /// Get the number of buffers held by the pool.
+    pub fn pooled(&self) -> usize {
+        self.shards.iter().fold(0, |total, shard| total + lock(&shard.buffers).len())
+    }
+}
+
+fn lock<'a, T>(shard: &'a Mutex<T>) -> MutexGuard<'a, T> {
+    shard.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use {ShardedPool, UniqueBuf};
+
+    #[test]
+    fn test_buffers_are_stolen_across_threads() {
+        let pool: Arc<ShardedPool<u64>> = Arc::new(ShardedPool::new(4, 32));
+
+        // Release buffers on one thread...
+        let releaser = pool.clone();
+        let addresses: Vec<usize> = thread::spawn(move || {
+            let buffers: Vec<_> = (0..8).map(|_| releaser.acquire()).collect();
+            let addresses = buffers.iter().map(|buffer| **buffer as usize).collect();
+            for buffer in buffers { releaser.release(buffer); }
+            addresses
+        }).join().unwrap();
+
+        // ...and acquire them on others, without allocating.
+        for _ in 0..2 {
+            let pool = pool.clone();
+            let acquired = thread::spawn(move || {
+                let buffers: Vec<_> = (0..4).map(|_| pool.acquire()).collect();
+                buffers.iter().map(|buffer| **buffer as usize).collect::<Vec<_>>()
+            }).join().unwrap();
+
+            for address in acquired { assert!(addresses.contains(&address)); }
+        }
+    }
+
+    #[test]
+    fn test_release_caps_and_filters() {
+        let mut pool: ShardedPool<u8> = ShardedPool::new(1, 16);
+        pool.set_max_per_shard(2);
+
+        pool.release(UniqueBuf::allocate(8));
+        for _ in 0..3 { pool.release(UniqueBuf::allocate(16)); }
+        assert_eq!(pool.pooled(), 2);
+    }
+
+    #[test]
+    fn test_shard_locks_do_not_share_cache_lines() {
+        use std::mem;
+        use super::CACHE_LINE;
+
+        let pool: ShardedPool<u64> = ShardedPool::new(2, 1);
+        let first = &pool.shards[0].buffers as *const _ as usize;
+        let second = &pool.shards[1].buffers as *const _ as usize;
+
+        let gap = second - first - mem::size_of_val(&pool.shards[0].buffers);
+        assert!(gap >= CACHE_LINE);
+    }
+}
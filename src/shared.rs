@@ -0,0 +1,623 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::{isize, mem, ptr, slice, usize};
+use std::intrinsics;
+
+use {MemBuf, SliceBuf};
+
+// The most references a buffer can have. Leaking clones beyond this could
+// overflow the count and free the buffer while it is still referenced.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+// The header at the start of the allocation of a reference-counted buffer,
+// followed by its elements.
+//
+// As with `Arc`, the strong references together hold one weak reference,
+// which is released after the elements are dropped.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Header<T> {
+    pub strong: AtomicUsize,
+    pub weak: AtomicUsize,
+    pub len: usize,
+    // Aligns the elements which follow the header.
+    _elements: [T; 0]
+}
+
+// Allocate a header, with a single strong reference, followed by space for `cap`
+// elements, none of which are initialized.
+pub fn allocate<T>(cap: usize) -> MemBuf<Header<T>> {
+    let header = mem::size_of::<Header<T>>();
+    let bytes = cap.checked_mul(mem::size_of::<T>())
+        .and_then(|bytes| bytes.checked_add(header - 1))
+        .expect("Capacity overflow.");
+
+    let buffer = MemBuf::allocate(1 + bytes / header);
+    unsafe {
+        ptr::write(*buffer, Header {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            len: 0,
+            _elements: []
+        });
+    }
+    buffer
+}
+
+// The first element after the header.
+pub fn elements<T>(buffer: &MemBuf<Header<T>>) -> *mut T {
+    unsafe { buffer.offset(1) as *mut T }
+}
+
+// Drop the initialized elements, once the last strong reference is gone.
+pub unsafe fn drop_elements<T>(buffer: MemBuf<Header<T>>) {
+    let elements = elements(&buffer);
+
+    // Shorten the buffer first, so a panicking destructor leaks the rest of
+    // the elements instead of dropping them twice.
+    while (**buffer).len > 0 {
+        (**buffer).len -= 1;
+        ptr::read(elements.offset((**buffer).len as isize));
+    }
+}
+
+// Create a buffer holding just `value`.
+pub fn single<T>(value: T) -> SharedBuf<T> {
+    let buffer = allocate(1);
+    unsafe {
+        ptr::write(elements(&buffer), value);
+        (**buffer).len = 1;
+    }
+    SharedBuf { buffer: buffer, _marker: PhantomData }
+}
+
+/// An immutable buffer of Ts which can be shared between threads, freed
+/// along with its elements when the last reference is dropped.
+///
+/// The reference count is kept in a header in front of the elements, in the
+/// same allocation, so cloning never allocates. Unlike `MemBuf`, which can
+/// also be copied freely, a `SharedBuf` is always safe to use.
+///
+/// ```
+/// # use membuf::SharedBuf;
+/// use std::thread;
+///
+/// let buffer = SharedBuf::from_slice(&[1, 2, 3]);
+///
+/// let other = buffer.clone();
+/// let sum = thread::spawn(move || other.iter().fold(0, |sum, &x| sum + x)).join().unwrap();
+///
+/// assert_eq!(sum, 6);
+/// assert_eq!(buffer.strong_count(), 1);
+/// ```
+#[derive(Debug)]
+pub struct SharedBuf<T> {
+    buffer: MemBuf<Header<T>>,
+    _marker: PhantomData<T>
+}
+
+unsafe impl<T: Send + Sync> Send for SharedBuf<T> {}
+unsafe impl<T: Send + Sync> Sync for SharedBuf<T> {}
+
+impl<T> SharedBuf<T> {
+    /// Create a new buffer holding clones of the elements of `slice`.
+    pub fn from_slice(slice: &[T]) -> SharedBuf<T> where T: Clone {
+        let buffer: MemBuf<Header<T>> = allocate(slice.len());
+        let elements = elements(&buffer);
+
+        // Count each element as soon as it is written, so a panicking clone
+        // only drops those written so far.
+        let shared = SharedBuf { buffer: buffer, _marker: PhantomData };
+        for (index, element) in slice.iter().enumerate() {
+            unsafe {
+                ptr::write(elements.offset(index as isize), element.clone());
+                (*shared.header_ptr()).len += 1;
+            }
+        }
+        shared
+    }
+
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.header().len
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(elements(&self.buffer), self.len()) }
+    }
+
+    /// Get the number of `SharedBuf`s referring to this buffer.
+    pub fn strong_count(&self) -> usize {
+        self.header().strong.load(Ordering::SeqCst)
+    }
+
+    /// Get the number of `WeakBuf`s referring to this buffer.
+    pub fn weak_count(&self) -> usize {
+        match self.header().weak.load(Ordering::SeqCst) {
+            // Locked by `is_unique`, which only happens without weak references.
+            usize::MAX => 0,
+            weak => weak - 1
+        }
+    }
+
+    /// Do both buffers refer to the same allocation?
+    pub fn ptr_eq(&self, other: &SharedBuf<T>) -> bool {
+        *self.buffer == *other.buffer
+    }
+
+    /// Create a weak reference to this buffer, which does not keep its
+    /// elements alive.
+    pub fn downgrade(&self) -> WeakBuf<T> {
+        let weak = &self.header().weak;
+        let mut current = weak.load(Ordering::Relaxed);
+
+        loop {
+            // Wait while `is_unique` has locked the weak count.
+            if current == usize::MAX {
+                current = weak.load(Ordering::Relaxed);
+                continue
+            }
+
+            if current > MAX_REFCOUNT {
+                unsafe { intrinsics::abort() }
+            }
+
+            // Acquire pairs with the release in `is_unique`, so this weak
+            // reference is ordered after any use of the elements it made.
+            let previous = weak.compare_and_swap(current, current + 1, Ordering::Acquire);
+            if previous == current { break }
+            current = previous;
+        }
+
+        WeakBuf { buffer: self.buffer, _marker: PhantomData }
+    }
+
+    /// Is this the only reference to the buffer, strong or weak?
+    pub fn is_unique(&self) -> bool {
+        // As with `Arc`, lock the weak count while checking the strong count,
+        // so another strong reference cannot be downgraded and dropped in
+        // between the two checks. Without weak references, no other strong
+        // reference can appear except through this one.
+        let weak = &self.header().weak;
+        if weak.compare_and_swap(1, usize::MAX, Ordering::Acquire) != 1 {
+            return false
+        }
+
+        // Acquire pairs with the release in `Drop`, so this thread sees all
+        // writes made through the other strong references.
+        let unique = self.header().strong.load(Ordering::Acquire) == 1;
+        weak.store(1, Ordering::Release);
+        unique
+    }
+
+    /// View the elements as a mutable slice, if this is the only reference
+    /// to the buffer.
+    ///
+    /// ```
+    /// # use membuf::SharedBuf;
+    ///
+    /// let mut buffer = SharedBuf::from_slice(&[1, 2]);
+    /// buffer.get_mut().unwrap()[0] = 3;
+    ///
+    /// let other = buffer.clone();
+    /// assert!(buffer.get_mut().is_none());
+    /// assert_eq!(&*other, &[3, 2]);
+    /// ```
+    pub fn get_mut(&mut self) -> Option<&mut [T]> {
+        if !self.is_unique() { return None }
+        Some(unsafe { slice::from_raw_parts_mut(elements(&self.buffer), self.len()) })
+    }
+
+    /// Convert this buffer into an `RcBuf`, if it is the only reference,
+    /// without copying it.
+    ///
+    /// A buffer with `WeakBuf`s referring to it is never converted, since
+    /// they could be upgraded by other threads.
+    ///
+    /// ```
+    /// # use membuf::SharedBuf;
+    ///
+    /// let buffer = SharedBuf::from_slice(&[1, 2]);
+    /// let other = buffer.clone();
+    ///
+    /// let buffer = buffer.try_into_rc().unwrap_err();
+    /// drop(other);
+    /// assert_eq!(&*buffer.try_into_rc().unwrap(), &[1, 2]);
+    /// ```
+    pub fn try_into_rc(self) -> Result<RcBuf<T>, SharedBuf<T>> {
+        if !self.is_unique() { return Err(self) }
+        Ok(RcBuf { buffer: self.into_buffer(), _marker: PhantomData })
+    }
+
+    fn header(&self) -> &Header<T> {
+        unsafe { &**self.buffer }
+    }
+
+    fn header_ptr(&self) -> *mut Header<T> {
+        *self.buffer
+    }
+
+    // Give up this reference without decrementing the count.
+    fn into_buffer(self) -> MemBuf<Header<T>> {
+        let buffer = self.buffer;
+        mem::forget(self);
+        buffer
+    }
+}
+
+impl<T> Clone for SharedBuf<T> {
+    fn clone(&self) -> SharedBuf<T> {
+        // As with `Arc`, new references can only be made from existing ones,
+        // so no ordering is needed.
+        if self.header().strong.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+            unsafe { intrinsics::abort() }
+        }
+
+        SharedBuf { buffer: self.buffer, _marker: PhantomData }
+    }
+}
+
+impl<T> Drop for SharedBuf<T> {
+    fn drop(&mut self) {
+        if self.header().strong.fetch_sub(1, Ordering::Release) != 1 { return }
+
+        // Every other reference has been dropped, and their uses of the
+        // elements must happen before the elements are dropped.
+        atomic::fence(Ordering::Acquire);
+        unsafe { drop_elements(self.buffer) }
+
+        // Release the weak reference held by the strong references.
+        drop(WeakBuf { buffer: self.buffer, _marker: PhantomData })
+    }
+}
+
+impl<T> Deref for SharedBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+/// The initialized elements are moved into a new, exactly sized, shared
+/// allocation.
+///
+/// ```
+/// # use membuf::{SharedBuf, SliceBuf};
+///
+/// let buffer = SharedBuf::from(SliceBuf::from_slice(b"abc"));
+/// assert_eq!(&*buffer, b"abc");
+/// ```
+impl<T> From<SliceBuf<T>> for SharedBuf<T> {
+    fn from(buffer: SliceBuf<T>) -> SharedBuf<T> {
+        let shared = allocate(buffer.len());
+
+        unsafe {
+            ptr::copy_nonoverlapping(buffer.as_ptr(), elements(&shared), buffer.len());
+            (**shared).len = buffer.len();
+        }
+
+        // `SliceBuf` frees its memory without dropping the elements moved out.
+        SharedBuf { buffer: shared, _marker: PhantomData }
+    }
+}
+
+/// A weak reference to a `SharedBuf`, which keeps its allocation but not its
+/// elements alive.
+///
+/// Caches can hold `WeakBuf`s to large buffers without keeping them in
+/// memory. Once the last `SharedBuf` is dropped the elements are dropped and
+/// `upgrade` fails; the memory itself is freed with the last `WeakBuf`.
+///
+/// ```
+/// # use membuf::SharedBuf;
+///
+/// let buffer = SharedBuf::from_slice(b"cached");
+/// let weak = buffer.downgrade();
+///
+/// assert_eq!(&*weak.upgrade().unwrap(), b"cached");
+///
+/// drop(buffer);
+/// assert!(weak.upgrade().is_none());
+/// ```
+#[derive(Debug)]
+pub struct WeakBuf<T> {
+    buffer: MemBuf<Header<T>>,
+    _marker: PhantomData<T>
+}
+
+unsafe impl<T: Send + Sync> Send for WeakBuf<T> {}
+unsafe impl<T: Send + Sync> Sync for WeakBuf<T> {}
+
+impl<T> WeakBuf<T> {
+    /// Get a strong reference to the buffer, unless its elements have
+    /// already been dropped.
+    pub fn upgrade(&self) -> Option<SharedBuf<T>> {
+        let strong = &self.header().strong;
+        let mut count = strong.load(Ordering::Relaxed);
+
+        // Only take a strong reference while another one keeps the elements
+        // alive.
+        loop {
+            if count == 0 { return None }
+            if count > MAX_REFCOUNT { unsafe { intrinsics::abort() } }
+
+            let previous = strong.compare_and_swap(count, count + 1, Ordering::Acquire);
+            if previous == count {
+                return Some(SharedBuf { buffer: self.buffer, _marker: PhantomData })
+            }
+            count = previous;
+        }
+    }
+
+    /// Get the number of `SharedBuf`s referring to the buffer.
+    pub fn strong_count(&self) -> usize {
+        self.header().strong.load(Ordering::SeqCst)
+    }
+
+    fn header(&self) -> &Header<T> {
+        unsafe { &**self.buffer }
+    }
+}
+
+impl<T> Clone for WeakBuf<T> {
+    fn clone(&self) -> WeakBuf<T> {
+        if self.header().weak.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+            unsafe { intrinsics::abort() }
+        }
+
+        WeakBuf { buffer: self.buffer, _marker: PhantomData }
+    }
+}
+
+impl<T> Drop for WeakBuf<T> {
+    fn drop(&mut self) {
+        if self.header().weak.fetch_sub(1, Ordering::Release) != 1 { return }
+
+        atomic::fence(Ordering::Acquire);
+        unsafe { self.buffer.deallocate() }
+    }
+}
+
+/// A single-threaded sibling of `SharedBuf`, whose reference count is not
+/// updated atomically.
+///
+/// Cloning and dropping an `RcBuf` is cheaper than for a `SharedBuf`, which
+/// matters for structures which do either very often, such as graphs. Both
+/// have the same layout, so a buffer which is not shared can be converted
+/// between them without copying.
+///
+/// ```
+/// # use membuf::RcBuf;
+///
+/// let buffer = RcBuf::from_slice(&["a".to_string(), "b".to_string()]);
+/// let other = buffer.clone();
+///
+/// assert_eq!(other[1], "b");
+/// assert_eq!(buffer.strong_count(), 2);
+/// ```
+#[derive(Debug)]
+pub struct RcBuf<T> {
+    buffer: MemBuf<Header<T>>,
+    _marker: PhantomData<T>
+}
+
+impl<T> RcBuf<T> {
+    /// Create a new buffer holding clones of the elements of `slice`.
+    pub fn from_slice(slice: &[T]) -> RcBuf<T> where T: Clone {
+        RcBuf { buffer: SharedBuf::from_slice(slice).into_buffer(), _marker: PhantomData }
+    }
+
+    /// Get the number of elements.
+    pub fn len(&self) -> usize {
+        self.header().len
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(elements(&self.buffer), self.len()) }
+    }
+
+    /// Get the number of `RcBuf`s referring to this buffer.
+    pub fn strong_count(&self) -> usize {
+        self.header().strong.load(Ordering::Relaxed)
+    }
+
+    /// Do both buffers refer to the same allocation?
+    pub fn ptr_eq(&self, other: &RcBuf<T>) -> bool {
+        *self.buffer == *other.buffer
+    }
+
+    /// Convert this buffer into a `SharedBuf`, if it is the only reference,
+    /// without copying it.
+    pub fn try_into_shared(self) -> Result<SharedBuf<T>, RcBuf<T>> {
+        if self.strong_count() != 1 { return Err(self) }
+        Ok(SharedBuf { buffer: self.into_buffer(), _marker: PhantomData })
+    }
+
+    fn header(&self) -> &Header<T> {
+        unsafe { &**self.buffer }
+    }
+
+    // Set the count with plain loads and stores, which relaxed atomic
+    // accesses compile to, rather than read-modify-write instructions.
+    fn set_strong_count(&self, count: usize) {
+        self.header().strong.store(count, Ordering::Relaxed)
+    }
+
+    // Give up this reference without decrementing the count.
+    fn into_buffer(self) -> MemBuf<Header<T>> {
+        let buffer = self.buffer;
+        mem::forget(self);
+        buffer
+    }
+}
+
+impl<T> Clone for RcBuf<T> {
+    fn clone(&self) -> RcBuf<T> {
+        let count = self.strong_count();
+        if count > MAX_REFCOUNT { unsafe { intrinsics::abort() } }
+
+        self.set_strong_count(count + 1);
+        RcBuf { buffer: self.buffer, _marker: PhantomData }
+    }
+}
+
+impl<T> Drop for RcBuf<T> {
+    fn drop(&mut self) {
+        let count = self.strong_count();
+        self.set_strong_count(count - 1);
+        if count != 1 { return }
+
+        // An `RcBuf` never has weak references besides the one held by the
+        // strong references.
+        unsafe {
+            drop_elements(self.buffer);
+            self.buffer.deallocate()
+        }
+    }
+}
+
+impl<T> Deref for RcBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+/// The initialized elements are moved into a new, exactly sized, shared
+/// allocation.
+impl<T> From<SliceBuf<T>> for RcBuf<T> {
+    fn from(buffer: SliceBuf<T>) -> RcBuf<T> {
+        RcBuf { buffer: SharedBuf::from(buffer).into_buffer(), _marker: PhantomData }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use std::rc::Rc;
+
+    use {RcBuf, SharedBuf};
+
+    #[test]
+    fn test_last_drop_drops_elements() {
+        let counted = Arc::new(());
+        let buffer = SharedBuf::from_slice(&[counted.clone(), counted.clone()]);
+        assert_eq!(Arc::strong_count(&counted), 3);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let buffer = buffer.clone();
+            thread::spawn(move || assert_eq!(buffer.len(), 2))
+        }).collect();
+        for thread in threads { thread.join().unwrap(); }
+
+        let other = buffer.clone();
+        assert!(other.ptr_eq(&buffer));
+        assert_eq!(buffer.strong_count(), 2);
+
+        drop(buffer);
+        assert_eq!(Arc::strong_count(&counted), 3);
+        drop(other);
+        assert_eq!(Arc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    fn test_element_alignment() {
+        let bytes = SharedBuf::from_slice(&[1u8; 3]);
+        assert_eq!(&*bytes, &[1, 1, 1]);
+
+        let wide = SharedBuf::from_slice(&[1u64 << 40; 5]);
+        assert_eq!(wide.as_ptr() as usize % 8, 0);
+        assert_eq!(wide.iter().fold(0, |sum, &x| sum + x), 5 << 40);
+
+        let empty: SharedBuf<()> = SharedBuf::from_slice(&[(); 7]);
+        assert_eq!(empty.len(), 7);
+    }
+
+    #[test]
+    fn test_weak_drop_ordering() {
+        let counted = Arc::new(());
+        let buffer = SharedBuf::from_slice(&[counted.clone()]);
+        let weak = buffer.downgrade();
+        let other = weak.clone();
+        assert_eq!((buffer.strong_count(), buffer.weak_count()), (1, 2));
+
+        // A weak reference blocks conversion, since it could be upgraded.
+        let buffer = buffer.try_into_rc().unwrap_err();
+
+        // The elements outlive the weak references dropped first...
+        drop(other);
+        let upgraded = weak.upgrade().unwrap();
+        drop(buffer);
+        assert_eq!(Arc::strong_count(&counted), 2);
+
+        // ...but not the last strong reference, while the weak one remains.
+        drop(upgraded);
+        assert_eq!(Arc::strong_count(&counted), 1);
+        assert_eq!(weak.strong_count(), 0);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_upgrade_races_drop() {
+        for _ in 0..100 {
+            let buffer = SharedBuf::from_slice(&[1u64; 16]);
+            let weak = buffer.downgrade();
+
+            let upgrader = thread::spawn(move || {
+                if let Some(buffer) = weak.upgrade() {
+                    assert_eq!(buffer.iter().fold(0, |sum, &x| sum + x), 16);
+                }
+            });
+            drop(buffer);
+            upgrader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_get_mut_races_downgrade() {
+        for _ in 0..100 {
+            let mut buffer = SharedBuf::from_slice(&[1u64; 16]);
+            let other = buffer.clone();
+
+            // Another strong reference is downgraded and dropped while
+            // `get_mut` checks for uniqueness, leaving a weak one behind.
+            let reader = thread::spawn(move || {
+                let weak = other.downgrade();
+                drop(other);
+                if let Some(buffer) = weak.upgrade() {
+                    assert!(buffer.iter().all(|&x| x == buffer[0]));
+                }
+            });
+
+            if let Some(elements) = buffer.get_mut() {
+                for x in elements.iter_mut() { *x = 2 }
+            }
+            drop(buffer);
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rc_conversion_keeps_allocation() {
+        let counted = Rc::new(());
+        let buffer = RcBuf::from_slice(&[counted.clone()]);
+        let other = buffer.clone();
+        assert_eq!(Rc::strong_count(&counted), 2);
+
+        let buffer = buffer.try_into_shared().unwrap_err();
+        drop(other);
+
+        let address = buffer.as_ptr();
+        let shared = buffer.try_into_shared().unwrap();
+        assert_eq!(shared.as_ptr(), address);
+        assert_eq!(shared.strong_count(), 1);
+
+        let buffer = shared.try_into_rc().unwrap();
+        assert_eq!(buffer.strong_count(), 1);
+        drop(buffer);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+}
@@ -0,0 +1,529 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::{cmp, ptr, slice};
+
+use {HexDump, UniqueBuf};
+
+/// A buffer of Ts which tracks how many of its elements are initialized.
+///
+/// `SliceBuf` is the layer most collections build on top of `UniqueBuf`:
+/// the first `len()` elements are initialized, so they can be viewed safely
+/// as a slice, and the rest of the capacity is spare room for new elements.
+///
+/// Like `UniqueBuf`, the destructor does not drop any of the elements, it
+/// only frees the memory. See `DropBuf` for a buffer which drops them.
+///
+/// ```
+/// # use membuf::SliceBuf;
+///
+/// let mut buffer: SliceBuf<u32> = SliceBuf::allocate(4);
+/// buffer.write(0, 7);
+/// buffer.write(1, 9);
+/// unsafe { buffer.set_len(2); }
+///
+/// assert_eq!(buffer.as_slice(), &[7, 9]);
+/// ```
+///
+/// With the `hexdump-debug` feature, the alternate `Debug` output of a
+/// `SliceBuf<u8>`, `{:#?}`, is a hexdump of its initialized bytes.
+pub struct SliceBuf<T> {
+    buffer: UniqueBuf<T>,
+    len: usize
+}
+
+impl<T> SliceBuf<T> {
+    /// Create a new, empty SliceBuf.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer: SliceBuf<u8> = SliceBuf::new();
+    /// assert_eq!(buffer.len(), 0);
+    /// assert_eq!(buffer.capacity(), 0);
+    /// ```
+    pub fn new() -> SliceBuf<T> {
+        SliceBuf { buffer: UniqueBuf::new(), len: 0 }
+    }
+
+    /// Create a new buffer with space for cap Ts, none of which are
+    /// initialized.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer: SliceBuf<u8> = SliceBuf::allocate(32);
+    /// assert_eq!(buffer.len(), 0);
+    /// assert_eq!(buffer.capacity(), 32);
+    /// ```
+    pub fn allocate(cap: usize) -> SliceBuf<T> {
+        SliceBuf { buffer: UniqueBuf::allocate(cap), len: 0 }
+    }
+
+    /// Create a new buffer with exactly enough space for `slice`, holding a
+    /// copy of its elements.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer = SliceBuf::from_slice(&[1u8, 2, 3]);
+    /// assert_eq!(buffer.len(), 3);
+    /// assert_eq!(buffer.as_slice(), &[1, 2, 3]);
+    /// ```
+    pub fn from_slice(slice: &[T]) -> SliceBuf<T> where T: Copy {
+        SliceBuf { buffer: UniqueBuf::from_slice(slice), len: slice.len() }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts.
+    ///
+    /// If the buffer shrinks below its length, the length is reduced to
+    /// match, without dropping the elements which no longer fit.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let mut buffer: SliceBuf<u8> = SliceBuf::allocate(4);
+    /// buffer.reallocate(16);
+    /// assert_eq!(buffer.capacity(), 16);
+    /// ```
+    pub fn reallocate(&mut self, cap: usize) {
+        self.buffer.reallocate_with_len(cap, self.len);
+        self.len = cmp::min(self.len, cap);
+    }
+
+    /// Get the number of initialized elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the current capacity of the SliceBuf.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Set the number of initialized elements.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    pub unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.capacity());
+        self.len = len;
+    }
+
+    /// View the initialized elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { self.buffer.as_slice(self.len) }
+    }
+
+    /// View the initialized elements as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { self.buffer.as_mut_slice(self.len) }
+    }
+
+    /// Format the bytes of the initialized elements as a hexdump.
+    ///
+    /// ## Safety
+    ///
+    /// Every byte of the initialized elements is read, so T must not contain
+    /// padding, or the padding must have been initialized.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let mut buffer: SliceBuf<u16> = SliceBuf::allocate(4);
+    /// buffer.write(0, 0x6b6f);
+    /// unsafe { buffer.set_len(1); }
+    ///
+    /// let dump = unsafe { buffer.hexdump() }.to_string();
+    /// assert!(dump.starts_with("00000000  6f 6b "));
+    /// ```
+    pub unsafe fn hexdump<'a>(&'a self) -> HexDump<'a> {
+        self.buffer.hexdump(self.len)
+    }
+
+    /// Write `value` into the element at `index`, without reading or dropping
+    /// the old value.
+    ///
+    /// Panics if `index` is not less than the capacity. This does not change
+    /// the length, so `set_len` must be used to include new elements.
+    pub fn write(&mut self, index: usize, value: T) {
+        assert!(index < self.capacity(), "Write to index {} of SliceBuf with capacity {}",
+                index, self.capacity());
+
+        unsafe { ptr::write(self.buffer.offset(index as isize), value) }
+    }
+
+    /// Get the uninitialized capacity after the initialized elements.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let mut buffer: SliceBuf<u32> = SliceBuf::allocate(4);
+    /// buffer.write(0, 1);
+    /// unsafe { buffer.set_len(1); }
+    ///
+    /// {
+    ///     let mut spare = buffer.spare_capacity_mut();
+    ///     assert_eq!(spare.len(), 3);
+    ///     spare.write(0, 2);
+    ///     spare.write(1, 3);
+    /// }
+    ///
+    /// unsafe { buffer.set_len(3); }
+    /// assert_eq!(buffer.as_slice(), &[1, 2, 3]);
+    /// ```
+    pub fn spare_capacity_mut<'a>(&'a mut self) -> SpareCapacity<'a, T> {
+        SpareCapacity {
+            ptr: unsafe { self.buffer.offset(self.len as isize) },
+            len: self.capacity() - self.len,
+            _marker: PhantomData
+        }
+    }
+
+    /// Get a read-only view of the capacity beyond the initialized elements.
+    ///
+    /// Reading from the view is unsafe, since its elements may not be
+    /// initialized.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let mut buffer: SliceBuf<u8> = SliceBuf::allocate(4);
+    /// buffer.spare_capacity_mut().write(0, 7);
+    ///
+    /// let spare = buffer.spare_capacity();
+    /// assert_eq!(spare.len(), 4);
+    /// assert_eq!(unsafe { spare.as_slice(1) }, &[7]);
+    /// ```
+    pub fn spare_capacity<'a>(&'a self) -> UninitCapacity<'a, T> {
+        UninitCapacity {
+            ptr: unsafe { self.buffer.offset(self.len as isize) },
+            len: self.capacity() - self.len,
+            _marker: PhantomData
+        }
+    }
+
+    /// Create a SliceBuf from a UniqueBuf whose first `len` elements are
+    /// initialized.
+    pub unsafe fn from_raw(buffer: UniqueBuf<T>, len: usize) -> SliceBuf<T> {
+        debug_assert!(len <= buffer.capacity());
+        SliceBuf { buffer: buffer, len: len }
+    }
+
+    /// Get the underlying UniqueBuf and the number of initialized elements.
+    pub fn into_raw(self) -> (UniqueBuf<T>, usize) {
+        (self.buffer, self.len)
+    }
+
+    /// Convert this buffer into a `Vec` of its initialized elements, reusing
+    /// the allocation where possible.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer = SliceBuf::from_slice(&[1, 2, 3]);
+    /// assert_eq!(buffer.into_vec(), [1, 2, 3]);
+    /// ```
+    pub fn into_vec(self) -> Vec<T> {
+        unsafe { self.buffer.into_vec(self.len) }
+    }
+
+    /// Convert this buffer into a boxed slice of its initialized elements,
+    /// reusing the allocation where possible.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer = SliceBuf::from(vec![1, 2, 3].into_boxed_slice());
+    /// assert_eq!(buffer.len(), 3);
+    /// assert_eq!(&*buffer.into_boxed_slice(), &[1, 2, 3]);
+    /// ```
+    pub fn into_boxed_slice(self) -> Box<[T]> {
+        unsafe { self.buffer.into_boxed_slice(self.len) }
+    }
+}
+
+impl<T> From<Box<[T]>> for SliceBuf<T> {
+    fn from(slice: Box<[T]>) -> SliceBuf<T> {
+        let len = slice.len();
+        SliceBuf { buffer: UniqueBuf::from(slice), len: len }
+    }
+}
+
+impl<T> Deref for SliceBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T> DerefMut for SliceBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] { self.as_mut_slice() }
+}
+
+/// SliceBufs are compared and hashed by their initialized elements, like
+/// slices, regardless of their capacity.
+///
+/// ```
+/// # use membuf::SliceBuf;
+///
+/// let mut a: SliceBuf<u8> = SliceBuf::allocate(2);
+/// let mut b: SliceBuf<u8> = SliceBuf::allocate(8);
+/// a.write(0, 1);
+/// b.write(0, 1);
+/// unsafe { a.set_len(1); b.set_len(1); }
+///
+/// assert_eq!(a, b);
+/// ```
+#[cfg(not(feature = "hexdump-debug"))]
+impl<T: fmt::Debug> fmt::Debug for SliceBuf<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.debug_fields(f) }
+}
+
+#[cfg(feature = "hexdump-debug")]
+impl<T: fmt::Debug> fmt::Debug for SliceBuf<T> {
+    default fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.debug_fields(f) }
+}
+
+#[cfg(feature = "hexdump-debug")]
+impl fmt::Debug for SliceBuf<u8> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() { return self.debug_fields(f) }
+
+        // Bytes have no padding, so every byte of the elements is initialized.
+        fmt::Display::fmt(&unsafe { self.hexdump() }, f)
+    }
+}
+
+impl<T: fmt::Debug> SliceBuf<T> {
+    fn debug_fields(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SliceBuf")
+            .field("buffer", &self.buffer)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for SliceBuf<T> {
+    fn eq(&self, other: &SliceBuf<T>) -> bool { self.as_slice() == other.as_slice() }
+}
+
+impl<T: Eq> Eq for SliceBuf<T> {}
+
+impl<T: PartialOrd> PartialOrd for SliceBuf<T> {
+    fn partial_cmp(&self, other: &SliceBuf<T>) -> Option<Ordering> {
+        self.as_slice().partial_cmp(other.as_slice())
+    }
+}
+
+impl<T: Ord> Ord for SliceBuf<T> {
+    fn cmp(&self, other: &SliceBuf<T>) -> Ordering { self.as_slice().cmp(other.as_slice()) }
+}
+
+impl<T: Hash> Hash for SliceBuf<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.as_slice().hash(state) }
+}
+
+impl<T> UniqueBuf<T> {
+    /// Get a write-only view of the whole capacity of this buffer, which
+    /// borrows the buffer so the view cannot outlive it.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    ///
+    /// {
+    ///     let mut uninit = buffer.uninit_capacity_mut();
+    ///     for i in 0..uninit.len() { uninit.write(i, i as u32); }
+    /// }
+    ///
+    /// assert_eq!(unsafe { buffer.as_slice(4) }, &[0, 1, 2, 3]);
+    /// ```
+    pub fn uninit_capacity_mut<'a>(&'a mut self) -> SpareCapacity<'a, T> {
+        SpareCapacity { ptr: **self, len: self.capacity(), _marker: PhantomData }
+    }
+
+    /// Get a read-only view of the whole capacity of this buffer, which
+    /// borrows the buffer so the view cannot outlive it.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    /// buffer.uninit_capacity_mut().write(2, 9);
+    ///
+    /// let uninit = buffer.uninit_capacity();
+    /// assert_eq!(uninit.len(), 4);
+    /// assert_eq!(unsafe { *uninit.get(2) }, 9);
+    /// ```
+    pub fn uninit_capacity<'a>(&'a self) -> UninitCapacity<'a, T> {
+        UninitCapacity { ptr: **self, len: self.capacity(), _marker: PhantomData }
+    }
+}
+
+/// Uninitialized capacity borrowed from a buffer, by
+/// `SliceBuf::spare_capacity_mut` or `UniqueBuf::uninit_capacity_mut`.
+///
+/// Elements can only be written, since they may not be initialized.
+pub struct SpareCapacity<'a, T: 'a> {
+    ptr: *mut T,
+    len: usize,
+    _marker: PhantomData<&'a mut T>
+}
+
+impl<'a, T> SpareCapacity<'a, T> {
+    /// Get the number of uninitialized elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Write `value` into the spare element at `index`.
+    ///
+    /// Panics if `index` is not less than `len()`.
+    pub fn write(&mut self, index: usize, value: T) {
+        assert!(index < self.len, "Write to index {} of spare capacity {}", index, self.len);
+        unsafe { ptr::write(self.ptr.offset(index as isize), value) }
+    }
+
+    /// Get a pointer to the first spare element.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr
+    }
+}
+
+/// Possibly uninitialized capacity borrowed from a buffer, by
+/// `SliceBuf::spare_capacity` or `UniqueBuf::uninit_capacity`.
+///
+/// Elements can only be read unsafely, by a caller who knows they are
+/// initialized.
+pub struct UninitCapacity<'a, T: 'a> {
+    ptr: *const T,
+    len: usize,
+    _marker: PhantomData<&'a T>
+}
+
+impl<'a, T> UninitCapacity<'a, T> {
+    /// Get the number of possibly uninitialized elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get a reference to the element at `index`.
+    ///
+    /// Panics if `index` is not less than `len()`.
+    ///
+    /// ## Safety
+    ///
+    /// The element must be initialized.
+    pub unsafe fn get(&self, index: usize) -> &'a T {
+        assert!(index < self.len, "Read of index {} of spare capacity {}", index, self.len);
+        &*self.ptr.offset(index as isize)
+    }
+
+    /// View the first `len` elements as a slice.
+    ///
+    /// Panics if `len` is greater than `len()`.
+    ///
+    /// ## Safety
+    ///
+    /// The first `len` elements must be initialized.
+    pub unsafe fn as_slice(&self, len: usize) -> &'a [T] {
+        assert!(len <= self.len, "Length {} exceeds spare capacity {}", len, self.len);
+        slice::from_raw_parts(self.ptr, len)
+    }
+
+    /// Get a pointer to the first element.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use SliceBuf;
+
+    #[test]
+    fn test_hexdump() {
+        let mut buffer: SliceBuf<u8> = SliceBuf::allocate(4);
+        buffer.write(0, b'o');
+        buffer.write(1, b'k');
+        unsafe { buffer.set_len(2); }
+
+        let dump = unsafe { buffer.hexdump() }.to_string();
+        assert!(dump.starts_with("00000000  6f 6b "));
+        assert!(dump.ends_with("|ok|\n00000002\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "hexdump-debug")]
+    fn test_hexdump_debug() {
+        let buffer = SliceBuf::from_slice(b"ok");
+        assert_eq!(format!("{:#?}", buffer), unsafe { buffer.hexdump() }.to_string());
+        assert!(format!("{:?}", buffer).starts_with("SliceBuf { buffer: UniqueBuf"));
+
+        let words = SliceBuf::from_slice(&[1u32]);
+        assert!(format!("{:#?}", words).starts_with("SliceBuf {"));
+    }
+
+    #[test]
+    fn test_content_comparison() {
+        use std::collections::HashSet;
+
+        let mut a: SliceBuf<u32> = SliceBuf::allocate(4);
+        let mut b: SliceBuf<u32> = SliceBuf::allocate(2);
+        a.write(0, 3);
+        a.write(1, 4);
+        b.write(0, 3);
+        unsafe { a.set_len(2); b.set_len(1); }
+
+        assert!(b < a);
+        assert!(a != b);
+
+        b.write(1, 4);
+        unsafe { b.set_len(2); }
+        assert_eq!(a, b);
+
+        let mut set = HashSet::new();
+        set.insert(a);
+        assert!(set.contains(&b));
+    }
+
+    #[test]
+    fn test_reallocate_keeps_prefix() {
+        let mut buffer: SliceBuf<u64> = SliceBuf::allocate(4);
+        for i in 0..4 { buffer.write(i, i as u64 * 10); }
+        unsafe { buffer.set_len(4); }
+
+        buffer.reallocate(64);
+        assert_eq!(&*buffer, &[0, 10, 20, 30]);
+
+        buffer.reallocate(2);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(&*buffer, &[0, 10]);
+
+        // Sparse growth copies only the prefix into a fresh allocation.
+        buffer.reallocate(64);
+        buffer.reallocate(1024);
+        assert_eq!(&*buffer, &[0, 10]);
+    }
+
+    #[test]
+    #[should_panic = "Write to index 4 of SliceBuf with capacity 4"]
+    fn test_write_out_of_bounds() {
+        let mut buffer: SliceBuf<u8> = SliceBuf::allocate(4);
+        buffer.write(4, 0);
+    }
+
+    #[test]
+    #[should_panic = "Write to index 1 of spare capacity 1"]
+    fn test_spare_write_out_of_bounds() {
+        let mut buffer: SliceBuf<u8> = SliceBuf::allocate(2);
+        buffer.write(0, 0);
+        unsafe { buffer.set_len(1); }
+        buffer.spare_capacity_mut().write(1, 0);
+    }
+}
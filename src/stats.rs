@@ -0,0 +1,335 @@
+//! Global allocation statistics.
+//!
+//! With the `stats` feature enabled, every allocation made through `alloc`
+//! updates a handful of counters, which `stats` reads. The counters use
+//! relaxed atomics, so they are cheap to maintain but a snapshot taken while
+//! other threads allocate may be slightly inconsistent.
+//!
+//! The `tags` feature additionally keeps statistics per tag, for buffers
+//! allocated with `allocate_tagged`, behind a mutex.
+//!
+//! Without the features every function in here is a no-op.
+
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+#[cfg(feature = "tags")]
+use std::cmp;
+#[cfg(feature = "tags")]
+use std::collections::HashMap;
+#[cfg(feature = "tags")]
+use std::sync::Mutex;
+
+/// A snapshot of the allocation statistics.
+#[cfg(feature = "stats")]
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of allocations which have not been deallocated.
+    pub live_allocations: usize,
+    /// The number of bytes allocated which have not been deallocated.
+    pub live_bytes: usize,
+    /// The number of allocations ever made, not counting reallocations.
+    pub total_allocations: usize,
+    /// The largest value `live_bytes` has reached.
+    pub peak_bytes: usize
+}
+
+#[cfg(feature = "stats")]
+static LIVE_ALLOCATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "stats")]
+static LIVE_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "stats")]
+static TOTAL_ALLOCATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "stats")]
+static PEAK_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Get the current allocation statistics.
+///
+/// Only available with the `stats` feature. The statistics cover buffers
+/// allocated through `alloc`, which includes `MemBuf`, `UniqueBuf` and
+/// `SecretBuf` but not the page-backed `PageBuf`. Sizes are the requested
+/// sizes, without any padding added by the debugging features.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+///
+/// let buffer: UniqueBuf<u64> = UniqueBuf::allocate(16);
+///
+/// let stats = membuf::stats();
+/// assert!(stats.live_allocations >= 1);
+/// assert!(stats.live_bytes >= 128);
+/// assert!(stats.peak_bytes >= stats.live_bytes);
+/// # drop(buffer);
+/// ```
+#[cfg(feature = "stats")]
+pub fn stats() -> Stats {
+    Stats {
+        live_allocations: LIVE_ALLOCATIONS.load(Ordering::Relaxed),
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        total_allocations: TOTAL_ALLOCATIONS.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the allocation statistics of every tag passed to `allocate_tagged`.
+///
+/// Only available with the `tags` feature. Tags which no longer have any
+/// live allocations are still included.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+///
+/// let body: UniqueBuf<u8> = UniqueBuf::allocate_tagged(4096, "http::body");
+///
+/// let stats = membuf::stats_by_tag();
+/// assert_eq!(stats["http::body"].live_allocations, 1);
+/// assert_eq!(stats["http::body"].live_bytes, 4096);
+/// # drop(body);
+/// ```
+#[cfg(feature = "tags")]
+pub fn stats_by_tag() -> HashMap<&'static str, Stats> {
+    TAGS.lock().unwrap().stats.clone()
+}
+
+#[cfg(feature = "tags")]
+struct Tags {
+    owners: HashMap<usize, &'static str>,
+    stats: HashMap<&'static str, Stats>
+}
+
+#[cfg(feature = "tags")]
+lazy_static! {
+    static ref TAGS: Mutex<Tags> = Mutex::new(Tags {
+        owners: HashMap::new(),
+        stats: HashMap::new()
+    });
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(feature = "stats")]
+pub fn allocated(_: *mut u8, size: usize) {
+    LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    grow(size);
+}
+
+/// Record the allocation at `old_ptr` being resized from `old_size` to
+/// `size` bytes at `ptr`.
+#[cfg(feature = "stats")]
+pub fn reallocated(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize) {
+    if size > old_size {
+        grow(size - old_size)
+    } else {
+        LIVE_BYTES.fetch_sub(old_size - size, Ordering::Relaxed);
+    }
+
+    retag(old_ptr, ptr, old_size, size);
+}
+
+/// Record the allocation of `size` bytes at `ptr` being deallocated.
+#[cfg(feature = "stats")]
+pub fn deallocated(ptr: *mut u8, size: usize) {
+    LIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+
+    untag(ptr, size);
+}
+
+/// The tag a buffer's memory is counted towards, which is zero-sized unless
+/// the `tags` feature is enabled.
+///
+/// Buffers remember their tag so it survives them being emptied and
+/// reallocated, which the per-pointer bookkeeping alone cannot follow.
+#[cfg(feature = "tags")]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Tag(Option<&'static str>);
+
+/// The tag a buffer's memory is counted towards, which is zero-sized unless
+/// the `tags` feature is enabled.
+#[cfg(not(feature = "tags"))]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Tag;
+
+#[cfg(feature = "tags")]
+impl Tag {
+    /// No tag.
+    pub fn none() -> Tag { Tag(None) }
+
+    /// The tag named `name`.
+    pub fn named(name: &'static str) -> Tag { Tag(Some(name)) }
+
+    /// Count the new allocation of `size` bytes at `ptr` towards this tag.
+    pub fn apply(self, ptr: *mut u8, size: usize) {
+        if let Some(name) = self.0 { tag(ptr, size, name) }
+    }
+}
+
+#[cfg(not(feature = "tags"))]
+impl Tag {
+    /// No tag.
+    #[inline(always)]
+    pub fn none() -> Tag { Tag }
+
+    /// The tag named `name`.
+    #[inline(always)]
+    pub fn named(_: &'static str) -> Tag { Tag }
+
+    /// Count the new allocation of `size` bytes at `ptr` towards this tag.
+    #[inline(always)]
+    pub fn apply(self, _: *mut u8, _: usize) {}
+}
+
+// Count the new allocation of `size` bytes at `ptr` towards `tag`.
+#[cfg(feature = "tags")]
+fn tag(ptr: *mut u8, size: usize, tag: &'static str) {
+    let mut tags = TAGS.lock().unwrap();
+    tags.owners.insert(ptr as usize, tag);
+
+    let stats = tags.stats.entry(tag).or_insert_with(Stats::default);
+    stats.live_allocations += 1;
+    stats.total_allocations += 1;
+    stats.live_bytes += size;
+    stats.peak_bytes = cmp::max(stats.peak_bytes, stats.live_bytes);
+}
+
+#[cfg(feature = "tags")]
+fn retag(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize) {
+    let mut tags = TAGS.lock().unwrap();
+
+    if let Some(tag) = tags.owners.remove(&(old_ptr as usize)) {
+        tags.owners.insert(ptr as usize, tag);
+
+        let stats = tags.stats.get_mut(tag).unwrap();
+        stats.live_bytes = stats.live_bytes - old_size + size;
+        stats.peak_bytes = cmp::max(stats.peak_bytes, stats.live_bytes);
+    }
+}
+
+#[cfg(feature = "tags")]
+fn untag(ptr: *mut u8, size: usize) {
+    let mut tags = TAGS.lock().unwrap();
+
+    if let Some(tag) = tags.owners.remove(&(ptr as usize)) {
+        let stats = tags.stats.get_mut(tag).unwrap();
+        stats.live_allocations -= 1;
+        stats.live_bytes -= size;
+    }
+}
+
+#[cfg(all(feature = "stats", not(feature = "tags")))]
+#[inline(always)]
+fn retag(_: *mut u8, _: *mut u8, _: usize, _: usize) {}
+
+#[cfg(all(feature = "stats", not(feature = "tags")))]
+#[inline(always)]
+fn untag(_: *mut u8, _: usize) {}
+
+#[cfg(feature = "stats")]
+fn grow(size: usize) {
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    let mut peak = PEAK_BYTES.load(Ordering::Relaxed);
+
+    while live > peak {
+        match PEAK_BYTES.compare_and_swap(peak, live, Ordering::Relaxed) {
+            current if current == peak => break,
+            current => peak = current
+        }
+    }
+}
+
+/// Record a new allocation of `size` bytes at `ptr`.
+#[cfg(not(feature = "stats"))]
+#[inline(always)]
+pub fn allocated(_: *mut u8, _: usize) {}
+
+/// Record the allocation at `old_ptr` being resized from `old_size` to
+/// `size` bytes at `ptr`.
+#[cfg(not(feature = "stats"))]
+#[inline(always)]
+pub fn reallocated(_: *mut u8, _: *mut u8, _: usize, _: usize) {}
+
+/// Record the allocation of `size` bytes at `ptr` being deallocated.
+#[cfg(not(feature = "stats"))]
+#[inline(always)]
+pub fn deallocated(_: *mut u8, _: usize) {}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use super::stats;
+    use MemBuf;
+
+    #[test]
+    fn test_stats_track_buffer() {
+        let mut buffer: MemBuf<u8> = MemBuf::allocate(100);
+        let total = stats().total_allocations;
+
+        unsafe { buffer.reallocate(1 << 20); }
+
+        let grown = stats();
+        assert!(grown.live_bytes >= 1 << 20);
+        assert!(grown.peak_bytes >= 1 << 20);
+        assert!(grown.total_allocations >= total);
+
+        unsafe { buffer.deallocate(); }
+        assert!(stats().peak_bytes >= 1 << 20);
+    }
+
+    #[test]
+    #[cfg(feature = "tags")]
+    fn test_tag_follows_reallocate() {
+        use super::stats_by_tag;
+
+        let mut buffer: MemBuf<u32> = MemBuf::allocate_tagged(4, "test::realloc");
+        unsafe { buffer.reallocate(64); }
+
+        let stats = stats_by_tag()["test::realloc"];
+        assert_eq!(stats.live_allocations, 1);
+        assert_eq!(stats.live_bytes, 256);
+
+        unsafe { buffer.deallocate(); }
+
+        let stats = stats_by_tag()["test::realloc"];
+        assert_eq!(stats.live_allocations, 0);
+        assert_eq!(stats.live_bytes, 0);
+        assert_eq!(stats.total_allocations, 1);
+        assert_eq!(stats.peak_bytes, 256);
+    }
+
+    #[test]
+    #[cfg(feature = "tags")]
+    fn test_tag_survives_empty_capacity() {
+        use super::stats_by_tag;
+
+        let mut buffer: MemBuf<u32> = MemBuf::allocate_tagged(4, "test::empty");
+        unsafe {
+            buffer.reallocate(0);
+            buffer.reallocate(8);
+        }
+
+        let stats = stats_by_tag()["test::empty"];
+        assert_eq!(stats.live_allocations, 1);
+        assert_eq!(stats.live_bytes, 32);
+
+        unsafe { buffer.deallocate(); }
+        assert_eq!(stats_by_tag()["test::empty"].live_allocations, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "tags")]
+    fn test_tag_follows_derived_buffers() {
+        use super::stats_by_tag;
+        use UniqueBuf;
+
+        let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate_tagged(64, "test::derived");
+        let copy = buffer.duplicate();
+        buffer.reallocate_with_len(1024, 0);
+        let (head, tail) = buffer.split_at(1000);
+
+        let stats = stats_by_tag()["test::derived"];
+        assert_eq!(stats.live_allocations, 3);
+        assert_eq!(stats.live_bytes, 64 + 1024);
+
+        drop((copy, head, tail));
+        assert_eq!(stats_by_tag()["test::derived"].live_bytes, 0);
+    }
+}
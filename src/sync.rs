@@ -0,0 +1,151 @@
+use std::ops::{Deref, DerefMut};
+
+use MemBuf;
+
+/// A `MemBuf` which can be sent to another thread.
+///
+/// `MemBuf` is `Copy`, so it implements neither `Send` nor `Sync`: nothing
+/// stops a copy staying behind on the old thread. Creating a `SendMemBuf`
+/// asserts that one does not, or that it is never used while the sent
+/// buffer is, so the buffer can be moved to another thread without writing
+/// an `unsafe impl Send` for each structure holding it.
+///
+/// ```
+/// # use membuf::{MemBuf, SendMemBuf};
+/// use std::thread;
+///
+/// let buffer: MemBuf<u32> = MemBuf::allocate(4);
+/// let sent = unsafe { SendMemBuf::new(buffer) };
+///
+/// thread::spawn(move || unsafe {
+///     *sent.offset(0) = 7;
+///     sent.into_inner().deallocate();
+/// }).join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SendMemBuf<T> {
+    inner: MemBuf<T>
+}
+
+unsafe impl<T: Send> Send for SendMemBuf<T> {}
+
+impl<T> SendMemBuf<T> {
+    /// Wrap `buffer` so it can be sent to another thread.
+    ///
+    /// ## Safety
+    ///
+    /// No other copy of `buffer` may be used while the wrapped buffer, or
+    /// any copy taken out of it, is in use on another thread.
+    pub unsafe fn new(buffer: MemBuf<T>) -> SendMemBuf<T> {
+        SendMemBuf { inner: buffer }
+    }
+
+    /// Get the wrapped buffer.
+    pub fn into_inner(self) -> MemBuf<T> {
+        self.inner
+    }
+}
+
+/// A `MemBuf` which can be shared between threads.
+///
+/// Creating a `SyncMemBuf` asserts that every access to the elements of the
+/// buffer, through any copy, on any thread, is synchronized by the caller,
+/// for example with a lock or by giving each thread a disjoint range.
+///
+/// It also asserts that the buffer is not reallocated or deallocated while
+/// it is shared. `MemBuf` is `Copy`, so a copy taken through `Deref` can
+/// still be deallocated from any thread holding the wrapper; doing so while
+/// another thread uses the buffer is a use after free.
+///
+/// ```
+/// # use membuf::{MemBuf, SyncMemBuf};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let buffer: MemBuf<u64> = MemBuf::allocate(2);
+/// let shared = Arc::new(unsafe { SyncMemBuf::new(buffer) });
+///
+/// // Each thread writes only its own element.
+/// let threads: Vec<_> = (0..2).map(|i| {
+///     let shared = shared.clone();
+///     thread::spawn(move || unsafe { *shared.offset(i) = i as u64 })
+/// }).collect();
+/// for thread in threads { thread.join().unwrap(); }
+///
+/// let buffer = Arc::try_unwrap(shared).unwrap().into_inner();
+/// unsafe {
+///     assert_eq!(*buffer.offset(1), 1);
+///     buffer.deallocate();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SyncMemBuf<T> {
+    inner: MemBuf<T>
+}
+
+unsafe impl<T: Send> Send for SyncMemBuf<T> {}
+unsafe impl<T: Send> Sync for SyncMemBuf<T> {}
+
+impl<T> SyncMemBuf<T> {
+    /// Wrap `buffer` so it can be shared between threads.
+    ///
+    /// ## Safety
+    ///
+    /// Every access to the elements of the buffer must be synchronized by
+    /// the caller, and no copy of `buffer`, including those taken out of the
+    /// wrapper through `Deref`, may be reallocated or deallocated while the
+    /// wrapped buffer is in use on any other thread.
+    pub unsafe fn new(buffer: MemBuf<T>) -> SyncMemBuf<T> {
+        SyncMemBuf { inner: buffer }
+    }
+
+    /// Get the wrapped buffer.
+    pub fn into_inner(self) -> MemBuf<T> {
+        self.inner
+    }
+}
+
+macro_rules! wrapper_impls {
+    ($wrapper:ident) => {
+        impl<T> Deref for $wrapper<T> {
+            type Target = MemBuf<T>;
+
+            fn deref(&self) -> &MemBuf<T> { &self.inner }
+        }
+
+        impl<T> DerefMut for $wrapper<T> {
+            fn deref_mut(&mut self) -> &mut MemBuf<T> { &mut self.inner }
+        }
+
+        impl<T> From<$wrapper<T>> for MemBuf<T> {
+            fn from(wrapper: $wrapper<T>) -> MemBuf<T> { wrapper.inner }
+        }
+    }
+}
+
+wrapper_impls!(SendMemBuf);
+wrapper_impls!(SyncMemBuf);
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use {MemBuf, SendMemBuf};
+
+    #[test]
+    fn test_send_round_trip() {
+        let buffer = unsafe { SendMemBuf::new(MemBuf::<u8>::allocate(16)) };
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = buffer;
+            unsafe { buffer.reallocate(32); }
+            tx.send(buffer).unwrap();
+        }).join().unwrap();
+
+        let buffer = MemBuf::from(rx.recv().unwrap());
+        assert_eq!(buffer.capacity(), 32);
+        unsafe { buffer.deallocate() }
+    }
+}
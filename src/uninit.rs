@@ -0,0 +1,136 @@
+use std::ptr;
+
+use {SliceBuf, SpareCapacity, UniqueBuf};
+
+/// A buffer of Ts, none of which are known to be initialized.
+///
+/// An `UninitBuf` can only be written to. Once its first elements are
+/// initialized, `assume_init` turns it into a `SliceBuf`, which allows
+/// reading them. Keeping the two states in separate types means elements
+/// cannot be read before they are written by mistake.
+///
+/// ```
+/// # use membuf::UninitBuf;
+///
+/// let mut buffer: UninitBuf<u32> = UninitBuf::allocate(4);
+/// for i in 0..3 { buffer.write(i, i as u32 + 1); }
+///
+/// let buffer = unsafe { buffer.assume_init(3) };
+/// assert_eq!(&*buffer, &[1, 2, 3]);
+/// assert_eq!(buffer.capacity(), 4);
+/// ```
+#[derive(Debug)]
+pub struct UninitBuf<T> {
+    buffer: UniqueBuf<T>
+}
+
+impl<T> UninitBuf<T> {
+    /// Create a new, empty UninitBuf.
+    pub fn new() -> UninitBuf<T> {
+        UninitBuf { buffer: UniqueBuf::new() }
+    }
+
+    /// Create a new buffer with space for cap Ts.
+    ///
+    /// ```
+    /// # use membuf::UninitBuf;
+    ///
+    /// let buffer: UninitBuf<u8> = UninitBuf::allocate(16);
+    /// assert_eq!(buffer.capacity(), 16);
+    /// ```
+    pub fn allocate(cap: usize) -> UninitBuf<T> {
+        UninitBuf { buffer: UniqueBuf::allocate(cap) }
+    }
+
+    /// Get the current capacity of the UninitBuf.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Write `value` into the element at `index`, without reading or dropping
+    /// the old value.
+    ///
+    /// Panics if `index` is not less than the capacity.
+    pub fn write(&mut self, index: usize, value: T) {
+        assert!(index < self.capacity(), "Write to index {} of UninitBuf with capacity {}",
+                index, self.capacity());
+
+        unsafe { ptr::write(self.buffer.offset(index as isize), value) }
+    }
+
+    /// Get a write-only view of the whole capacity.
+    pub fn as_spare_capacity<'a>(&'a mut self) -> SpareCapacity<'a, T> {
+        self.buffer.uninit_capacity_mut()
+    }
+
+    /// Get a pointer to the first element, for initializing the buffer
+    /// through foreign code.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        *self.buffer
+    }
+
+    /// Declare the first `len` elements initialized, turning this buffer
+    /// into a `SliceBuf` which allows reading them.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must have been initialized.
+    pub unsafe fn assume_init(self, len: usize) -> SliceBuf<T> {
+        SliceBuf::from_raw(self.buffer, len)
+    }
+
+    /// Get the underlying UniqueBuf.
+    pub fn into_inner(self) -> UniqueBuf<T> {
+        self.buffer
+    }
+}
+
+impl<T> From<UniqueBuf<T>> for UninitBuf<T> {
+    fn from(buffer: UniqueBuf<T>) -> UninitBuf<T> {
+        UninitBuf { buffer: buffer }
+    }
+}
+
+impl<T> SliceBuf<T> {
+    /// Forget the initialized elements, without dropping them, to reuse the
+    /// allocation as an `UninitBuf`.
+    ///
+    /// ```
+    /// # use membuf::SliceBuf;
+    ///
+    /// let buffer = SliceBuf::from_slice(&[1u8, 2, 3]);
+    /// let mut uninit = buffer.into_uninit();
+    /// uninit.write(0, 9);
+    ///
+    /// assert_eq!(&*unsafe { uninit.assume_init(1) }, &[9]);
+    /// ```
+    pub fn into_uninit(self) -> UninitBuf<T> {
+        UninitBuf { buffer: self.into_raw().0 }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use UninitBuf;
+
+    #[test]
+    fn test_spare_capacity_initializes() {
+        let mut buffer: UninitBuf<u16> = UninitBuf::allocate(3);
+        {
+            let mut spare = buffer.as_spare_capacity();
+            for i in 0..spare.len() { spare.write(i, 10 * i as u16); }
+        }
+
+        let address = buffer.as_mut_ptr() as *const u16;
+        let buffer = unsafe { buffer.assume_init(3) };
+        assert_eq!((&*buffer, buffer.as_ptr()), (&[0, 10, 20][..], address));
+    }
+
+    #[test]
+    #[should_panic = "Write to index 2 of UninitBuf with capacity 2"]
+    fn test_write_out_of_bounds() {
+        let mut buffer: UninitBuf<u8> = UninitBuf::allocate(2);
+        buffer.write(2, 0);
+    }
+}
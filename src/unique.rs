@@ -1,5 +1,14 @@
-use std::ops::Deref;
-use MemBuf;
+use std::cmp::Ordering;
+use std::ops::{Deref, Range};
+use std::{cmp, mem, ptr, slice};
+
+use libc;
+
+use core::nonzero::NonZero;
+
+use {AllocError, CapacityOverflow, MemBuf};
+use {alloc, canary, registry};
+use backend::{BackendHandle, DEFAULT_BACKEND};
 
 /// A safe wrapper around a heap allocated buffer of Ts, tracking capacity only.
 ///
@@ -50,6 +59,243 @@ impl<T> UniqueBuf<T> {
         UniqueBuf { inner: MemBuf::allocate(cap) }
     }
 
+    /// Create a new buffer with space for cap Ts, allocated from `backend`.
+    ///
+    /// See `MemBuf::allocate_in`.
+    pub fn allocate_in(cap: usize, backend: BackendHandle) -> UniqueBuf<T> {
+        UniqueBuf { inner: MemBuf::allocate_in(cap, backend) }
+    }
+
+    /// Create a new buffer with space for cap Ts, whose memory is counted
+    /// towards `tag` in `stats_by_tag`.
+    ///
+    /// See `MemBuf::allocate_tagged` for details.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u8> = UniqueBuf::allocate_tagged(1024, "http::body");
+    /// assert_eq!(buffer.capacity(), 1024);
+    /// ```
+    pub fn allocate_tagged(cap: usize, tag: &'static str) -> UniqueBuf<T> {
+        UniqueBuf { inner: MemBuf::allocate_tagged(cap, tag) }
+    }
+
+    /// Create a new buffer with space for cap Ts, allocated from `backend`,
+    /// whose memory is counted towards `tag`.
+    ///
+    /// See `MemBuf::allocate_tagged_in`.
+    pub fn allocate_tagged_in(cap: usize, backend: BackendHandle,
+                              tag: &'static str) -> UniqueBuf<T> {
+        UniqueBuf { inner: MemBuf::allocate_tagged_in(cap, backend, tag) }
+    }
+
+    /// Create a new buffer with space for cap Ts, returning an error instead
+    /// of panicking on capacity overflow.
+    ///
+    /// Running out of memory still aborts.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<usize> = UniqueBuf::checked_allocate(128).unwrap();
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn checked_allocate(cap: usize) -> Result<UniqueBuf<T>, CapacityOverflow> {
+        MemBuf::checked_allocate(cap).map(|inner| UniqueBuf { inner: inner })
+    }
+
+    /// Create a new buffer with space for cap Ts, returning an error instead
+    /// of panicking or aborting if the allocation fails.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<usize> = UniqueBuf::try_allocate(128).unwrap();
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn try_allocate(cap: usize) -> Result<UniqueBuf<T>, AllocError> {
+        MemBuf::try_allocate(cap).map(|inner| UniqueBuf { inner: inner })
+    }
+
+    /// Create a new buffer with space for cap Ts, allocated from `backend`,
+    /// returning an error instead of panicking or aborting if the allocation
+    /// fails.
+    pub fn try_allocate_in(cap: usize, backend: BackendHandle) -> Result<UniqueBuf<T>, AllocError> {
+        MemBuf::try_allocate_in(cap, backend).map(|inner| UniqueBuf { inner: inner })
+    }
+
+    /// Get the backend this buffer is allocated from.
+    pub fn backend(&self) -> BackendHandle {
+        self.inner.backend()
+    }
+
+    /// Create a new buffer with exactly enough space for `slice`, holding a
+    /// copy of its elements.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer = UniqueBuf::from_slice(&[1u8, 2, 3]);
+    /// assert_eq!(buffer.capacity(), 3);
+    /// assert_eq!(unsafe { buffer.as_slice(3) }, &[1, 2, 3]);
+    /// ```
+    pub fn from_slice(slice: &[T]) -> UniqueBuf<T> where T: Copy {
+        let buffer: UniqueBuf<T> = UniqueBuf::allocate(slice.len());
+
+        unsafe {
+            ptr::copy_nonoverlapping(slice.as_ptr(), *buffer, slice.len());
+            buffer.inner.assume_init_range(0..slice.len());
+        }
+        buffer
+    }
+
+    /// Take over the allocation of a `Vec`, returning the buffer and the
+    /// number of initialized elements at its start.
+    ///
+    /// The allocation is reused without copying, unless the `canaries`
+    /// feature is enabled or the backend has been replaced, in which case the
+    /// elements are moved into a fresh allocation.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let (buffer, len) = UniqueBuf::from_vec(vec![1, 2, 3]);
+    /// assert_eq!(len, 3);
+    /// assert_eq!(unsafe { buffer.as_slice(len) }, &[1, 2, 3]);
+    /// ```
+    pub fn from_vec(mut vec: Vec<T>) -> (UniqueBuf<T>, usize) {
+        let (len, cap) = (vec.len(), vec.capacity());
+
+        if mem::size_of::<T>() == 0 || cap == 0 || !alloc::is_std_heap() {
+            let buffer: UniqueBuf<T> = UniqueBuf::allocate(cap);
+
+            unsafe {
+                ptr::copy_nonoverlapping(vec.as_ptr(), *buffer, len);
+                vec.set_len(0);
+                buffer.inner.assume_init_range(0..len);
+            }
+            return (buffer, len)
+        }
+
+        unsafe {
+            let ptr = NonZero::new(vec.as_mut_ptr());
+            mem::forget(vec);
+
+            alloc::adopt(ptr, NonZero::new(cap));
+            let buffer = UniqueBuf { inner: MemBuf::from_raw(ptr, cap) };
+            buffer.inner.assume_init_range(0..len);
+            (buffer, len)
+        }
+    }
+
+    /// Convert this buffer into a `Vec` of its first `len` elements, whose
+    /// capacity is the capacity of this buffer.
+    ///
+    /// As with `from_vec`, the allocation is reused without copying unless
+    /// the `canaries` feature is enabled or the backend has been replaced.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<String> = UniqueBuf::allocate(4);
+    /// unsafe { buffer.write_at(0, "membuf".to_string()); }
+    ///
+    /// let vec = unsafe { buffer.into_vec(1) };
+    /// assert_eq!(vec, ["membuf"]);
+    /// assert_eq!(vec.capacity(), 4);
+    /// ```
+    pub unsafe fn into_vec(self, len: usize) -> Vec<T> {
+        debug_assert!(len <= self.capacity());
+        let cap = self.capacity();
+
+        let std_heap = alloc::is_std_heap() && self.backend() == DEFAULT_BACKEND;
+
+        if mem::size_of::<T>() == 0 || cap == 0 || !std_heap {
+            let mut vec = Vec::with_capacity(cap);
+            ptr::copy_nonoverlapping(*self, vec.as_mut_ptr(), len);
+            vec.set_len(len);
+            return vec
+        }
+
+        let ptr = *self.inner;
+        alloc::disown(NonZero::new(ptr), NonZero::new(cap));
+        mem::forget(self);
+        Vec::from_raw_parts(ptr, len, cap)
+    }
+
+    /// Convert this buffer into a boxed slice of its first `len` elements.
+    ///
+    /// The buffer is first shrunk to exactly `len` elements, after which the
+    /// allocation is reused as for `into_vec`.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(16);
+    /// unsafe { buffer.write_at(0, 42); }
+    ///
+    /// let boxed = unsafe { buffer.into_boxed_slice(1) };
+    /// assert_eq!(&*boxed, &[42]);
+    /// ```
+    pub unsafe fn into_boxed_slice(mut self, len: usize) -> Box<[T]> {
+        debug_assert!(len <= self.capacity());
+
+        self.reallocate(len);
+        self.into_vec(len).into_boxed_slice()
+    }
+
+    /// Hand this buffer over to code which will release it directly through
+    /// the backend, such as a C library calling `free` on memory allocated
+    /// with the `backend::Malloc` backend, returning its pointer.
+    ///
+    /// The buffer is no longer tracked by membuf, and is not released when
+    /// it goes out of scope. Panics with the `canaries` feature, since the
+    /// pointer is then not the start of the allocation. Buffers with no
+    /// allocation, of zero capacity or of zero sized types, cannot be
+    /// handed over and return a dangling pointer.
+    pub fn into_foreign(self) -> *mut T {
+        assert!(!canary::enabled(), "Buffers with canaries cannot be handed over");
+
+        let (ptr, cap) = (*self.inner, self.capacity());
+        mem::forget(self);
+
+        if mem::size_of::<T>() != 0 && cap != 0 {
+            unsafe { alloc::disown(NonZero::new(ptr), NonZero::new(cap)) }
+        }
+        ptr
+    }
+
+    /// Take over memory for `cap` Ts allocated by foreign code directly
+    /// through the backend, such as with `malloc` when the `backend::Malloc`
+    /// backend is installed.
+    ///
+    /// Panics with the `canaries` feature. None of the elements are
+    /// considered initialized.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must be an allocation of exactly `cap` Ts from the allocator of
+    /// the current backend, which is not owned by anything else.
+    pub unsafe fn from_foreign(ptr: *mut T, cap: usize) -> UniqueBuf<T> {
+        assert!(!canary::enabled(), "Buffers with canaries cannot be handed over");
+
+        if mem::size_of::<T>() != 0 && cap != 0 {
+            alloc::adopt(NonZero::new(ptr), NonZero::new(cap));
+        }
+        UniqueBuf { inner: MemBuf::from_raw(NonZero::new(ptr), cap) }
+    }
+
     /// Reallocate this buffer to fit a new number of Ts.
     ///
     /// Unlike `std::rt::heap::reallocate`, cap == 0 is allowed.
@@ -67,6 +313,69 @@ impl<T> UniqueBuf<T> {
         unsafe { self.inner.reallocate(cap) }
     }
 
+    /// Reallocate this buffer to fit a new number of Ts, preserving only the
+    /// first `len` elements.
+    ///
+    /// `reallocate` may copy the whole old capacity, including elements
+    /// beyond the ones in use. When growing a buffer of which less than half
+    /// is in use, this instead allocates a fresh buffer and copies only the
+    /// first `len` elements. `len` is clamped to the old and new capacities.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u64> = UniqueBuf::allocate(1024);
+    /// unsafe { buffer.write_at(0, 7); }
+    ///
+    /// buffer.reallocate_with_len(4096, 1);
+    /// assert_eq!(buffer.capacity(), 4096);
+    /// assert_eq!(unsafe { buffer.read_at(0) }, 7);
+    /// ```
+    pub fn reallocate_with_len(&mut self, cap: usize, len: usize) {
+        let len = cmp::min(len, cmp::min(cap, self.capacity()));
+
+        if cap > self.capacity() && len < self.capacity() / 2 {
+            let mut fresh = UniqueBuf { inner: self.inner.allocate_like(cap) };
+            unsafe { fresh.copy_from(self, len); }
+            self.swap(&mut fresh);
+        } else {
+            self.reallocate(cap);
+        }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts, returning an error
+    /// instead of panicking on capacity overflow.
+    ///
+    /// Running out of memory still aborts. On capacity overflow the buffer is
+    /// left untouched.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<usize> = UniqueBuf::allocate(128);
+    /// assert!(buffer.checked_reallocate(10_000_000_000_000_000_000).is_err());
+    /// assert_eq!(buffer.capacity(), 128);
+    /// ```
+    pub fn checked_reallocate(&mut self, cap: usize) -> Result<(), CapacityOverflow> {
+        unsafe { self.inner.checked_reallocate(cap) }
+    }
+
+    /// Reallocate this buffer to fit a new number of Ts, returning an error
+    /// instead of panicking or aborting if the allocation fails.
+    ///
+    /// On failure the buffer is left untouched.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<usize> = UniqueBuf::allocate(128);
+    /// buffer.try_reallocate(1024).unwrap();
+    /// assert_eq!(buffer.capacity(), 1024);
+    /// ```
+    pub fn try_reallocate(&mut self, cap: usize) -> Result<(), AllocError> {
+        unsafe { self.inner.try_reallocate(cap) }
+    }
+
     /// Get the current capacity of the UniqueBuf.
     ///
     /// ```
@@ -79,6 +388,596 @@ impl<T> UniqueBuf<T> {
         self.inner.capacity()
     }
 
+    /// Get a pointer to the element at `index`, panicking if `index` is not
+    /// less than the capacity of the buffer.
+    ///
+    /// See `MemBuf::ptr_at` for details.
+    pub fn ptr_at(&self, index: usize) -> *mut T {
+        self.inner.ptr_at(index)
+    }
+
+    /// Exchange the allocations of this buffer and `other`, without copying
+    /// any of their contents.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut front: UniqueBuf<u8> = UniqueBuf::allocate(16);
+    /// let mut back: UniqueBuf<u8> = UniqueBuf::allocate(32);
+    /// let (front_ptr, back_ptr) = (*front, *back);
+    ///
+    /// front.swap(&mut back);
+    /// assert_eq!((*front, front.capacity()), (back_ptr, 32));
+    /// assert_eq!((*back, back.capacity()), (front_ptr, 16));
+    /// ```
+    pub fn swap(&mut self, other: &mut UniqueBuf<T>) {
+        mem::swap(&mut self.inner, &mut other.inner)
+    }
+
+    /// Create an independent copy of this buffer, with the same capacity and
+    /// a bitwise copy of its whole contents.
+    ///
+    /// Unlike copying a `MemBuf`, which aliases the same allocation, the copy
+    /// has its own allocation. Since the copy is bitwise, unless `T` is `Copy`
+    /// the caller must ensure only one copy of each element is ever dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    /// buffer.fill_with_copies(9);
+    ///
+    /// let copy = buffer.duplicate();
+    /// buffer.fill_with_copies(0);
+    ///
+    /// assert!(*copy != *buffer);
+    /// assert_eq!(unsafe { copy.as_slice(4) }, &[9; 4]);
+    /// ```
+    pub fn duplicate(&self) -> UniqueBuf<T> {
+        let mut copy = UniqueBuf { inner: self.inner.allocate_like(self.capacity()) };
+        unsafe { copy.copy_from(self, self.capacity()); }
+        copy
+    }
+
+    /// Split this buffer into two independent buffers, the first with
+    /// capacity `mid` and the second holding the rest.
+    ///
+    /// Every buffer owns exactly one allocation, so this copies the elements
+    /// from `mid` onwards into a new allocation and then shrinks this one; the
+    /// contents of both halves are preserved bitwise. Panics if `mid` is
+    /// greater than the capacity.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u16> = UniqueBuf::allocate(6);
+    /// buffer.fill_range_bytes(0..2, 0);
+    /// buffer.fill_range_bytes(2..6, 1);
+    ///
+    /// let (head, tail) = buffer.split_at(2);
+    /// assert_eq!(unsafe { head.as_slice(2) }, &[0, 0]);
+    /// assert_eq!(unsafe { tail.as_slice(4) }, &[0x0101; 4]);
+    /// ```
+    pub fn split_at(mut self, mid: usize) -> (UniqueBuf<T>, UniqueBuf<T>) {
+        let cap = self.capacity();
+        if mid > cap {
+            panic!("Split at {} out of bounds for buffer with capacity {}", mid, cap)
+        }
+
+        let tail = UniqueBuf { inner: self.inner.allocate_like(cap - mid) };
+
+        unsafe {
+            ptr::copy_nonoverlapping(self.offset(mid as isize), *tail, cap - mid);
+
+            let size = mem::size_of::<T>();
+            if registry::is_initialized(*self as *mut u8, mid * size, cap * size) {
+                tail.inner.assume_init_range(0..cap - mid);
+            }
+        }
+
+        self.reallocate(mid);
+        (self, tail)
+    }
+
+    /// Write `value` into the element at `index`, without reading or dropping
+    /// the old value.
+    ///
+    /// See `MemBuf::write_at` for details.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity of the buffer, which is checked
+    /// in debug builds.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<usize> = UniqueBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     buffer.write_at(3, 12);
+    ///     assert_eq!(buffer.read_at(3), 12);
+    /// }
+    /// ```
+    pub unsafe fn write_at(&mut self, index: usize, value: T) {
+        self.inner.write_at(index, value)
+    }
+
+    /// Read the element at `index`, without moving it out of the buffer.
+    ///
+    /// See `MemBuf::read_at` for details.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be less than the capacity of the buffer, which is checked
+    /// in debug builds, and the element must be initialized.
+    pub unsafe fn read_at(&self, index: usize) -> T {
+        self.inner.read_at(index)
+    }
+
+    /// Write `value` into the element at `index` with a volatile store.
+    ///
+    /// See `MemBuf::write_volatile_at` for details.
+    pub unsafe fn write_volatile_at(&mut self, index: usize, value: T) {
+        self.inner.write_volatile_at(index, value)
+    }
+
+    /// Read the element at `index` with a volatile load.
+    ///
+    /// See `MemBuf::read_volatile_at` for details.
+    pub unsafe fn read_volatile_at(&self, index: usize) -> T {
+        self.inner.read_volatile_at(index)
+    }
+
+    /// View the first `len` elements of this buffer as a slice.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    /// use std::ptr;
+    ///
+    /// let buffer: UniqueBuf<u32> = UniqueBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     ptr::write(buffer.offset(0), 1);
+    ///     ptr::write(buffer.offset(1), 2);
+    ///     assert_eq!(buffer.as_slice(2), &[1, 2]);
+    /// }
+    /// ```
+    pub unsafe fn as_slice(&self, len: usize) -> &[T] {
+        debug_assert!(len <= self.capacity());
+        slice::from_raw_parts(**self, len)
+    }
+
+    /// View the first `len` elements of this buffer as a mutable slice.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and the first `len` elements must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    /// use std::ptr;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(8);
+    ///
+    /// unsafe {
+    ///     ptr::write(buffer.offset(0), 1);
+    ///     buffer.as_mut_slice(1)[0] = 5;
+    ///     assert_eq!(buffer.as_slice(1), &[5]);
+    /// }
+    /// ```
+    pub unsafe fn as_mut_slice(&mut self, len: usize) -> &mut [T] {
+        debug_assert!(len <= self.capacity());
+        slice::from_raw_parts_mut(**self, len)
+    }
+
+    /// View the first `len` elements of this buffer as raw bytes.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity, which is checked in debug builds,
+    /// and every byte of the first `len` elements must be initialized, which
+    /// excludes types with padding.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u16> = UniqueBuf::allocate(4);
+    /// buffer.fill_with_copies(0x0102);
+    ///
+    /// unsafe {
+    ///     assert_eq!(buffer.as_bytes(2).len(), 4);
+    ///     assert!(buffer.as_bytes(2).iter().all(|&b| b == 1 || b == 2));
+    /// }
+    /// ```
+    pub unsafe fn as_bytes(&self, len: usize) -> &[u8] {
+        debug_assert!(len <= self.capacity());
+        slice::from_raw_parts(**self as *const u8, len * mem::size_of::<T>())
+    }
+
+    /// View the first `len` elements of this buffer as mutable raw bytes.
+    ///
+    /// ## Safety
+    ///
+    /// As for `as_bytes`, and additionally any bytes written must leave the
+    /// elements valid Ts.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(2);
+    /// buffer.fill_with_copies(0);
+    ///
+    /// unsafe {
+    ///     for byte in buffer.as_bytes_mut(2) { *byte = 0xff; }
+    ///     assert_eq!(buffer.as_slice(2), &[!0, !0]);
+    /// }
+    /// ```
+    pub unsafe fn as_bytes_mut(&mut self, len: usize) -> &mut [u8] {
+        debug_assert!(len <= self.capacity());
+        slice::from_raw_parts_mut(**self as *mut u8, len * mem::size_of::<T>())
+    }
+
+    /// Lexicographically compare the first `len` elements of this buffer
+    /// with the first `len` elements of `other`.
+    ///
+    /// ## Safety
+    ///
+    /// `len` must be at most the capacity of both buffers, which is checked in
+    /// debug builds, and the first `len` elements of both must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    /// use std::cmp::Ordering;
+    ///
+    /// let mut a: UniqueBuf<u8> = UniqueBuf::allocate(4);
+    /// let mut b: UniqueBuf<u8> = UniqueBuf::allocate(4);
+    /// a.fill_bytes(1);
+    /// b.fill_bytes(1);
+    /// b.fill_range_bytes(3..4, 2);
+    ///
+    /// unsafe {
+    ///     assert_eq!(a.compare(&b, 3), Ordering::Equal);
+    ///     assert_eq!(a.compare(&b, 4), Ordering::Less);
+    /// }
+    /// ```
+    pub unsafe fn compare(&self, other: &UniqueBuf<T>, len: usize) -> Ordering where T: Ord {
+        self.as_slice(len).cmp(other.as_slice(len))
+    }
+
+    /// Are the elements in `range` of this buffer bitwise equal to the same
+    /// elements of `other`?
+    ///
+    /// This is a single `memcmp`, so it is much faster than comparing element
+    /// by element, but it is only correct for plain-old-data types.
+    ///
+    /// ## Safety
+    ///
+    /// `range` must be within the capacity of both buffers, which is checked
+    /// in debug builds, and every byte of the elements in it must be
+    /// initialized in both, which excludes types with padding.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut a: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    /// let mut b: UniqueBuf<u32> = UniqueBuf::allocate(8);
+    /// a.fill_with_copies(7);
+    /// b.fill_with_copies(7);
+    /// b.fill_range_bytes(3..4, 0);
+    ///
+    /// unsafe {
+    ///     assert!(a.eq_range(&b, 0..3));
+    ///     assert!(!a.eq_range(&b, 2..4));
+    /// }
+    /// ```
+    pub unsafe fn eq_range(&self, other: &UniqueBuf<T>, range: Range<usize>) -> bool {
+        debug_assert!(range.start <= range.end);
+        debug_assert!(range.end <= self.capacity() && range.end <= other.capacity());
+
+        let size = mem::size_of::<T>();
+        let start = range.start as isize;
+        libc::memcmp(self.offset(start) as *const libc::c_void,
+                     other.offset(start) as *const libc::c_void,
+                     (range.end - range.start) * size) == 0
+    }
+
+    /// Set every byte of the buffer's capacity to `byte`.
+    ///
+    /// Any previous contents are overwritten without being dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    /// buffer.fill_bytes(0xff);
+    /// assert_eq!(unsafe { buffer.as_slice(4) }, &[!0; 4]);
+    /// ```
+    pub fn fill_bytes(&mut self, byte: u8) {
+        let cap = self.capacity();
+        self.fill_range_bytes(0..cap, byte)
+    }
+
+    /// Set every byte of the elements in `range` to `byte`.
+    ///
+    /// Any previous contents are overwritten without being dropped. Panics if
+    /// `range` is not within the capacity of the buffer.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u16> = UniqueBuf::allocate(4);
+    /// buffer.fill_range_bytes(0..2, 0);
+    /// buffer.fill_range_bytes(2..4, 1);
+    /// assert_eq!(unsafe { buffer.as_slice(4) }, &[0, 0, 0x0101, 0x0101]);
+    /// ```
+    pub fn fill_range_bytes(&mut self, range: Range<usize>, byte: u8) {
+        self.check_range(&range);
+
+        unsafe {
+            ptr::write_bytes(self.offset(range.start as isize), byte,
+                             range.end - range.start);
+            self.inner.assume_init_range(range);
+        }
+    }
+
+    /// Write a copy of `value` into every element of the buffer's capacity.
+    ///
+    /// Any previous contents are overwritten without being dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<(u8, bool)> = UniqueBuf::allocate(3);
+    /// buffer.fill_with_copies((7, true));
+    /// assert_eq!(unsafe { buffer.as_slice(3) }, &[(7, true); 3]);
+    /// ```
+    pub fn fill_with_copies(&mut self, value: T) where T: Copy {
+        if mem::size_of::<T>() == 0 { return }
+
+        unsafe {
+            for index in 0..self.capacity() {
+                ptr::write(self.offset(index as isize), value);
+            }
+            self.inner.assume_init_range(0..self.capacity());
+        }
+    }
+
+    /// Copy the first `count` elements of `src` to the start of this buffer.
+    ///
+    /// This is a `memcpy`: the elements are copied bitwise, so unless `T` is
+    /// `Copy` the caller must ensure only one of the copies is ever dropped.
+    ///
+    /// ## Safety
+    ///
+    /// `count` must be at most the capacity of both buffers, which is checked
+    /// in debug builds. Any previous contents of this buffer are overwritten
+    /// without being dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut src: UniqueBuf<u32> = UniqueBuf::allocate(4);
+    /// let mut dst: UniqueBuf<u32> = UniqueBuf::allocate(8);
+    /// src.fill_with_copies(3);
+    ///
+    /// unsafe {
+    ///     dst.copy_from(&src, 4);
+    ///     assert_eq!(dst.as_slice(4), &[3, 3, 3, 3]);
+    /// }
+    /// ```
+    pub unsafe fn copy_from(&mut self, src: &UniqueBuf<T>, count: usize) {
+        debug_assert!(count <= self.capacity() && count <= src.capacity());
+
+        ptr::copy_nonoverlapping(**src, **self, count);
+        if registry::is_initialized(**src as *mut u8, 0, count * mem::size_of::<T>()) {
+            self.inner.assume_init_range(0..count);
+        }
+    }
+
+    /// Move the elements in `src` to start at `dst` within this buffer.
+    ///
+    /// This is a `memmove`, so the ranges may overlap. Elements in the source
+    /// range which are not also in the destination range are left as bitwise
+    /// copies, and should be treated as uninitialized.
+    ///
+    /// ## Safety
+    ///
+    /// Both ranges must be within the capacity of the buffer, which is checked
+    /// in debug builds. Elements in the destination range are overwritten
+    /// without being dropped.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(4);
+    ///
+    /// unsafe {
+    ///     buffer.write_at(0, 1);
+    ///     buffer.write_at(1, 2);
+    ///     buffer.write_at(2, 3);
+    ///     buffer.move_within(0..3, 1);
+    ///     assert_eq!(&buffer.as_slice(4)[1..], &[1, 2, 3]);
+    /// }
+    /// ```
+    pub unsafe fn move_within(&mut self, src: Range<usize>, dst: usize) {
+        self.copy_elements(src, dst)
+    }
+
+    /// Do the element ranges `a` and `b` overlap?
+    ///
+    /// Empty ranges overlap nothing.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u8> = UniqueBuf::allocate(8);
+    /// assert!(buffer.ranges_overlap(0..4, 3..5));
+    /// assert!(!buffer.ranges_overlap(0..4, 4..8));
+    /// assert!(!buffer.ranges_overlap(2..2, 0..8));
+    /// ```
+    pub fn ranges_overlap(&self, a: Range<usize>, b: Range<usize>) -> bool {
+        a.start < a.end && b.start < b.end && a.start < b.end && b.start < a.end
+    }
+
+    /// Copy the elements in `src` to start at `dst` within this buffer, with
+    /// a `memcpy` if the ranges are disjoint and a `memmove` if they overlap.
+    ///
+    /// Elements in the source range which are not also in the destination
+    /// range are left as bitwise copies.
+    ///
+    /// ## Safety
+    ///
+    /// As for `move_within`.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(6);
+    /// buffer.fill_range_bytes(0..3, 1);
+    /// buffer.fill_range_bytes(3..6, 2);
+    ///
+    /// unsafe {
+    ///     buffer.copy_elements(2..4, 3);
+    ///     assert_eq!(buffer.as_slice(6), &[1, 1, 1, 1, 2, 2]);
+    ///
+    ///     buffer.copy_elements(4..6, 0);
+    ///     assert_eq!(buffer.as_slice(6), &[2, 2, 1, 1, 2, 2]);
+    /// }
+    /// ```
+    pub unsafe fn copy_elements(&mut self, src: Range<usize>, dst: usize) {
+        debug_assert!(src.start <= src.end && src.end <= self.capacity());
+        let count = src.end - src.start;
+        debug_assert!(dst <= self.capacity() && count <= self.capacity() - dst);
+
+        let (from, to) = (self.offset(src.start as isize), self.offset(dst as isize));
+        if self.ranges_overlap(src.clone(), dst..dst + count) {
+            ptr::copy(from, to, count);
+        } else {
+            ptr::copy_nonoverlapping(from, to, count);
+        }
+
+        let size = mem::size_of::<T>();
+        if registry::is_initialized(**self as *mut u8, src.start * size, src.end * size) {
+            self.inner.assume_init_range(dst..dst + count);
+        }
+    }
+
+    /// Open a gap of `gap_len` uninitialized elements at `index`, by moving
+    /// the elements in `index..initialized_len` up by `gap_len`.
+    ///
+    /// This is the shifting step of inserting into the middle of a vector;
+    /// afterwards the initialized elements are `0..index` and
+    /// `index + gap_len..initialized_len + gap_len`.
+    ///
+    /// ## Safety
+    ///
+    /// `index` must be at most `initialized_len`, and `initialized_len +
+    /// gap_len` at most the capacity, which is checked in debug builds.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(5);
+    ///
+    /// unsafe {
+    ///     buffer.write_at(0, 1);
+    ///     buffer.write_at(1, 4);
+    ///     buffer.write_at(2, 5);
+    ///
+    ///     buffer.open_gap(1, 2, 3);
+    ///     buffer.write_at(1, 2);
+    ///     buffer.write_at(2, 3);
+    ///     assert_eq!(buffer.as_slice(5), &[1, 2, 3, 4, 5]);
+    /// }
+    /// ```
+    pub unsafe fn open_gap(&mut self, index: usize, gap_len: usize, initialized_len: usize) {
+        debug_assert!(index <= initialized_len);
+        debug_assert!(gap_len <= self.capacity() &&
+                      initialized_len <= self.capacity() - gap_len);
+
+        self.move_within(index..initialized_len, index + gap_len);
+    }
+
+    /// Close a gap of `gap_len` elements at `index`, by moving the elements in
+    /// `index + gap_len..initialized_len` down by `gap_len`.
+    ///
+    /// This is the shifting step of removing from the middle of a vector; the
+    /// elements in the gap are overwritten without being dropped, so they
+    /// should be read out or dropped beforehand. Afterwards the initialized
+    /// elements are `0..initialized_len - gap_len`.
+    ///
+    /// ## Safety
+    ///
+    /// `index + gap_len` must be at most `initialized_len`, which must be at
+    /// most the capacity, which is checked in debug builds.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let mut buffer: UniqueBuf<u8> = UniqueBuf::allocate(4);
+    ///
+    /// unsafe {
+    ///     for i in 0..4 { buffer.write_at(i, i as u8 + 1); }
+    ///     assert_eq!(buffer.read_at(1), 2);
+    ///
+    ///     buffer.close_gap(1, 1, 4);
+    ///     assert_eq!(buffer.as_slice(3), &[1, 3, 4]);
+    /// }
+    /// ```
+    pub unsafe fn close_gap(&mut self, index: usize, gap_len: usize, initialized_len: usize) {
+        debug_assert!(initialized_len <= self.capacity());
+        debug_assert!(index <= initialized_len && gap_len <= initialized_len - index);
+
+        self.move_within(index + gap_len..initialized_len, index);
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        if range.start > range.end || range.end > self.capacity() {
+            panic!("Range {}..{} out of bounds for buffer with capacity {}",
+                   range.start, range.end, self.capacity())
+        }
+    }
+
+    /// Tell AddressSanitizer and Valgrind that only the first `len` elements
+    /// of this buffer hold live data.
+    ///
+    /// See `MemBuf::set_unpoisoned_len` for details. Panics if `len` is
+    /// greater than the capacity.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u32> = UniqueBuf::allocate(16);
+    /// buffer.set_unpoisoned_len(4);
+    /// ```
+    pub fn set_unpoisoned_len(&self, len: usize) {
+        assert!(len <= self.capacity(), "Length {} exceeds capacity {}", len, self.capacity());
+        unsafe { self.inner.set_unpoisoned_len(len) }
+    }
+
+    /// Check whether the canaries around this buffer are intact.
+    ///
+    /// Only available with the `canaries` feature, see
+    /// `MemBuf::verify_canaries` for details.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer: UniqueBuf<u32> = UniqueBuf::allocate(16);
+    /// assert!(buffer.verify_canaries());
+    /// ```
+    #[cfg(feature = "canaries")]
+    pub fn verify_canaries(&self) -> bool {
+        unsafe { self.inner.verify_canaries() }
+    }
+
     /// Create a UniqueBuf from an existing MemBuf.
     ///
     /// ```
@@ -92,6 +991,22 @@ impl<T> UniqueBuf<T> {
     }
 }
 
+/// Take over the allocation of a boxed slice, as for `from_vec`; every
+/// element of the buffer is initialized.
+///
+/// ```
+/// # use membuf::UniqueBuf;
+///
+/// let boxed: Box<[u16]> = vec![1, 2].into_boxed_slice();
+/// let buffer = UniqueBuf::from(boxed);
+/// assert_eq!(unsafe { buffer.as_slice(2) }, &[1, 2]);
+/// ```
+impl<T> From<Box<[T]>> for UniqueBuf<T> {
+    fn from(slice: Box<[T]>) -> UniqueBuf<T> {
+        UniqueBuf::from_vec(slice.into_vec()).0
+    }
+}
+
 impl<T> Drop for UniqueBuf<T> {
     fn drop(&mut self) {
         unsafe { self.inner.deallocate() }
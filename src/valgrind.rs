@@ -0,0 +1,58 @@
+//! Valgrind client requests.
+//!
+//! With the `valgrind` feature enabled, buffers are registered with Valgrind
+//! as individual heap blocks, so memcheck can report leaks and invalid
+//! accesses per buffer, and spare capacity beyond `set_unpoisoned_len` is
+//! marked as undefined. Client requests are cheap no-ops when the program is
+//! not running under Valgrind.
+//!
+//! Without the feature, or on targets other than x86-64, every function in
+//! here is a no-op.
+
+const MALLOCLIKE_BLOCK: usize = 0x1301;
+const FREELIKE_BLOCK: usize = 0x1302;
+const MAKE_MEM_UNDEFINED: usize = 0x4d43_0001;
+
+/// Is Valgrind support enabled?
+pub fn enabled() -> bool {
+    cfg!(all(feature = "valgrind", target_arch = "x86_64"))
+}
+
+/// Register `size` bytes at `ptr` as a freshly allocated, undefined block.
+pub unsafe fn malloclike(ptr: *mut u8, size: usize) {
+    client_request(0, MALLOCLIKE_BLOCK, ptr as usize, size, 0, 0, 0);
+}
+
+/// Unregister a block previously registered with `malloclike`.
+pub unsafe fn freelike(ptr: *mut u8) {
+    client_request(0, FREELIKE_BLOCK, ptr as usize, 0, 0, 0, 0);
+}
+
+/// Mark `size` bytes at `ptr` as addressable but undefined.
+pub unsafe fn make_undefined(ptr: *mut u8, size: usize) {
+    client_request(0, MAKE_MEM_UNDEFINED, ptr as usize, size, 0, 0, 0);
+}
+
+#[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+unsafe fn client_request(default: usize, request: usize,
+                         a1: usize, a2: usize, a3: usize, a4: usize, a5: usize) -> usize {
+    let args = [request, a1, a2, a3, a4, a5];
+    let result;
+
+    // The magic instruction sequence Valgrind recognizes as a client request,
+    // it has no effect when running natively.
+    asm!("rolq $$3, %rdi; rolq $$13, %rdi; rolq $$61, %rdi; rolq $$51, %rdi; xchgq %rbx, %rbx"
+         : "={rdx}"(result)
+         : "{rax}"(args.as_ptr()), "{rdx}"(default)
+         : "cc", "memory"
+         : "volatile");
+
+    result
+}
+
+#[cfg(not(all(feature = "valgrind", target_arch = "x86_64")))]
+#[inline(always)]
+unsafe fn client_request(default: usize, _: usize,
+                         _: usize, _: usize, _: usize, _: usize, _: usize) -> usize {
+    default
+}
@@ -0,0 +1,147 @@
+use std::ops::{Deref, Range};
+use std::slice;
+
+use {FrozenBuf, UniqueBuf};
+
+/// A window into part of a `FrozenBuf`, which keeps the whole buffer alive.
+///
+/// Views are cheap to clone and can be narrowed further, so a parser can
+/// hand out many windows into one allocation without copying or borrowing.
+///
+/// ```
+/// # use membuf::SliceBuf;
+///
+/// let message = SliceBuf::from_slice(b"GET /index.html").freeze();
+/// let method = message.slice(0..3);
+/// let path = message.slice(4..15);
+/// drop(message);
+///
+/// assert_eq!(&*method, b"GET");
+/// assert_eq!(&*path.slice(1..6), b"index");
+/// ```
+#[derive(Debug)]
+pub struct BufView<T> {
+    parent: FrozenBuf<T>,
+    start: usize,
+    len: usize
+}
+
+impl<T> FrozenBuf<T> {
+    /// Get a view of the elements in `range`.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> BufView<T> {
+        check_range(&range, self.len());
+        BufView { parent: self.clone(), start: range.start, len: range.end - range.start }
+    }
+}
+
+impl<T> BufView<T> {
+    /// Get the number of elements in the view.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get a pointer to the first element of the view.
+    pub fn as_ptr(&self) -> *const T {
+        unsafe { self.parent.as_ptr().offset(self.start as isize) }
+    }
+
+    /// View the elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// Get a view of the elements in `range`, relative to this view.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> BufView<T> {
+        check_range(&range, self.len);
+        BufView {
+            parent: self.parent.clone(),
+            start: self.start + range.start,
+            len: range.end - range.start
+        }
+    }
+
+    /// Get the buffer this is a view into.
+    pub fn parent(&self) -> &FrozenBuf<T> {
+        &self.parent
+    }
+}
+
+impl<T> Clone for BufView<T> {
+    fn clone(&self) -> BufView<T> {
+        BufView { parent: self.parent.clone(), start: self.start, len: self.len }
+    }
+}
+
+impl<T> Deref for BufView<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T> UniqueBuf<T> {
+    /// View the elements in `range` as a slice, borrowing the buffer.
+    ///
+    /// This is the borrowed counterpart of `FrozenBuf::slice`. Panics if the
+    /// range is beyond the capacity.
+    ///
+    /// ## Safety
+    ///
+    /// The elements in `range` must be initialized.
+    ///
+    /// ```
+    /// # use membuf::UniqueBuf;
+    ///
+    /// let buffer = UniqueBuf::from_slice(&[1, 2, 3, 4]);
+    /// assert_eq!(unsafe { buffer.view(1..3) }, &[2, 3]);
+    /// ```
+    pub unsafe fn view(&self, range: Range<usize>) -> &[T] {
+        check_range(&range, self.capacity());
+        slice::from_raw_parts(self.offset(range.start as isize), range.end - range.start)
+    }
+}
+
+fn check_range(range: &Range<usize>, len: usize) {
+    if range.start > range.end || range.end > len {
+        panic!("Range {}..{} out of bounds for buffer with length {}",
+               range.start, range.end, len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use SliceBuf;
+
+    #[test]
+    fn test_views_keep_parent_alive() {
+        let counted = Arc::new(());
+        let mut buffer: SliceBuf<Arc<()>> = SliceBuf::allocate(3);
+        for i in 0..3 { buffer.write(i, counted.clone()); }
+        unsafe { buffer.set_len(3); }
+
+        let frozen = buffer.freeze();
+        let (first, rest) = (frozen.slice(0..1), frozen.slice(1..3));
+        drop(frozen);
+
+        let last = rest.slice(1..2);
+        assert_eq!(last.as_ptr(), unsafe { first.as_ptr().offset(2) });
+        assert_eq!(rest.parent().len(), 3);
+
+        drop((first, rest));
+        assert_eq!(Arc::strong_count(&counted), 4);
+        drop(last);
+        assert_eq!(Arc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    #[should_panic = "Range 2..5 out of bounds for buffer with length 4"]
+    fn test_view_out_of_bounds() {
+        let frozen = SliceBuf::from_slice(&[0u8; 8]).freeze();
+        frozen.slice(4..8).slice(2..5);
+    }
+}
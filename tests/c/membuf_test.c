@@ -0,0 +1,35 @@
+/*
+ * Exercises the C interface through include/membuf.h. Built by build.rs and
+ * run by the tests of the `ffi` module, with the `c-header-test` feature.
+ */
+
+#include <stddef.h>
+#include <string.h>
+
+#include "membuf.h"
+
+/* Returns 0 if every check passes, or the number of the first that fails. */
+int membuf_c_test(void) {
+    RawMemBuf buf = membuf_alloc(16);
+    RawMemBuf failed;
+
+    if (buf.ptr == NULL) return 1;
+    memset(buf.ptr, 0xaa, 16);
+
+    if (membuf_realloc(&buf, 4096) != 0) return 2;
+    if (membuf_capacity(&buf) != 4096) return 3;
+    if (buf.ptr[15] != 0xaa) return 4;
+
+    if (membuf_realloc(&buf, (size_t)-1) != -1) return 5;
+    if (membuf_capacity(&buf) != 4096) return 6;
+    membuf_free(buf);
+
+    failed = membuf_alloc((size_t)-1);
+    if (failed.ptr != NULL) return 7;
+    if (membuf_realloc(&failed, 16) != -2) return 8;
+    if (membuf_realloc(NULL, 16) != -2) return 9;
+    if (membuf_capacity(NULL) != 0) return 10;
+    membuf_free(failed);
+
+    return 0;
+}